@@ -0,0 +1,887 @@
+//! Audio helpers shared by the pipeline
+//!
+//! WAV parsing and encoding so audio from the frontend can be inspected and
+//! converted to the 16-bit PCM the services expect. MP3, FLAC and Ogg input is
+//! decoded with symphonia and re-wrapped as WAV.
+
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+/// Sample rate Whisper models are trained on
+pub const ASR_SAMPLE_RATE: u32 = 16_000;
+
+/// Format details read from a WAV header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WavInfo {
+    /// 1 = integer PCM, 3 = IEEE float
+    pub audio_format: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+}
+
+/// Decoded WAV file with samples converted to interleaved i16
+#[derive(Debug, Clone)]
+pub struct DecodedWav {
+    pub info: WavInfo,
+    pub samples: Vec<i16>,
+}
+
+impl DecodedWav {
+    /// Duration in seconds
+    pub fn duration(&self) -> f64 {
+        let frames = self.samples.len() as f64 / self.info.channels.max(1) as f64;
+        frames / self.info.sample_rate.max(1) as f64
+    }
+}
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Parse a RIFF/WAVE file and convert its samples to i16
+pub fn parse_wav(data: &[u8]) -> Result<DecodedWav, String> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err("Not a WAV file (missing RIFF/WAVE header)".to_string());
+    }
+
+    let mut info: Option<WavInfo> = None;
+    let mut pcm: Option<&[u8]> = None;
+    let mut offset = 12;
+
+    // Walk the chunk list looking for "fmt " and "data"
+    while offset + 8 <= data.len() {
+        let id = &data[offset..offset + 4];
+        let size = read_u32(data, offset + 4).unwrap_or(0) as usize;
+        let body_start = offset + 8;
+        let body_end = body_start.saturating_add(size).min(data.len());
+        let body = &data[body_start..body_end];
+
+        match id {
+            b"fmt " => {
+                if body.len() < 16 {
+                    return Err("WAV fmt chunk is too short".to_string());
+                }
+                let mut audio_format = read_u16(body, 0).unwrap_or(0);
+                if audio_format == WAVE_FORMAT_EXTENSIBLE {
+                    // Actual format is the first two bytes of the SubFormat GUID
+                    audio_format = read_u16(body, 24)
+                        .ok_or("WAV extensible fmt chunk is too short")?;
+                }
+                info = Some(WavInfo {
+                    audio_format,
+                    channels: read_u16(body, 2).unwrap_or(0),
+                    sample_rate: read_u32(body, 4).unwrap_or(0),
+                    bits_per_sample: read_u16(body, 14).unwrap_or(0),
+                });
+            }
+            b"data" => pcm = Some(body),
+            _ => {}
+        }
+
+        // Chunks are padded to an even size
+        offset = body_start.saturating_add(size).saturating_add(size & 1);
+    }
+
+    let info = info.ok_or("WAV file has no fmt chunk")?;
+    let pcm = pcm.ok_or("WAV file has no data chunk")?;
+
+    if info.channels == 0 || info.sample_rate == 0 {
+        return Err(format!(
+            "Invalid WAV format: {} channels at {} Hz",
+            info.channels, info.sample_rate
+        ));
+    }
+
+    let samples = match (info.audio_format, info.bits_per_sample) {
+        (WAVE_FORMAT_PCM, 8) => pcm.iter().map(|&b| ((b as i16) - 128) << 8).collect(),
+        (WAVE_FORMAT_PCM, 16) => pcm
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect(),
+        (WAVE_FORMAT_PCM, 24) => pcm
+            .chunks_exact(3)
+            .map(|b| i16::from_le_bytes([b[1], b[2]]))
+            .collect(),
+        (WAVE_FORMAT_PCM, 32) => pcm
+            .chunks_exact(4)
+            .map(|b| (i32::from_le_bytes([b[0], b[1], b[2], b[3]]) >> 16) as i16)
+            .collect(),
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => pcm
+            .chunks_exact(4)
+            .map(|b| f32_to_i16(f32::from_le_bytes([b[0], b[1], b[2], b[3]])))
+            .collect(),
+        (format, bits) => {
+            return Err(format!(
+                "Unsupported WAV encoding: format {} with {} bits per sample",
+                format, bits
+            ))
+        }
+    };
+
+    Ok(DecodedWav { info, samples })
+}
+
+/// Convert a float sample in [-1.0, 1.0] to i16, clamping out-of-range values
+pub fn f32_to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+/// Encode interleaved i16 samples as a 16-bit PCM WAV file
+pub fn encode_wav(samples: &[i16], sample_rate: u32, channels: u16) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(44 + samples.len() * 2);
+
+    let block_align = channels * 2;
+    let data_size = (samples.len() * 2) as u32;
+    let file_size = data_size + 36;
+
+    // RIFF header
+    buffer.extend_from_slice(b"RIFF");
+    buffer.extend_from_slice(&file_size.to_le_bytes());
+    buffer.extend_from_slice(b"WAVE");
+
+    // fmt subchunk
+    buffer.extend_from_slice(b"fmt ");
+    buffer.extend_from_slice(&16u32.to_le_bytes()); // Subchunk1Size for PCM
+    buffer.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes()); // AudioFormat
+    buffer.extend_from_slice(&channels.to_le_bytes()); // NumChannels
+    buffer.extend_from_slice(&sample_rate.to_le_bytes()); // SampleRate
+    buffer.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes()); // ByteRate
+    buffer.extend_from_slice(&block_align.to_le_bytes()); // BlockAlign
+    buffer.extend_from_slice(&16u16.to_le_bytes()); // BitsPerSample
+
+    // data subchunk
+    buffer.extend_from_slice(b"data");
+    buffer.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        buffer.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    buffer
+}
+
+/// Join pieces of audio into one WAV file
+///
+/// Each piece is either a complete WAV file or raw 16-bit PCM at the given
+/// sample rate with `raw_channels` channels. A single WAV piece is returned
+/// unchanged; otherwise the pieces must share a sample rate and channel count.
+pub fn join_wav_pieces<'a, I>(pieces: I, raw_channels: u16) -> Result<Vec<u8>, String>
+where
+    I: IntoIterator<Item = (&'a [u8], u32)>,
+{
+    let pieces: Vec<(&[u8], u32)> = pieces.into_iter().collect();
+    if let [(data, _)] = pieces.as_slice() {
+        if detect_container(data) == "wav" {
+            return Ok(data.to_vec());
+        }
+    }
+
+    let mut format: Option<(u32, u16)> = None;
+    let mut samples = Vec::new();
+    for (data, sample_rate) in pieces {
+        let (piece, piece_format) = if detect_container(data) == "wav" {
+            let wav = parse_wav(data)?;
+            (wav.samples, (wav.info.sample_rate, wav.info.channels))
+        } else {
+            let pcm = data.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect();
+            (pcm, (sample_rate, raw_channels.max(1)))
+        };
+        match format {
+            None => format = Some(piece_format),
+            Some(expected) if expected != piece_format => {
+                return Err(format!(
+                    "Audio pieces differ in format: {} Hz x{} and {} Hz x{}",
+                    expected.0, expected.1, piece_format.0, piece_format.1
+                ));
+            }
+            Some(_) => {}
+        }
+        samples.extend(piece);
+    }
+
+    let (sample_rate, channels) = format.ok_or("No audio to join")?;
+    Ok(encode_wav(&samples, sample_rate, channels))
+}
+
+/// Fraction of samples at (or within one step of) full scale
+pub fn clipping_ratio(samples: &[i16]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let clipped = samples
+        .iter()
+        .filter(|&&s| s >= i16::MAX - 1 || s <= i16::MIN + 1)
+        .count();
+    clipped as f64 / samples.len() as f64
+}
+
+/// Peak absolute amplitude normalized to [0.0, 1.0]
+pub fn peak_level(samples: &[i16]) -> f32 {
+    samples
+        .iter()
+        .map(|&s| (s as i32).unsigned_abs())
+        .max()
+        .map_or(0.0, |peak| peak as f32 / 32768.0)
+}
+
+/// Root-mean-square level of float samples in [-1.0, 1.0]
+pub fn rms_level(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f32 = samples.iter().map(|s| s * s).sum();
+    (sum / samples.len() as f32).sqrt()
+}
+
+/// Exponential moving average with separate attack/decay coefficients
+///
+/// A higher attack makes the level rise quickly on speech onset while a lower
+/// decay lets it fall off gradually, which keeps VU meters from flickering.
+#[derive(Debug, Clone, Copy)]
+pub struct LevelSmoother {
+    /// Coefficient applied when the level rises (0.0-1.0, 1.0 = no smoothing)
+    pub attack: f32,
+    /// Coefficient applied when the level falls (0.0-1.0, 1.0 = no smoothing)
+    pub decay: f32,
+    value: f32,
+}
+
+impl LevelSmoother {
+    pub fn new(attack: f32, decay: f32) -> Self {
+        Self { attack, decay, value: 0.0 }
+    }
+
+    /// Feed a raw level and return the smoothed value
+    pub fn update(&mut self, raw: f32) -> f32 {
+        let coefficient = if raw > self.value { self.attack } else { self.decay };
+        self.value += coefficient * (raw - self.value);
+        self.value
+    }
+}
+
+impl Default for LevelSmoother {
+    fn default() -> Self {
+        Self::new(0.5, 0.1)
+    }
+}
+
+/// Buffers push-to-talk audio as it arrives so it can be transcribed before
+/// the button is released
+#[derive(Debug, Clone)]
+pub struct AudioAccumulator {
+    samples: Vec<i16>,
+    sample_rate: u32,
+    /// Seconds of new audio between interim transcriptions (None = on demand only)
+    interim_secs: Option<f32>,
+    /// Sample count at the last interim flush
+    flushed: usize,
+}
+
+impl AudioAccumulator {
+    pub fn new(sample_rate: u32, interim_secs: Option<f32>) -> Self {
+        Self {
+            samples: Vec::new(),
+            sample_rate,
+            interim_secs: interim_secs.filter(|&secs| secs > 0.0),
+            flushed: 0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Length of the buffered audio, in seconds
+    pub fn duration_secs(&self) -> f64 {
+        self.samples.len() as f64 / self.sample_rate.max(1) as f64
+    }
+
+    /// Append a chunk of mono samples
+    ///
+    /// Returns whether an interim transcription is due, in which case the
+    /// caller should `flush` and transcribe the returned audio. Not flushing
+    /// leaves the audio pending, so it is included in the next interim.
+    pub fn push(&mut self, chunk: &[i16]) -> bool {
+        self.samples.extend_from_slice(chunk);
+        self.interim_secs.is_some_and(|secs| {
+            let pending = (self.samples.len() - self.flushed) as f64 / self.sample_rate.max(1) as f64;
+            pending >= secs as f64
+        })
+    }
+
+    /// Audio added since the previous flush, for an interim transcription
+    pub fn flush(&mut self) -> Vec<i16> {
+        let pending = self.samples[self.flushed..].to_vec();
+        self.flushed = self.samples.len();
+        pending
+    }
+
+    /// Take the complete recording, for the final transcription
+    pub fn finish(self) -> Vec<i16> {
+        self.samples
+    }
+}
+
+/// Decode 16-bit little-endian PCM, ignoring a trailing odd byte
+pub fn pcm16_from_le_bytes(bytes: &[u8]) -> Vec<i16> {
+    bytes.chunks_exact(2)
+        .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+        .collect()
+}
+
+/// Default loudness target for normalized output (RMS, about -20 dBFS)
+pub const DEFAULT_TARGET_RMS: f32 = 0.1;
+
+/// Largest gain applied when normalizing, so near-silence is not amplified into noise
+const MAX_NORMALIZE_GAIN: f32 = 10.0;
+
+/// Highest peak allowed after normalization
+const NORMALIZE_PEAK_CEILING: f32 = 0.98;
+
+/// Scale i16 samples towards `target_rms`
+///
+/// The gain is capped so the loudest sample stays below full scale, which
+/// means very peaky audio may end up slightly quieter than the target.
+pub fn normalize_rms(samples: &[i16], target_rms: f32) -> Vec<i16> {
+    let floats: Vec<f32> = samples.iter().map(|&s| s as f32 / 32768.0).collect();
+    let rms = rms_level(&floats);
+    let peak = peak_level(samples);
+    if rms <= f32::EPSILON || peak <= f32::EPSILON {
+        return samples.to_vec();
+    }
+
+    let gain = (target_rms / rms)
+        .min(MAX_NORMALIZE_GAIN)
+        .min(NORMALIZE_PEAK_CEILING / peak);
+
+    floats.iter().map(|&s| f32_to_i16(s * gain)).collect()
+}
+
+/// Peak level audio is normalized to before transcription, in dBFS
+pub const ASR_PEAK_TARGET_DB: f32 = -3.0;
+
+/// Scale i16 samples so the loudest one sits at `target_db` dBFS
+///
+/// Gain is capped like `normalize_rms` so near-silence is not amplified into
+/// noise, and samples are clamped to full scale.
+pub fn normalize_peak(samples: &[i16], target_db: f32) -> Vec<i16> {
+    let peak = peak_level(samples);
+    if peak <= f32::EPSILON {
+        return samples.to_vec();
+    }
+    let target = 10f32.powf(target_db / 20.0).min(NORMALIZE_PEAK_CEILING);
+    let gain = (target / peak).min(MAX_NORMALIZE_GAIN);
+    samples.iter().map(|&s| f32_to_i16(s as f32 / 32768.0 * gain)).collect()
+}
+
+/// Convert interleaved samples between channel counts
+///
+/// Downmixing averages all channels; upmixing copies the mono mix to every
+/// output channel.
+pub fn convert_channels(samples: &[i16], from: u16, to: u16) -> Vec<i16> {
+    let (from, to) = (from.max(1) as usize, to.max(1) as usize);
+    if from == to {
+        return samples.to_vec();
+    }
+    samples
+        .chunks(from)
+        .flat_map(|frame| {
+            let mono = frame.iter().map(|&s| s as i32).sum::<i32>() / frame.len() as i32;
+            std::iter::repeat(mono as i16).take(to)
+        })
+        .collect()
+}
+
+/// Resample mono samples from `from` Hz to `to` Hz by linear interpolation
+///
+/// When downsampling, each output sample first averages the input samples it
+/// spans, a cheap low-pass that keeps most aliasing out of the speech band.
+pub fn resample_linear(samples: &[i16], from: u32, to: u32) -> Vec<i16> {
+    if from == to || from == 0 || to == 0 || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let ratio = from as f64 / to as f64;
+    let smoothed: Cow<[i16]> = if ratio > 1.0 {
+        let half = (ratio / 2.0).floor() as usize;
+        Cow::Owned(
+            (0..samples.len())
+                .map(|i| {
+                    let window = &samples[i.saturating_sub(half)..(i + half + 1).min(samples.len())];
+                    (window.iter().map(|&s| s as i32).sum::<i32>() / window.len() as i32) as i16
+                })
+                .collect(),
+        )
+    } else {
+        Cow::Borrowed(samples)
+    };
+
+    let out_len = ((samples.len() as f64) / ratio).round() as usize;
+    let last = smoothed.len() - 1;
+    (0..out_len)
+        .map(|i| {
+            let position = i as f64 * ratio;
+            let index = (position.floor() as usize).min(last);
+            let next = (index + 1).min(last);
+            let fraction = position - index as f64;
+            let a = smoothed[index] as f64;
+            let b = smoothed[next] as f64;
+            (a + (b - a) * fraction).round() as i16
+        })
+        .collect()
+}
+
+/// Convert WAV audio to the 16 kHz mono 16-bit PCM Whisper expects
+///
+/// Audio already in that format is returned as-is. Fails if `data` is not a
+/// WAV file.
+pub fn prepare_asr_wav(data: &[u8]) -> Result<Cow<'_, [u8]>, String> {
+    let wav = parse_wav(data)?;
+    let info = wav.info;
+    if info.audio_format == WAVE_FORMAT_PCM
+        && info.bits_per_sample == 16
+        && info.channels == 1
+        && info.sample_rate == ASR_SAMPLE_RATE
+    {
+        return Ok(Cow::Borrowed(data));
+    }
+
+    let mono = convert_channels(&wav.samples, info.channels, 1);
+    let resampled = resample_linear(&mono, info.sample_rate, ASR_SAMPLE_RATE);
+    log::info!(
+        "Converted {} Hz {}-channel {}-bit audio to {} Hz mono for ASR",
+        info.sample_rate, info.channels, info.bits_per_sample, ASR_SAMPLE_RATE
+    );
+    Ok(Cow::Owned(encode_wav(&resampled, ASR_SAMPLE_RATE, 1)))
+}
+
+/// Audio file formats accepted by the pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+    Wav,
+    Mp3,
+    Flac,
+    Ogg,
+}
+
+impl AudioFormat {
+    /// Detect the format from magic bytes (None for unsupported containers)
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        match detect_container(data) {
+            "wav" => Some(AudioFormat::Wav),
+            "mp3" => Some(AudioFormat::Mp3),
+            "flac" => Some(AudioFormat::Flac),
+            "ogg" => Some(AudioFormat::Ogg),
+            _ => None,
+        }
+    }
+
+    /// File extension, used as a decoder hint
+    pub fn extension(self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "wav",
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Flac => "flac",
+            AudioFormat::Ogg => "ogg",
+        }
+    }
+}
+
+/// Detect the format of `data` and convert it to WAV
+pub fn detect_and_convert_to_wav(data: &[u8]) -> Result<Cow<'_, [u8]>, String> {
+    let format = AudioFormat::detect(data).ok_or_else(|| {
+        format!("Unsupported audio format: {} (expected WAV, MP3, FLAC or Ogg)", detect_container(data))
+    })?;
+    convert_to_wav(data, format)
+}
+
+/// Convert audio in `format` to a 16-bit PCM WAV file (WAV input is returned as-is)
+pub fn convert_to_wav(data: &[u8], format: AudioFormat) -> Result<Cow<'_, [u8]>, String> {
+    if format == AudioFormat::Wav {
+        return Ok(Cow::Borrowed(data));
+    }
+    let (samples, sample_rate, channels) = decode_compressed(data, format)?;
+    log::info!(
+        "Decoded {:?} audio: {} samples at {} Hz, {} channels",
+        format, samples.len(), sample_rate, channels
+    );
+    Ok(Cow::Owned(encode_wav(&samples, sample_rate, channels)))
+}
+
+/// Decode a compressed file to interleaved i16 samples, sample rate and channel count
+fn decode_compressed(data: &[u8], format: AudioFormat) -> Result<(Vec<i16>, u32, u16), String> {
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+    use symphonia::core::errors::Error as DecodeError;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    let source = MediaSourceStream::new(Box::new(std::io::Cursor::new(data.to_vec())), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(format.extension());
+
+    let mut reader = symphonia::default::get_probe()
+        .format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| format!("Failed to read {:?} audio: {}", format, e))?
+        .format;
+    let track = reader.tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| format!("No audio track found in {:?} file", format))?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("Unsupported {:?} codec: {}", format, e))?;
+
+    let mut sample_rate = track.codec_params.sample_rate.unwrap_or(0);
+    let mut channels = track.codec_params.channels.map_or(0, |c| c.count() as u16);
+    let mut samples = Vec::new();
+
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(DecodeError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(format!("Failed to read audio packet: {}", e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let spec = *decoded.spec();
+                sample_rate = spec.rate;
+                channels = spec.channels.count() as u16;
+                let mut buffer = SampleBuffer::<i16>::new(decoded.capacity() as u64, spec);
+                buffer.copy_interleaved_ref(decoded);
+                samples.extend_from_slice(buffer.samples());
+            }
+            // A corrupt frame is skipped rather than failing the whole file
+            Err(DecodeError::DecodeError(e)) => log::warn!("Skipping undecodable audio packet: {}", e),
+            Err(e) => return Err(format!("Failed to decode audio: {}", e)),
+        }
+    }
+
+    if samples.is_empty() || sample_rate == 0 || channels == 0 {
+        return Err(format!("No audio could be decoded from {:?} file", format));
+    }
+    Ok((samples, sample_rate, channels))
+}
+
+/// Guess the container format from magic bytes ("wav", "ogg", "flac", "mp3", "webm" or "unknown")
+pub fn detect_container(data: &[u8]) -> &'static str {
+    match data {
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => "wav",
+        [b'O', b'g', b'g', b'S', ..] => "ogg",
+        [b'f', b'L', b'a', b'C', ..] => "flac",
+        [b'I', b'D', b'3', ..] => "mp3",
+        [0xFF, b, ..] if b & 0xE0 == 0xE0 => "mp3",
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => "webm",
+        _ => "unknown",
+    }
+}
+
+/// Frame length used for voice activity detection
+const VAD_FRAME_MS: u32 = 30;
+
+/// RMS level above which a frame counts as speech (about -40 dBFS)
+const VAD_ENERGY_THRESHOLD: f32 = 0.01;
+
+/// Samples in one voice activity detection frame of interleaved audio
+pub fn vad_frame_len(sample_rate: u32, channels: u16) -> usize {
+    (sample_rate * VAD_FRAME_MS / 1000).max(1) as usize * channels.max(1) as usize
+}
+
+/// RMS level of each `vad_frame_len` frame; the last one may be shorter
+pub fn frame_levels(samples: &[i16], sample_rate: u32, channels: u16) -> Vec<f32> {
+    samples
+        .chunks(vad_frame_len(sample_rate, channels))
+        .map(|frame| {
+            let floats: Vec<f32> = frame.iter().map(|&s| s as f32 / 32768.0).collect();
+            rms_level(&floats)
+        })
+        .collect()
+}
+
+/// Fraction of frames that contain speech, using a simple energy detector
+pub fn speech_ratio(samples: &[i16], sample_rate: u32, channels: u16) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+
+    let frames = frame_levels(samples, sample_rate, channels);
+    let voiced = frames.iter().filter(|&&level| level >= VAD_ENERGY_THRESHOLD).count();
+    voiced as f32 / frames.len() as f32
+}
+
+/// Convert a linear level to dBFS, floored at -96 dB for silence
+pub fn level_to_db(level: f32) -> f32 {
+    if level <= 0.0 {
+        return -96.0;
+    }
+    (20.0 * level.log10()).max(-96.0)
+}
+
+/// Frame length over which AGC measures level and updates its gain
+const AGC_FRAME_MS: u32 = 10;
+
+/// Time constant of the AGC's running level estimate, in seconds
+const AGC_LEVEL_TIME_CONSTANT: f32 = 0.4;
+
+/// Smallest gain AGC applies to loud input
+const MIN_AGC_GAIN: f32 = 0.1;
+
+/// How much faster AGC lowers gain than it raises it, so loud onsets do not clip for long
+const AGC_ATTACK_SPEEDUP: f32 = 10.0;
+
+/// Automatic gain control settings
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AgcConfig {
+    pub enabled: bool,
+    /// Level the input is steered towards (linear RMS)
+    pub target_rms: f32,
+    /// Fastest gain increase, in dB per second (decreases are faster)
+    pub adaptation_rate: f32,
+}
+
+impl Default for AgcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target_rms: DEFAULT_TARGET_RMS,
+            adaptation_rate: 6.0,
+        }
+    }
+}
+
+/// Streaming automatic gain control for mono f32 audio
+///
+/// Keeps a running RMS estimate across chunks and slews the gain towards
+/// `target_rms`, rising no faster than `adaptation_rate`. Frames below the speech
+/// threshold do not update the estimate, so pauses are not boosted into
+/// noise, and the gain is ramped across each frame to avoid zipper noise.
+pub struct AutomaticGainControl {
+    config: AgcConfig,
+    sample_rate: u32,
+    /// Running mean square of voiced frames (None until speech is seen)
+    mean_square: Option<f32>,
+    gain: f32,
+}
+
+impl AutomaticGainControl {
+    pub fn new(config: AgcConfig, sample_rate: u32) -> Self {
+        Self { config, sample_rate: sample_rate.max(1), mean_square: None, gain: 1.0 }
+    }
+
+    /// Gain currently applied
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    /// Apply gain to a chunk of samples in place
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let frame_len = (self.sample_rate * AGC_FRAME_MS / 1000).max(1) as usize;
+
+        for frame in samples.chunks_mut(frame_len) {
+            let seconds = frame.len() as f32 / self.sample_rate as f32;
+            let level = rms_level(frame);
+
+            if level >= VAD_ENERGY_THRESHOLD {
+                let alpha = 1.0 - (-seconds / AGC_LEVEL_TIME_CONSTANT).exp();
+                let square = level * level;
+                self.mean_square = Some(match self.mean_square {
+                    Some(mean) => mean + alpha * (square - mean),
+                    None => square,
+                });
+            }
+
+            let start_gain = self.gain;
+            if let Some(mean) = self.mean_square {
+                let desired = (self.config.target_rms / mean.sqrt()).clamp(MIN_AGC_GAIN, MAX_NORMALIZE_GAIN);
+                // Slew in the dB domain so rises and falls sound equally smooth
+                let max_rise_db = self.config.adaptation_rate.max(0.0) * seconds;
+                let max_fall_db = max_rise_db * AGC_ATTACK_SPEEDUP;
+                let step_db = (20.0 * (desired / self.gain).log10()).clamp(-max_fall_db, max_rise_db);
+                self.gain *= 10f32.powf(step_db / 20.0);
+            }
+
+            let len = frame.len() as f32;
+            for (i, sample) in frame.iter_mut().enumerate() {
+                let gain = start_gain + (self.gain - start_gain) * (i + 1) as f32 / len;
+                *sample = (*sample * gain).clamp(-NORMALIZE_PEAK_CEILING, NORMALIZE_PEAK_CEILING);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One second of a 440 Hz sine at `amplitude` (0..1 of full scale)
+    fn sine(amplitude: f32, sample_rate: u32) -> Vec<i16> {
+        (0..sample_rate)
+            .map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                f32_to_i16(amplitude * (2.0 * std::f32::consts::PI * 440.0 * t).sin())
+            })
+            .collect()
+    }
+
+    fn rms(samples: &[i16]) -> f32 {
+        let floats: Vec<f32> = samples.iter().map(|&s| s as f32 / 32768.0).collect();
+        rms_level(&floats)
+    }
+
+    #[test]
+    fn normalize_rms_approaches_target() {
+        let target = 0.1;
+        for amplitude in [0.05, 0.5] {
+            let normalized = normalize_rms(&sine(amplitude, 16_000), target);
+            assert!((rms(&normalized) - target).abs() < 0.002, "amplitude {}: rms {}", amplitude, rms(&normalized));
+        }
+    }
+
+    #[test]
+    fn normalize_rms_keeps_peaks_below_full_scale() {
+        let normalized = normalize_rms(&sine(0.5, 16_000), 0.9);
+        assert!(peak_level(&normalized) <= NORMALIZE_PEAK_CEILING + 0.001);
+    }
+
+    #[test]
+    fn normalize_rms_leaves_silence_alone() {
+        assert_eq!(normalize_rms(&[0; 160], 0.1), vec![0; 160]);
+    }
+
+    #[test]
+    fn normalize_peak_reaches_target() {
+        let normalized = normalize_peak(&sine(0.2, 16_000), ASR_PEAK_TARGET_DB);
+        let target = 10f32.powf(ASR_PEAK_TARGET_DB / 20.0);
+        assert!((peak_level(&normalized) - target).abs() < 0.002, "peak {}", peak_level(&normalized));
+    }
+
+    #[test]
+    fn normalize_peak_caps_gain() {
+        let normalized = normalize_peak(&sine(0.01, 16_000), ASR_PEAK_TARGET_DB);
+        assert!((peak_level(&normalized) - 0.01 * MAX_NORMALIZE_GAIN).abs() < 0.002);
+    }
+
+    #[test]
+    fn normalize_peak_stays_below_full_scale() {
+        let normalized = normalize_peak(&sine(0.5, 16_000), 0.0);
+        assert!(peak_level(&normalized) <= NORMALIZE_PEAK_CEILING + 0.001);
+    }
+
+    #[test]
+    fn normalize_peak_leaves_silence_alone() {
+        assert_eq!(normalize_peak(&[0; 160], ASR_PEAK_TARGET_DB), vec![0; 160]);
+    }
+
+    #[test]
+    fn convert_channels_upmixes_mono() {
+        assert_eq!(convert_channels(&[100, -200, 300], 1, 2), [100, 100, -200, -200, 300, 300]);
+    }
+
+    #[test]
+    fn convert_channels_downmixes_stereo() {
+        assert_eq!(convert_channels(&[100, 300, -200, -400, 1000, 0], 2, 1), [200, -300, 500]);
+    }
+
+    #[test]
+    fn convert_channels_keeps_matching_layout() {
+        assert_eq!(convert_channels(&[1, 2, 3, 4], 2, 2), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn resample_linear_keeps_matching_rate() {
+        assert_eq!(resample_linear(&[1, 2, 3], 16_000, 16_000), [1, 2, 3]);
+    }
+
+    #[test]
+    fn resample_linear_interpolates_upsampling() {
+        assert_eq!(resample_linear(&[0, 100], 8_000, 16_000), [0, 50, 100, 100]);
+    }
+
+    #[test]
+    fn resample_linear_downsamples_to_target_length() {
+        let resampled = resample_linear(&vec![1000; 48_000], 48_000, 16_000);
+        assert_eq!(resampled.len(), 16_000);
+        assert!(resampled.iter().all(|&s| s == 1000));
+    }
+
+    #[test]
+    fn prepare_asr_wav_passes_through_whisper_format() {
+        let wav = encode_wav(&sine(0.5, ASR_SAMPLE_RATE), ASR_SAMPLE_RATE, 1);
+        assert!(matches!(prepare_asr_wav(&wav), Ok(Cow::Borrowed(_))));
+    }
+
+    #[test]
+    fn prepare_asr_wav_converts_to_16k_mono() {
+        let stereo = convert_channels(&sine(0.5, 48_000), 1, 2);
+        let input = encode_wav(&stereo, 48_000, 2);
+        let prepared = prepare_asr_wav(&input).unwrap();
+        let wav = parse_wav(&prepared).unwrap();
+        assert_eq!((wav.info.sample_rate, wav.info.channels, wav.info.bits_per_sample), (ASR_SAMPLE_RATE, 1, 16));
+        assert_eq!(wav.samples.len(), ASR_SAMPLE_RATE as usize);
+    }
+
+    #[test]
+    fn prepare_asr_wav_rejects_other_containers() {
+        assert!(prepare_asr_wav(b"ID3\x04\x00").is_err());
+    }
+
+    fn pcm_bytes(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn join_wav_pieces_returns_single_wav_unchanged() {
+        let wav = encode_wav(&[1, 2, 3], 22_050, 1);
+        assert_eq!(join_wav_pieces([(wav.as_slice(), 0)], 1).unwrap(), wav);
+    }
+
+    #[test]
+    fn join_wav_pieces_concatenates_wav_and_raw_pcm() {
+        let wav = encode_wav(&[1, 2], 24_000, 1);
+        let raw = pcm_bytes(&[3, 4]);
+        let joined = parse_wav(&join_wav_pieces([(wav.as_slice(), 0), (raw.as_slice(), 24_000)], 1).unwrap()).unwrap();
+        assert_eq!((joined.info.sample_rate, joined.info.channels), (24_000, 1));
+        assert_eq!(joined.samples, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn join_wav_pieces_rejects_mismatched_formats() {
+        let first = encode_wav(&[1, 2], 24_000, 1);
+        let second = encode_wav(&[3, 4], 16_000, 1);
+        assert!(join_wav_pieces([(first.as_slice(), 0), (second.as_slice(), 0)], 1).is_err());
+    }
+
+    #[test]
+    fn join_wav_pieces_needs_audio() {
+        assert!(join_wav_pieces(std::iter::empty(), 1).is_err());
+    }
+
+    #[test]
+    fn accumulator_flushes_only_new_audio() {
+        let mut accumulator = AudioAccumulator::new(10, Some(0.5));
+        assert!(!accumulator.push(&[1, 2, 3]));
+        assert!(accumulator.push(&[4, 5]));
+        assert_eq!(accumulator.flush(), [1, 2, 3, 4, 5]);
+        // Skipping a due flush keeps the audio for the next one
+        assert!(accumulator.push(&[6, 7, 8, 9, 10]));
+        assert!(accumulator.push(&[11]));
+        assert_eq!(accumulator.flush(), [6, 7, 8, 9, 10, 11]);
+        assert_eq!(accumulator.finish(), (1..=11).collect::<Vec<i16>>());
+    }
+}
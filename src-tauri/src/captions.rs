@@ -0,0 +1,124 @@
+//! Subtitle export for timed transcriptions
+//!
+//! Formats ASR segments as SubRip (SRT) or WebVTT. Very short segments are
+//! merged into their neighbours so captions stay on screen long enough to read.
+
+use crate::services::asr::TranscriptSegment;
+
+/// Segments shorter than this (in seconds) are merged with the next one
+const MIN_CAPTION_SECS: f64 = 1.0;
+
+/// Longest caption produced by merging, in characters
+const MAX_CAPTION_CHARS: usize = 84;
+
+/// Subtitle file format
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaptionFormat {
+    Srt,
+    Vtt,
+}
+
+impl CaptionFormat {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format.trim().to_lowercase().as_str() {
+            "srt" => Ok(Self::Srt),
+            "vtt" | "webvtt" => Ok(Self::Vtt),
+            other => Err(format!("Unknown caption format: {} (expected \"srt\" or \"vtt\")", other)),
+        }
+    }
+
+    /// File extension without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Srt => "srt",
+            Self::Vtt => "vtt",
+        }
+    }
+}
+
+/// Merge segments that are too short to read into the following segment
+pub fn merge_short_segments(segments: &[TranscriptSegment]) -> Vec<TranscriptSegment> {
+    let mut merged: Vec<TranscriptSegment> = Vec::new();
+
+    for segment in segments {
+        let text = segment.text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let end = segment.end.max(segment.start);
+
+        if let Some(last) = merged.last_mut() {
+            let too_short = last.end - last.start < MIN_CAPTION_SECS;
+            let fits = last.text.chars().count() + 1 + text.chars().count() <= MAX_CAPTION_CHARS;
+            if too_short && fits {
+                last.text.push(' ');
+                last.text.push_str(text);
+                last.end = end;
+                continue;
+            }
+        }
+
+        merged.push(TranscriptSegment {
+            start: segment.start,
+            end,
+            text: text.to_string(),
+        });
+    }
+
+    merged
+}
+
+/// Format seconds as `HH:MM:SS,mmm` (SRT) or `HH:MM:SS.mmm` (VTT)
+fn timecode(seconds: f64, format: CaptionFormat) -> String {
+    let total_ms = (seconds.max(0.0) * 1000.0).round() as u64;
+    let separator = match format {
+        CaptionFormat::Srt => ',',
+        CaptionFormat::Vtt => '.',
+    };
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        total_ms / 3_600_000,
+        total_ms / 60_000 % 60,
+        total_ms / 1000 % 60,
+        separator,
+        total_ms % 1000
+    )
+}
+
+/// Escape caption text for the given format
+///
+/// Blank lines would end a cue early in both formats, so line breaks are
+/// collapsed. WebVTT additionally treats `&`, `<` and `>` as markup, and
+/// `-->` inside a cue would be read as a timing line.
+fn escape_text(text: &str, format: CaptionFormat) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match format {
+        CaptionFormat::Srt => text.replace("-->", "->"),
+        CaptionFormat::Vtt => text
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;"),
+    }
+}
+
+/// Render segments as a complete subtitle file
+pub fn format_captions(segments: &[TranscriptSegment], format: CaptionFormat) -> String {
+    let mut output = String::new();
+    if format == CaptionFormat::Vtt {
+        output.push_str("WEBVTT\n\n");
+    }
+
+    for (i, segment) in merge_short_segments(segments).iter().enumerate() {
+        if format == CaptionFormat::Srt {
+            output.push_str(&format!("{}\n", i + 1));
+        }
+        output.push_str(&format!(
+            "{} --> {}\n{}\n\n",
+            timecode(segment.start, format),
+            timecode(segment.end, format),
+            escape_text(&segment.text, format)
+        ));
+    }
+
+    output
+}
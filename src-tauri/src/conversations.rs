@@ -0,0 +1,348 @@
+//! Saved conversations on disk
+//!
+//! Each conversation is a JSON file in `<app data>/conversations/<id>.json`.
+
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::paths::APP_DATA_DIR;
+use crate::services::llm::ChatMessage;
+
+/// Maximum length of an auto-derived title
+const TITLE_MAX_CHARS: usize = 50;
+/// Maximum length of the list preview
+const PREVIEW_MAX_CHARS: usize = 100;
+
+/// A saved conversation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conversation {
+    pub id: String,
+    /// User-set title; derived from the first user message when None
+    pub title: Option<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub messages: Vec<ChatMessage>,
+}
+
+/// Conversation summary for the history browser
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationMeta {
+    pub id: String,
+    pub title: String,
+    pub message_count: usize,
+    /// Unix timestamp in seconds
+    pub last_updated: u64,
+    pub preview: String,
+}
+
+impl Conversation {
+    /// Title to display, falling back to the first user message
+    pub fn display_title(&self) -> String {
+        if let Some(title) = self.title.as_deref().filter(|t| !t.trim().is_empty()) {
+            return title.to_string();
+        }
+        self.messages
+            .iter()
+            .find(|m| m.role == "user")
+            .map(|m| truncate_words(&m.content, TITLE_MAX_CHARS))
+            .unwrap_or_else(|| "New conversation".to_string())
+    }
+
+    pub fn meta(&self) -> ConversationMeta {
+        ConversationMeta {
+            id: self.id.clone(),
+            title: self.display_title(),
+            message_count: self.messages.len(),
+            last_updated: self.updated_at,
+            preview: self.messages
+                .last()
+                .map(|m| truncate_words(&m.content, PREVIEW_MAX_CHARS))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Current time as Unix seconds
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Format Unix seconds as "YYYY-MM-DD HH:MM UTC"
+pub fn format_utc(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let minutes = secs % 86_400 / 60;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, minutes / 60, minutes % 60)
+}
+
+/// Shorten text to at most `max_chars`, preferring a word boundary
+fn truncate_words(text: &str, max_chars: usize) -> String {
+    let text = text.trim();
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(max_chars).collect();
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(idx) if idx > max_chars / 2 => &cut[..idx],
+        _ => cut.as_str(),
+    };
+    format!("{}…", cut.trim_end())
+}
+
+/// Directory conversations are stored in
+pub fn conversations_dir() -> PathBuf {
+    APP_DATA_DIR.join("conversations")
+}
+
+/// Live history, saved on exit and restored on launch
+pub fn session_path() -> PathBuf {
+    APP_DATA_DIR.join("session.json")
+}
+
+/// Path of a conversation file, rejecting ids that could escape the directory
+fn conversation_path(id: &str) -> Result<PathBuf, String> {
+    let valid = !id.is_empty()
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(format!("Invalid conversation id: {}", id));
+    }
+    Ok(conversations_dir().join(format!("{}.json", id)))
+}
+
+/// Generate a new conversation id
+pub fn new_id() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    format!("conv-{}", millis)
+}
+
+/// Write a conversation to disk
+pub fn save(conversation: &Conversation) -> Result<(), String> {
+    let path = conversation_path(&conversation.id)?;
+    std::fs::create_dir_all(conversations_dir())
+        .map_err(|e| format!("Failed to create conversations directory: {}", e))?;
+    let json = serde_json::to_string_pretty(conversation)
+        .map_err(|e| format!("Failed to serialize conversation: {}", e))?;
+    std::fs::write(&path, json)
+        .map_err(|e| format!("Failed to save conversation: {}", e))
+}
+
+/// Read a conversation from disk
+pub fn load(id: &str) -> Result<Conversation, String> {
+    let path = conversation_path(id)?;
+    let json = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read conversation {}: {}", id, e))?;
+    serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to parse conversation {}: {}", id, e))
+}
+
+/// List saved conversations, most recently updated first.
+/// Unreadable files are skipped.
+pub fn list() -> Result<Vec<ConversationMeta>, String> {
+    let dir = conversations_dir();
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let entries = std::fs::read_dir(&dir)
+        .map_err(|e| format!("Failed to read conversations directory: {}", e))?;
+
+    let mut metas: Vec<ConversationMeta> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| {
+            let json = std::fs::read_to_string(&path).ok()?;
+            match serde_json::from_str::<Conversation>(&json) {
+                Ok(conversation) => Some(conversation.meta()),
+                Err(e) => {
+                    log::warn!("Skipping unreadable conversation {:?}: {}", path, e);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    metas.sort_by(|a, b| b.last_updated.cmp(&a.last_updated));
+    Ok(metas)
+}
+
+/// Set a conversation's title (empty clears it back to the derived title)
+pub fn rename(id: &str, title: &str) -> Result<ConversationMeta, String> {
+    let mut conversation = load(id)?;
+    let title = title.trim();
+    conversation.title = if title.is_empty() { None } else { Some(title.to_string()) };
+    conversation.updated_at = now_secs();
+    save(&conversation)?;
+    Ok(conversation.meta())
+}
+
+/// Delete a saved conversation
+pub fn delete(id: &str) -> Result<(), String> {
+    let path = conversation_path(id)?;
+    std::fs::remove_file(&path)
+        .map_err(|e| format!("Failed to delete conversation {}: {}", id, e))
+}
+
+/// A conversation parsed from Markdown
+#[derive(Debug, Clone)]
+pub struct ImportedMarkdown {
+    pub title: Option<String>,
+    /// Text before the first message, or a "System" section
+    pub system_prompt: Option<String>,
+    pub messages: Vec<ChatMessage>,
+}
+
+/// Role named by a heading or label, if it names one
+fn role_for_label(label: &str) -> Option<&'static str> {
+    let lower = label.to_lowercase();
+    let words: Vec<&str> = lower.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).collect();
+    let has = |names: &[&str]| words.iter().any(|w| names.contains(w));
+    if has(&["system", "instructions"]) {
+        Some("system")
+    } else if has(&["user", "you", "me", "human", "question", "prompt"]) {
+        Some("user")
+    } else if has(&["assistant", "ai", "bot", "model", "assidenter", "answer", "response", "reply"]) {
+        Some("assistant")
+    } else {
+        None
+    }
+}
+
+/// Split a `**Role:** text` or `Role: text` line into its label and the rest
+fn split_label_line(line: &str) -> Option<(&str, &str)> {
+    let trimmed = line.trim_start();
+    let (label, rest) = if let Some(bold) = trimmed.strip_prefix("**") {
+        let end = bold.find("**")?;
+        (&bold[..end], &bold[end + 2..])
+    } else {
+        let colon = trimmed.find(':')?;
+        (&trimmed[..colon], &trimmed[colon..])
+    };
+    let label = label.trim().trim_end_matches(':').trim();
+    // Only short labels count, so ordinary sentences with a colon are left alone
+    if label.is_empty() || label.split_whitespace().count() > 3 {
+        return None;
+    }
+    let rest = rest.trim_start().strip_prefix(':').unwrap_or(rest).trim();
+    Some((label, rest))
+}
+
+/// Parse role-headed Markdown (as shared or exported) into messages
+///
+/// Sections start at headings (`## User`, `### Assistant`) or label lines
+/// (`**User:** ...`). Headings that name no known role alternate between
+/// user and assistant. A leading `# Title` becomes the title, and any text
+/// before the first message (or in a "System" section) the system prompt.
+pub fn parse_markdown(text: &str) -> ImportedMarkdown {
+    let mut title = None;
+    let mut preamble: Vec<&str> = Vec::new();
+    let mut system: Vec<String> = Vec::new();
+    let mut messages: Vec<ChatMessage> = Vec::new();
+    let mut current: Option<(&'static str, Vec<&str>)> = None;
+
+    fn flush(current: &mut Option<(&'static str, Vec<&str>)>, system: &mut Vec<String>, messages: &mut Vec<ChatMessage>) {
+        if let Some((role, lines)) = current.take() {
+            let content = lines.join("\n").trim().to_string();
+            if content.is_empty() {
+                return;
+            }
+            if role == "system" {
+                system.push(content);
+            } else {
+                messages.push(ChatMessage::new(role, content));
+            }
+        }
+    }
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed == "---" || trimmed == "***" {
+            continue;
+        }
+
+        let heading = trimmed.starts_with('#').then(|| {
+            let level = trimmed.chars().take_while(|&c| c == '#').count();
+            (level, trimmed[level..].trim().trim_matches('*').trim().trim_end_matches(':'))
+        });
+
+        let (role, first_line) = match heading {
+            Some((level, label)) => match role_for_label(label) {
+                Some(role) => (role, None),
+                None if level == 1 && title.is_none() && messages.is_empty() && current.is_none() => {
+                    title = Some(label.to_string()).filter(|t| !t.is_empty());
+                    continue;
+                }
+                None => {
+                    // Unknown heading: alternate with the previous speaker
+                    let previous = current.as_ref().map(|(r, _)| *r)
+                        .or_else(|| messages.last().map(|m| if m.role == "user" { "user" } else { "assistant" }));
+                    (if previous == Some("user") { "assistant" } else { "user" }, None)
+                }
+            },
+            None => match split_label_line(line).and_then(|(label, rest)| Some((role_for_label(label)?, rest))) {
+                Some((role, rest)) => (role, Some(rest)),
+                None => {
+                    match current.as_mut() {
+                        Some((_, lines)) => lines.push(line),
+                        None => preamble.push(line),
+                    }
+                    continue;
+                }
+            },
+        };
+
+        flush(&mut current, &mut system, &mut messages);
+        current = Some((role, first_line.into_iter().collect()));
+    }
+    flush(&mut current, &mut system, &mut messages);
+
+    let preamble = preamble.join("\n").trim().to_string();
+    if !preamble.is_empty() {
+        system.insert(0, preamble);
+    }
+
+    ImportedMarkdown {
+        title,
+        system_prompt: (!system.is_empty()).then(|| system.join("\n\n")),
+        messages,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_the_epoch() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00 UTC");
+    }
+
+    #[test]
+    fn formats_leap_days() {
+        assert_eq!(format_utc(951_782_400), "2000-02-29 00:00 UTC");
+        assert_eq!(format_utc(1_709_251_199), "2024-02-29 23:59 UTC");
+    }
+
+    #[test]
+    fn formats_year_and_century_boundaries() {
+        assert_eq!(format_utc(1_735_689_540), "2024-12-31 23:59 UTC");
+        assert_eq!(format_utc(4_102_444_800), "2100-01-01 00:00 UTC");
+    }
+}
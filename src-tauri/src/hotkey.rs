@@ -0,0 +1,70 @@
+//! Global shortcut that toggles listening without focusing the window
+//!
+//! Desktop only. The chosen accelerator is persisted in the app data
+//! directory and registered again on launch.
+
+use tauri::{AppHandle, Runtime};
+use tauri::plugin::TauriPlugin;
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
+
+use crate::paths::APP_DATA_DIR;
+
+/// File the accelerator is persisted to
+const HOTKEY_FILE: &str = "hotkey.json";
+
+/// Accelerator used until the user picks one
+pub const DEFAULT_HOTKEY: &str = "CommandOrControl+Shift+Space";
+
+/// Global shortcut plugin calling `on_pressed` when the hotkey is pressed
+pub fn plugin<R: Runtime>(on_pressed: fn(&AppHandle<R>)) -> TauriPlugin<R> {
+    tauri_plugin_global_shortcut::Builder::new()
+        .with_handler(move |app, _shortcut, event| {
+            if event.state() == ShortcutState::Pressed {
+                on_pressed(app);
+            }
+        })
+        .build()
+}
+
+/// Persisted accelerator, or the default
+pub fn load() -> String {
+    std::fs::read_to_string(APP_DATA_DIR.join(HOTKEY_FILE))
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_else(|| DEFAULT_HOTKEY.to_string())
+}
+
+/// Persist the accelerator registered on launch
+pub fn save(accelerator: &str) -> Result<(), String> {
+    std::fs::create_dir_all(&*APP_DATA_DIR)
+        .map_err(|e| format!("Failed to create data directory: {}", e))?;
+    let json = serde_json::to_string(accelerator)
+        .map_err(|e| format!("Failed to serialize hotkey: {}", e))?;
+    std::fs::write(APP_DATA_DIR.join(HOTKEY_FILE), json)
+        .map_err(|e| format!("Failed to save hotkey: {}", e))
+}
+
+/// Parse an accelerator such as "CommandOrControl+Shift+Space"
+pub fn parse(accelerator: &str) -> Result<Shortcut, String> {
+    accelerator
+        .parse::<Shortcut>()
+        .map_err(|e| format!("Invalid hotkey '{}': {}", accelerator, e))
+}
+
+/// Register `accelerator` as the listening hotkey
+///
+/// Fails with a readable error if another application already holds it.
+pub fn register<R: Runtime>(app: &AppHandle<R>, accelerator: &str) -> Result<(), String> {
+    let shortcut = parse(accelerator)?;
+    app.global_shortcut()
+        .register(shortcut)
+        .map_err(|e| format!("Hotkey {} is unavailable, it may be in use by another application: {}", accelerator, e))
+}
+
+/// Release a previously registered hotkey
+pub fn unregister<R: Runtime>(app: &AppHandle<R>, accelerator: &str) -> Result<(), String> {
+    let shortcut = parse(accelerator)?;
+    app.global_shortcut()
+        .unregister(shortcut)
+        .map_err(|e| format!("Failed to release hotkey {}: {}", accelerator, e))
+}
@@ -1,21 +1,41 @@
 mod services;
+mod conversations;
+mod captions;
+mod mic;
+mod paths;
+mod trace;
+#[cfg(feature = "ocr")]
+mod ocr;
+#[cfg(desktop)]
+mod hotkey;
+pub mod audio;
+pub mod text;
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::{HashSet, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
-use tauri::{AppHandle, Emitter, State};
+use tauri::{AppHandle, Emitter, Manager, State};
 use serde::{Deserialize, Serialize};
 use base64::Engine;
 use xcap::Monitor;
 use image::codecs::png::PngEncoder;
 use image::ImageEncoder;
 
-use crate::services::{WhisperLiveKit, QwenLLM, VoxCPMTTS, ServiceMode};
-use crate::services::asr::WhisperConfig;
-use crate::services::llm::QwenConfig;
-use crate::services::tts::VoxCPMConfig;
+use crate::conversations::{Conversation, ConversationMeta};
+use crate::trace::TurnTrace;
+use crate::services::{WhisperLiveKit, QwenLLM, VoxCPMTTS, ServiceMode, RetryBudget};
+use crate::services::fallback::{parse_chain, with_fallback, FallbackEvent};
+use crate::services::asr::{WhisperConfig, PunctuationMethod, TranscriptionResult};
+use crate::services::llm::{QwenConfig, ResponseLength, LLMResponse, ChatOptions, ContextUsage, ChatMessage, JsonResponse, FINISH_CANCELLED, FOLLOW_UP_MARKER};
+use crate::services::tts::{VoxCPMConfig, TTSResult, AlignmentToken, VoiceInfo};
 
 #[cfg(feature = "embedded-services")]
-use crate::services::embedded::{ModelManager, ModelInfo};
+use crate::services::embedded::{DownloadSummary, ModelManager, ModelInfo, RepairReport, EmbeddedLLM, EmbeddedLLMConfig, KvCacheUsage, WhisperVariant};
+#[cfg(feature = "embedded-services")]
+use crate::services::embedded::{EmbeddedASR, EmbeddedASRConfig, EmbeddedTTS, EmbeddedTTSConfig, MODEL_DIR, LLM_MODEL_FILE, save_model_dir};
 
 /// Application state (thread-safe)
 pub struct AppState {
@@ -23,22 +43,290 @@ pub struct AppState {
     llm: Mutex<QwenLLM>,
     tts: Mutex<VoxCPMTTS>,
     is_listening: AtomicBool,
-    service_mode: ServiceMode,
+    /// Set while a turn is running through the pipeline
+    is_processing: AtomicBool,
+    /// Response audio is being sent or played, until `tts_playback_finished`
+    is_speaking: AtomicBool,
+    /// Set by `stop_generation` to cut off a streaming LLM reply
+    cancel_generation: AtomicBool,
+    /// Generation counter; audio from an older turn is stale and never emitted
+    turn_id: AtomicU64,
+    /// Text messages waiting to be processed by `enqueue_messages`
+    message_queue: Mutex<VecDeque<String>>,
+    queue_running: AtomicBool,
+    /// Set once `context-warning` has fired, until usage drops again
+    context_warned: AtomicBool,
+    /// Id of the saved conversation the current history belongs to
+    current_conversation: Mutex<Option<String>>,
+    /// Languages reported by the most recent transcriptions
+    recent_languages: Mutex<VecDeque<String>>,
+    /// Voices offered by the TTS server, fetched on first language match
+    tts_voices: Mutex<Option<Vec<VoiceInfo>>>,
+    /// Most recent transcription, kept for caption export
+    last_transcription: Mutex<Option<TranscriptionResult>>,
+    /// Dictation mode: transcriptions accumulate here until finalized
+    dictation: Mutex<DictationState>,
+    /// Running native mic level monitor, if any
+    mic_monitor: std::sync::Mutex<Option<mic::CaptureHandle>>,
+    /// Audio pushed with `push_audio_chunk` since listening started
+    push_to_talk: std::sync::Mutex<Option<audio::AudioAccumulator>>,
+    /// An interim push-to-talk transcription is in flight
+    interim_running: AtomicBool,
+    /// Recording started by `start_recording`, if any
+    recording: std::sync::Mutex<Option<mic::Recording>>,
+    /// EMA smoothing applied to `mic-level` events
+    mic_smoothing: Arc<std::sync::Mutex<audio::LevelSmoother>>,
+    /// Gain control applied to streamed microphone audio
+    agc: std::sync::Mutex<audio::AgcConfig>,
+    /// Audio of the most recent spoken response, one entry per synthesized piece
+    last_tts_audio: Mutex<Vec<TTSResult>>,
+    /// Phrase spoken while the LLM is thinking, and its cached audio
+    thinking_filler: Mutex<Option<String>>,
+    filler_audio: Mutex<Option<TTSResult>>,
+    /// Monitor captured when no index is given
+    default_monitor: Mutex<Option<usize>>,
+    /// Registered global hotkey toggling listening (desktop only)
+    hotkey: std::sync::Mutex<Option<String>>,
+    /// Whether turns run on remote servers or on-device models
+    service_mode: Mutex<ServiceMode>,
+    /// Backends tried, in order, when the service mode's backend fails
+    fallback_modes: Mutex<Vec<ServiceMode>>,
+    /// Services and backends `fallback-used` was emitted for this turn
+    reported_fallbacks: std::sync::Mutex<HashSet<String>>,
+    /// Retry time shared by all stages of a single pipeline run
+    retry_budget: Arc<RetryBudget>,
+    /// Record a `TurnTrace` for each `process_audio` run
+    trace_turn: AtomicBool,
+    last_turn_trace: Mutex<Option<TurnTrace>>,
+    #[cfg(feature = "embedded-services")]
+    model_manager: Mutex<ModelManager>,
+    #[cfg(feature = "embedded-services")]
+    embedded_llm: Mutex<EmbeddedLLM>,
+    #[cfg(feature = "embedded-services")]
+    embedded_asr: Mutex<EmbeddedASR>,
     #[cfg(feature = "embedded-services")]
-    model_manager: ModelManager,
+    embedded_tts: Mutex<EmbeddedTTS>,
 }
 
 impl AppState {
     fn new() -> Self {
+        // Every client's retries draw on the same per-turn budget
+        let retry_budget = Arc::new(RetryBudget::default());
+        let mut asr = WhisperLiveKit::new(WhisperConfig::default());
+        asr.set_retry_budget(retry_budget.clone());
+        let mut llm = QwenLLM::new(QwenConfig::default());
+        llm.set_retry_budget(retry_budget.clone());
+        let mut tts = VoxCPMTTS::new(VoxCPMConfig::default());
+        tts.set_retry_budget(retry_budget.clone());
+
         Self {
-            asr: Mutex::new(WhisperLiveKit::new(WhisperConfig::default())),
-            llm: Mutex::new(QwenLLM::new(QwenConfig::default())),
-            tts: Mutex::new(VoxCPMTTS::new(VoxCPMConfig::default())),
+            asr: Mutex::new(asr),
+            llm: Mutex::new(llm),
+            tts: Mutex::new(tts),
             is_listening: AtomicBool::new(false),
-            service_mode: ServiceMode::default(),
+            is_processing: AtomicBool::new(false),
+            is_speaking: AtomicBool::new(false),
+            cancel_generation: AtomicBool::new(false),
+            turn_id: AtomicU64::new(0),
+            message_queue: Mutex::new(VecDeque::new()),
+            queue_running: AtomicBool::new(false),
+            context_warned: AtomicBool::new(false),
+            interim_running: AtomicBool::new(false),
+            current_conversation: Mutex::new(None),
+            recent_languages: Mutex::new(VecDeque::with_capacity(LANGUAGE_WINDOW)),
+            tts_voices: Mutex::new(None),
+            last_transcription: Mutex::new(None),
+            dictation: Mutex::new(DictationState::default()),
+            mic_monitor: std::sync::Mutex::new(None),
+            push_to_talk: std::sync::Mutex::new(None),
+            recording: std::sync::Mutex::new(None),
+            mic_smoothing: Arc::new(std::sync::Mutex::new(audio::LevelSmoother::default())),
+            agc: std::sync::Mutex::new(audio::AgcConfig::default()),
+            last_tts_audio: Mutex::new(Vec::new()),
+            thinking_filler: Mutex::new(None),
+            filler_audio: Mutex::new(None),
+            default_monitor: Mutex::new(None),
+            hotkey: std::sync::Mutex::new(None),
+            service_mode: Mutex::new(ServiceMode::default()),
+            fallback_modes: Mutex::new(vec![ServiceMode::Remote]),
+            reported_fallbacks: std::sync::Mutex::new(HashSet::new()),
+            retry_budget,
+            trace_turn: AtomicBool::new(false),
+            last_turn_trace: Mutex::new(None),
+            #[cfg(feature = "embedded-services")]
+            model_manager: Mutex::new(ModelManager::new()),
+            #[cfg(feature = "embedded-services")]
+            embedded_llm: Mutex::new(EmbeddedLLM::new(EmbeddedLLMConfig::load_or_default())),
+            #[cfg(feature = "embedded-services")]
+            embedded_asr: Mutex::new(EmbeddedASR::new(EmbeddedASRConfig {
+                model_path: MODEL_DIR.join(WhisperVariant::load_active().file_name()),
+                ..EmbeddedASRConfig::default()
+            })),
             #[cfg(feature = "embedded-services")]
-            model_manager: ModelManager::new(),
+            embedded_tts: Mutex::new(EmbeddedTTS::new(EmbeddedTTSConfig::default())),
+        }
+    }
+
+    /// Backends to try in order: the service mode's own, then its fallbacks
+    async fn service_chain(&self) -> Vec<ServiceMode> {
+        let primary = *self.service_mode.lock().await;
+        let mut chain = vec![primary];
+        chain.extend(self.fallback_modes.lock().await.iter().filter(|&&m| m != primary));
+        chain
+    }
+
+    /// `service_chain` without the backends that cannot run `service` (see
+    /// `backend_supports`), unless none is left, so the error explains why
+    async fn chain_for(&self, service: &str) -> Vec<ServiceMode> {
+        let chain = self.service_chain().await;
+        let supported: Vec<ServiceMode> = chain.iter().copied().filter(|&mode| backend_supports(mode, service)).collect();
+        if supported.is_empty() { chain } else { supported }
+    }
+
+    /// Enter the listening state. Returns false if already listening.
+    ///
+    /// If the assistant is speaking, its playback is stopped first (barge-in).
+    fn start_listening(&self, app: &AppHandle) -> bool {
+        if self.is_listening.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        if self.stop_speaking(app) {
+            log::info!("User barged in, response playback stopped");
+        }
+        let _ = app.emit("listening-started", ());
+        log::info!("Listening started");
+        true
+    }
+
+    /// Leave the listening state
+    fn stop_listening(&self, app: &AppHandle) {
+        self.is_listening.store(false, Ordering::SeqCst);
+        let _ = app.emit("listening-stopped", ());
+        log::info!("Listening stopped");
+    }
+
+    /// Stop response audio: cancel the rest of the synthesis and emit
+    /// `stop-tts` so the frontend drops queued playback. Returns whether the
+    /// assistant was speaking.
+    fn stop_speaking(&self, app: &AppHandle) -> bool {
+        if !self.is_speaking.swap(false, Ordering::SeqCst) {
+            return false;
+        }
+        // Superseding the turn makes `synthesize_response` stop after the current piece
+        let turn_id = self.turn_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let _ = app.emit("stop-tts", turn_id);
+        true
+    }
+
+    /// Emit `context-warning` when history usage crosses `CONTEXT_WARNING_RATIO`
+    async fn check_context_usage(&self, app: &AppHandle) {
+        let usage = self.llm.lock().await.context_usage();
+        let high = usage.used_tokens as f32 >= usage.max_tokens as f32 * CONTEXT_WARNING_RATIO;
+        if high && !self.context_warned.swap(true, Ordering::SeqCst) {
+            log::warn!("Context usage at {}/{} tokens", usage.used_tokens, usage.max_tokens);
+            let _ = app.emit("context-warning", &usage);
+        } else if !high {
+            self.context_warned.store(false, Ordering::SeqCst);
+        }
+    }
+
+    /// Start a new turn, invalidating audio from any previous one and
+    /// refilling the retry budget
+    fn begin_turn(&self, app: &AppHandle) -> u64 {
+        self.retry_budget.reset();
+        if let Ok(mut reported) = self.reported_fallbacks.lock() {
+            reported.clear();
+        }
+        let turn_id = self.turn_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let _ = app.emit("turn-started", turn_id);
+        turn_id
+    }
+
+    /// Whether `turn_id` has not been superseded or interrupted
+    fn is_current_turn(&self, turn_id: u64) -> bool {
+        self.turn_id.load(Ordering::SeqCst) == turn_id
+    }
+
+    /// Record the language of a transcription in the rolling window
+    async fn record_language(&self, language: Option<&str>) {
+        let Some(language) = language.map(str::trim).filter(|l| !l.is_empty() && *l != "auto") else {
+            return;
+        };
+        let mut recent = self.recent_languages.lock().await;
+        if recent.len() == LANGUAGE_WINDOW {
+            recent.pop_front();
         }
+        recent.push_back(language.to_lowercase());
+    }
+
+    /// Most common language over recent turns, or None with no data
+    async fn dominant_language(&self) -> Option<LanguageDetection> {
+        let recent = self.recent_languages.lock().await;
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        for language in recent.iter() {
+            match counts.iter_mut().find(|(l, _)| *l == language.as_str()) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((language.as_str(), 1)),
+            }
+        }
+
+        let (language, count) = counts.into_iter().max_by_key(|(_, count)| *count)?;
+        Some(LanguageDetection {
+            language: language.to_string(),
+            confidence: count as f32 / recent.len() as f32,
+            samples: recent.len(),
+        })
+    }
+}
+
+/// Fraction of the context window at which `context-warning` is emitted
+const CONTEXT_WARNING_RATIO: f32 = 0.8;
+
+/// Number of recent turns considered when detecting the conversation language
+const LANGUAGE_WINDOW: usize = 10;
+
+/// Dominant spoken language over recent turns
+#[derive(Debug, Clone, Serialize)]
+pub struct LanguageDetection {
+    pub language: String,
+    /// Fraction of recent turns in this language
+    pub confidence: f32,
+    /// Number of turns considered
+    pub samples: usize,
+}
+
+/// Accumulated dictation across multiple recordings
+#[derive(Debug, Default)]
+struct DictationState {
+    append_mode: bool,
+    buffer: String,
+}
+
+/// Marks the pipeline as busy for as long as it is held
+struct ProcessingGuard<'a>(&'a AtomicBool);
+
+impl<'a> ProcessingGuard<'a> {
+    fn try_acquire(flag: &'a AtomicBool) -> Option<Self> {
+        flag.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .ok()
+            .map(|_| Self(flag))
+    }
+}
+
+impl Drop for ProcessingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Result for a turn whose audio was discarded after an interruption
+fn interrupted_result(transcription: String, response: String) -> ProcessingResult {
+    ProcessingResult {
+        status: "interrupted".to_string(),
+        transcription: Some(transcription),
+        response: Some(response),
+        audio_ready: false,
+        timing: PipelineTiming::default(),
     }
 }
 
@@ -46,8 +334,18 @@ impl AppState {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServiceConfig {
     pub asr_url: String,
+    /// Streaming ASR endpoint (derived from `asr_url` when absent)
+    #[serde(default)]
+    pub asr_ws_url: Option<String>,
     pub llm_url: String,
     pub tts_url: String,
+    /// Bearer tokens per service; absent keeps the current key, empty clears it
+    #[serde(default)]
+    pub asr_api_key: Option<String>,
+    #[serde(default)]
+    pub llm_api_key: Option<String>,
+    #[serde(default)]
+    pub tts_api_key: Option<String>,
 }
 
 /// Processing result sent to frontend
@@ -57,8 +355,90 @@ pub struct ProcessingResult {
     pub transcription: Option<String>,
     pub response: Option<String>,
     pub audio_ready: bool,
+    /// Stage durations (voice turns only)
+    #[serde(flatten)]
+    pub timing: PipelineTiming,
+}
+
+/// Payload of the `pipeline-timing` event: how long each stage of a voice
+/// turn took, in milliseconds (None for stages that did not run)
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct PipelineTiming {
+    pub asr_ms: Option<u64>,
+    pub llm_ms: Option<u64>,
+    pub tts_ms: Option<u64>,
+    pub total_ms: Option<u64>,
+}
+
+impl PipelineTiming {
+    /// Record the end-to-end time and emit `pipeline-timing`
+    fn finish(mut self, app: &AppHandle, started: std::time::Instant) -> Self {
+        self.total_ms = Some(elapsed_ms(started));
+        log::info!(
+            "Pipeline timing: ASR {:?}ms, LLM {:?}ms, TTS {:?}ms, total {:?}ms",
+            self.asr_ms, self.llm_ms, self.tts_ms, self.total_ms
+        );
+        let _ = app.emit("pipeline-timing", &self);
+        self
+    }
+}
+
+/// Milliseconds since `started`
+fn elapsed_ms(started: std::time::Instant) -> u64 {
+    started.elapsed().as_millis() as u64
+}
+
+/// Result of one message processed from the queue
+#[derive(Debug, Clone, Serialize)]
+pub struct QueuedMessageResult {
+    pub index: usize,
+    pub message: String,
+    pub result: Option<ProcessingResult>,
+    pub error: Option<String>,
+}
+
+/// Summary emitted when the message queue drains
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueSummary {
+    pub processed: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// What the app detected when decoding audio, for debugging capture issues
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioDiagnostics {
+    pub decoded_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    pub duration: f64,
+    pub peak_level: f32,
+    pub clipping_ratio: f64,
+    pub reencoded_base64: String,
+    pub warnings: Vec<String>,
+}
+
+/// Quick summary of a recording, for feedback before running the pipeline
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioInfo {
+    /// Sample encoding: "pcm" or "float"
+    pub format: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    pub duration: f64,
+    /// No frames above the voice activity threshold
+    pub is_silent: bool,
+    pub is_clipping: bool,
+    pub peak_db: f32,
 }
 
+/// Fraction of speech frames below which a recording counts as silent
+const SILENT_SPEECH_RATIO: f32 = 0.02;
+
+/// Fraction of clipped samples above which a recording counts as clipping
+const CLIPPING_THRESHOLD: f64 = 0.001;
+
 /// Service status for frontend
 #[derive(Debug, Clone, Serialize)]
 pub struct ServiceStatus {
@@ -66,226 +446,3053 @@ pub struct ServiceStatus {
     pub asr_ready: bool,
     pub llm_ready: bool,
     pub tts_ready: bool,
+    /// Health check round-trip times (None when unreachable or not checked)
+    pub asr_latency_ms: Option<u64>,
+    pub llm_latency_ms: Option<u64>,
+    pub tts_latency_ms: Option<u64>,
     #[cfg(feature = "embedded-services")]
     pub models_ready: bool,
 }
 
-/// Start listening for voice input (simplified - frontend handles audio)
+/// Start listening for voice input
+///
+/// The frontend captures audio and may send it with `push_audio_chunk` as
+/// mono 16-bit PCM at `sample_rate` (default 16 kHz). With `interim_secs`,
+/// each time that much new audio has arrived it is transcribed on its own.
 #[tauri::command]
-async fn start_listening(app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
-    if state.is_listening.load(Ordering::SeqCst) {
+async fn start_listening(
+    sample_rate: Option<u32>,
+    interim_secs: Option<f32>,
+    app: AppHandle,
+    state: State<'_, AppState>
+) -> Result<(), String> {
+    if sample_rate == Some(0) {
+        return Err("Sample rate must be greater than 0".to_string());
+    }
+    if !state.start_listening(&app) {
         return Err("Already listening".to_string());
     }
-    state.is_listening.store(true, Ordering::SeqCst);
-    
-    // Emit listening started event
-    let _ = app.emit("listening-started", ());
-    
-    log::info!("Listening started");
+    let accumulator = audio::AudioAccumulator::new(sample_rate.unwrap_or(audio::ASR_SAMPLE_RATE), interim_secs);
+    *state.push_to_talk.lock().map_err(|e| e.to_string())? = Some(accumulator);
     Ok(())
 }
 
 /// Stop listening for voice input
+///
+/// Audio sent with `push_audio_chunk` is transcribed and returned as the
+/// final transcript (also emitted as `transcription`).
 #[tauri::command]
-async fn stop_listening(app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
-    state.is_listening.store(false, Ordering::SeqCst);
-    
-    // Emit listening stopped event
-    let _ = app.emit("listening-stopped", ());
-    
-    log::info!("Listening stopped");
-    Ok(())
+async fn stop_listening(app: AppHandle, state: State<'_, AppState>) -> Result<Option<TranscriptionResult>, String> {
+    state.stop_listening(&app);
+    finish_push_to_talk(&app, &state).await
 }
 
-/// Check if currently listening
+/// Add base64 16-bit little-endian mono PCM to the audio being recorded
+///
+/// When an interim transcription is due, the audio added since the previous
+/// one is transcribed in the background and emitted as `push-to-talk-interim`
+/// (or `push-to-talk-interim-error`). While one is running, new audio waits
+/// for the next interim.
 #[tauri::command]
-async fn is_listening(state: State<'_, AppState>) -> Result<bool, String> {
-    Ok(state.is_listening.load(Ordering::SeqCst))
+async fn push_audio_chunk(audio_base64: String, app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+    if !state.is_listening.load(Ordering::SeqCst) {
+        return Err("Not listening".to_string());
+    }
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(&audio_base64)
+        .map_err(|e| format!("Failed to decode audio: {}", e))?;
+    let chunk = audio::pcm16_from_le_bytes(&bytes);
+
+    let interim = {
+        let mut push_to_talk = state.push_to_talk.lock().map_err(|e| e.to_string())?;
+        let accumulator = push_to_talk
+            .get_or_insert_with(|| audio::AudioAccumulator::new(audio::ASR_SAMPLE_RATE, None));
+        let due = accumulator.push(&chunk) && !state.interim_running.swap(true, Ordering::SeqCst);
+        due.then(|| (accumulator.flush(), accumulator.sample_rate()))
+    };
+
+    if let Some((samples, sample_rate)) = interim {
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            let state = app.state::<AppState>();
+            match transcribe_pcm(&app, &state, &samples, sample_rate).await {
+                Ok(result) => {
+                    let _ = app.emit("push-to-talk-interim", &result);
+                }
+                Err(e) => {
+                    log::warn!("Failed to transcribe interim push-to-talk audio: {}", e);
+                    let _ = app.emit("push-to-talk-interim-error", &e);
+                }
+            }
+            state.interim_running.store(false, Ordering::SeqCst);
+        });
+    }
+    Ok(())
 }
 
-/// Get current service status
-#[tauri::command]
-async fn get_service_status(state: State<'_, AppState>) -> Result<ServiceStatus, String> {
-    let mode = match state.service_mode {
-        ServiceMode::Remote => "remote",
-        ServiceMode::Embedded => "embedded",
+/// Transcribe mono PCM with the first backend of the service chain that succeeds
+async fn transcribe_pcm(app: &AppHandle, st: &AppState, samples: &[i16], sample_rate: u32) -> Result<TranscriptionResult, String> {
+    let wav = audio::encode_wav(samples, sample_rate, 1);
+    let chain = st.chain_for("asr").await;
+    with_fallback("asr", &chain, |mode| transcribe_on(app, st, mode, &wav), |event| {
+        report_fallback(app, st, event);
+    }).await
+}
+
+/// Transcribe the audio pushed while listening, emitting it as `transcription`
+///
+/// Returns None when no audio was pushed.
+async fn finish_push_to_talk(app: &AppHandle, st: &AppState) -> Result<Option<TranscriptionResult>, String> {
+    let accumulator = st.push_to_talk.lock().map_err(|e| e.to_string())?.take();
+    let Some(accumulator) = accumulator.filter(|a| a.duration_secs() > 0.0) else {
+        return Ok(None);
     };
 
-    Ok(ServiceStatus {
-        mode: mode.to_string(),
-        asr_ready: true, // Remote services are always "ready" (connectivity checked on use)
-        llm_ready: true,
-        tts_ready: true,
-        #[cfg(feature = "embedded-services")]
-        models_ready: state.model_manager.are_models_ready(),
-    })
+    let duration = accumulator.duration_secs();
+    let sample_rate = accumulator.sample_rate();
+    let samples = accumulator.finish();
+    let result = transcribe_pcm(app, st, &samples, sample_rate).await?;
+    log::info!("Push-to-talk transcription ({:.1}s): {}", duration, result.text);
+
+    apply_detected_language(app, st, result.language.as_deref()).await;
+    *st.last_transcription.lock().await = Some(result.clone());
+    let _ = app.emit("transcription", &result.text);
+    Ok(Some(result))
 }
 
-/// Process audio data (received from frontend as base64 WAV)
-#[tauri::command]
-async fn process_audio(
-    audio_base64: String,
-    app: AppHandle,
-    state: State<'_, AppState>
-) -> Result<ProcessingResult, String> {
-    // Decode base64 audio
-    let audio_data = base64::engine::general_purpose::STANDARD
-        .decode(&audio_base64)
-        .map_err(|e| format!("Failed to decode audio: {}", e))?;
-    
-    // Emit processing status
-    let _ = app.emit("processing-status", "Transcribing...");
-    
-    // Step 1: ASR - Transcribe speech to text
-    let asr = state.asr.lock().await;
-    let transcription = asr.transcribe_wav(&audio_data).await?;
-    drop(asr);
-    
-    let transcribed_text = transcription.text.clone();
-    log::info!("Transcription: {}", transcribed_text);
-    
-    let _ = app.emit("transcription", &transcribed_text);
-    
-    if transcribed_text.trim().is_empty() {
-        return Ok(ProcessingResult {
-            status: "empty".to_string(),
-            transcription: Some(transcribed_text),
-            response: None,
-            audio_ready: false,
+/// Start listening if idle, stop if listening (bound to the global hotkey)
+#[cfg(desktop)]
+fn toggle_listening(app: &AppHandle) {
+    let state = app.state::<AppState>();
+    if state.is_listening.load(Ordering::SeqCst) {
+        state.stop_listening(app);
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            let state = app.state::<AppState>();
+            if let Err(e) = finish_push_to_talk(&app, &state).await {
+                log::warn!("Failed to transcribe push-to-talk audio: {}", e);
+            }
         });
+    } else {
+        state.start_listening(app);
     }
-    
-    // Step 2: LLM - Generate response
-    let _ = app.emit("processing-status", "Thinking...");
-    
-    let mut llm = state.llm.lock().await;
-    let llm_response = llm.chat(&transcribed_text).await?;
-    drop(llm);
-    
-    let response_text = llm_response.text.clone();
-    log::info!("LLM Response: {}", response_text);
-    
-    let _ = app.emit("llm-response", &response_text);
-    
-    // Step 3: TTS - Synthesize speech
-    let _ = app.emit("processing-status", "Generating audio...");
-    
-    let tts = state.tts.lock().await;
-    let tts_result = tts.synthesize(&response_text).await?;
-    drop(tts);
-    
-    // Emit TTS audio data as base64
-    let audio_base64 = base64::engine::general_purpose::STANDARD.encode(&tts_result.audio_data);
-    let _ = app.emit("tts-audio", audio_base64);
-    
-    Ok(ProcessingResult {
-        status: "complete".to_string(),
-        transcription: Some(transcribed_text),
-        response: Some(response_text),
-        audio_ready: true,
-    })
 }
 
-/// Configure services
+/// Get the global hotkey that toggles listening (None if none is registered)
+#[cfg(desktop)]
 #[tauri::command]
-async fn configure_services(config: ServiceConfig, state: State<'_, AppState>) -> Result<(), String> {
-    // Update ASR config
-    let mut asr = state.asr.lock().await;
-    asr.set_server_url(config.asr_url);
-    drop(asr);
+async fn get_hotkey(state: State<'_, AppState>) -> Result<Option<String>, String> {
+    Ok(state.hotkey.lock().map_err(|e| e.to_string())?.clone())
+}
 
-    // Update LLM config
-    let mut llm = state.llm.lock().await;
-    llm.set_server_url(config.llm_url);
-    drop(llm);
+/// Change the global hotkey that toggles listening and persist it
+///
+/// `accelerator` uses Tauri's format, e.g. "CommandOrControl+Shift+Space".
+/// If another application holds it, the previous hotkey stays registered.
+#[cfg(desktop)]
+#[tauri::command]
+async fn set_hotkey(accelerator: String, app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+    let previous = state.hotkey.lock().map_err(|e| e.to_string())?.clone();
+    if previous.as_deref() == Some(accelerator.as_str()) {
+        return Ok(());
+    }
 
-    // Update TTS config
-    let mut tts = state.tts.lock().await;
-    tts.set_server_url(config.tts_url);
-    drop(tts);
+    hotkey::register(&app, &accelerator)?;
+    if let Some(previous) = previous {
+        if let Err(e) = hotkey::unregister(&app, &previous) {
+            log::warn!("{}", e);
+        }
+    }
+    *state.hotkey.lock().map_err(|e| e.to_string())? = Some(accelerator.clone());
+    hotkey::save(&accelerator)?;
+    log::info!("Listening hotkey set to {}", accelerator);
+    Ok(())
+}
 
-    log::info!("Services configured");
+#[cfg(not(desktop))]
+#[tauri::command]
+async fn get_hotkey() -> Result<Option<String>, String> {
+    Err("Global hotkeys are only available on desktop".to_string())
+}
+
+#[cfg(not(desktop))]
+#[tauri::command]
+async fn set_hotkey(_accelerator: String) -> Result<(), String> {
+    Err("Global hotkeys are only available on desktop".to_string())
+}
+
+/// Mic level smoothing coefficients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MicLevelSmoothing {
+    pub attack: f32,
+    pub decay: f32,
+}
+
+/// Start emitting `mic-level` events from the default microphone
+#[tauri::command]
+async fn start_mic_monitor(app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+    let mut monitor = state.mic_monitor.lock().map_err(|e| e.to_string())?;
+    if monitor.is_some() {
+        return Ok(());
+    }
+    *monitor = Some(mic::start_level_monitor(app, state.mic_smoothing.clone())?);
+    log::info!("Mic level monitor started");
     Ok(())
 }
 
-/// Clear LLM conversation history
+/// Stop emitting `mic-level` events
 #[tauri::command]
-async fn clear_conversation(state: State<'_, AppState>) -> Result<(), String> {
-    let mut llm = state.llm.lock().await;
-    llm.clear_history();
-    log::info!("Conversation cleared");
+async fn stop_mic_monitor(state: State<'_, AppState>) -> Result<(), String> {
+    let mut monitor = state.mic_monitor.lock().map_err(|e| e.to_string())?;
+    if monitor.take().is_some() {
+        log::info!("Mic level monitor stopped");
+    }
     Ok(())
 }
 
-/// Send a text message to the LLM (without speech)
+/// Raw microphone recording
+#[derive(Debug, Clone, Serialize)]
+pub struct RawAudio {
+    /// Little-endian 16-bit PCM samples, base64 encoded
+    pub base64_pcm: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+/// Record from the default microphone for a fixed duration and return raw PCM
+///
+/// No transcription is performed; useful for custom DSP or for checking
+/// capture quality independently of ASR.
 #[tauri::command]
-async fn send_text_message(
-    message: String,
-    app: AppHandle,
-    state: State<'_, AppState>
-) -> Result<ProcessingResult, String> {
-    // LLM - Generate response
-    let _ = app.emit("processing-status", "Thinking...");
-    
-    let mut llm = state.llm.lock().await;
-    let llm_response = llm.chat(&message).await?;
-    drop(llm);
+async fn capture_raw_audio(duration_ms: u64) -> Result<RawAudio, String> {
+    if duration_ms == 0 || duration_ms > mic::MAX_RECORD_MS {
+        return Err(format!("Duration must be between 1 and {} ms", mic::MAX_RECORD_MS));
+    }
 
-    let response_text = llm_response.text.clone();
-    let _ = app.emit("llm-response", &response_text);
+    let (samples, sample_rate) = mic::record_for(Duration::from_millis(duration_ms)).await?;
 
-    // TTS - Synthesize speech
-    let _ = app.emit("processing-status", "Generating audio...");
-    
-    let tts = state.tts.lock().await;
-    let tts_result = tts.synthesize(&response_text).await?;
-    drop(tts);
+    let pcm: Vec<u8> = samples
+        .iter()
+        .flat_map(|&s| audio::f32_to_i16(s).to_le_bytes())
+        .collect();
 
-    // Emit TTS audio data as base64
-    let audio_base64 = base64::engine::general_purpose::STANDARD.encode(&tts_result.audio_data);
-    let _ = app.emit("tts-audio", audio_base64);
+    log::info!("Captured {} samples at {} Hz", samples.len(), sample_rate);
 
-    Ok(ProcessingResult {
-        status: "complete".to_string(),
-        transcription: Some(message),
-        response: Some(response_text),
-        audio_ready: true,
+    Ok(RawAudio {
+        base64_pcm: base64::engine::general_purpose::STANDARD.encode(&pcm),
+        sample_rate,
+        channels: 1,
     })
 }
 
-// ============================================================================
-// Model Management Commands (for embedded/Android mode)
-// ============================================================================
+/// Get the mic level smoothing coefficients
+#[tauri::command]
+async fn get_mic_level_smoothing(state: State<'_, AppState>) -> Result<MicLevelSmoothing, String> {
+    let smoother = state.mic_smoothing.lock().map_err(|e| e.to_string())?;
+    Ok(MicLevelSmoothing { attack: smoother.attack, decay: smoother.decay })
+}
 
-/// Get information about required models
-#[cfg(feature = "embedded-services")]
+/// Set the mic level smoothing coefficients (0 < value <= 1, 1 = no smoothing)
 #[tauri::command]
-async fn get_model_info(state: State<'_, AppState>) -> Result<Vec<ModelInfo>, String> {
-    Ok(state.model_manager.get_model_info())
+async fn set_mic_level_smoothing(smoothing: MicLevelSmoothing, state: State<'_, AppState>) -> Result<(), String> {
+    for (name, value) in [("attack", smoothing.attack), ("decay", smoothing.decay)] {
+        if !(value > 0.0 && value <= 1.0) {
+            return Err(format!("{} must be in (0, 1], got {}", name, value));
+        }
+    }
+    let mut smoother = state.mic_smoothing.lock().map_err(|e| e.to_string())?;
+    smoother.attack = smoothing.attack;
+    smoother.decay = smoothing.decay;
+    Ok(())
 }
 
-/// Check if all models are ready
-#[cfg(feature = "embedded-services")]
+/// Get the automatic gain control settings for streamed microphone audio
+#[tauri::command]
+async fn get_agc(state: State<'_, AppState>) -> Result<audio::AgcConfig, String> {
+    Ok(*state.agc.lock().map_err(|e| e.to_string())?)
+}
+
+/// Configure automatic gain control for streaming sessions
+///
+/// `target_rms` is a linear level in (0, 1]; `adaptation_rate` is the fastest
+/// gain increase in dB per second. Takes effect from the next session.
+#[tauri::command]
+async fn set_agc(agc: audio::AgcConfig, state: State<'_, AppState>) -> Result<(), String> {
+    if !(agc.target_rms > 0.0 && agc.target_rms <= 1.0) {
+        return Err(format!("target_rms must be in (0, 1], got {}", agc.target_rms));
+    }
+    if !(agc.adaptation_rate > 0.0) {
+        return Err(format!("adaptation_rate must be positive, got {}", agc.adaptation_rate));
+    }
+    *state.agc.lock().map_err(|e| e.to_string())? = agc;
+    log::info!(
+        "AGC {} (target {:.1} dBFS, {} dB/s)",
+        if agc.enabled { "enabled" } else { "disabled" },
+        audio::level_to_db(agc.target_rms),
+        agc.adaptation_rate
+    );
+    Ok(())
+}
+
+/// Detect the primary spoken language over recent turns
+///
+/// Returns None until at least one transcription has reported a language.
+#[tauri::command]
+async fn detect_conversation_language(state: State<'_, AppState>) -> Result<Option<LanguageDetection>, String> {
+    Ok(state.dominant_language().await)
+}
+
+/// Export the last transcription's segments as an SRT or VTT subtitle file
+///
+/// Returns the path written.
 #[tauri::command]
-async fn are_models_ready(state: State<'_, AppState>) -> Result<bool, String> {
-    Ok(state.model_manager.are_models_ready())
+async fn export_captions(format: String, path: String, state: State<'_, AppState>) -> Result<String, String> {
+    let format = captions::CaptionFormat::parse(&format)?;
+
+    let segments = match state.last_transcription.lock().await.as_ref() {
+        Some(transcription) if !transcription.segments.is_empty() => transcription.segments.clone(),
+        Some(_) => return Err("Last transcription has no timed segments".to_string()),
+        None => return Err("No transcription to export".to_string()),
+    };
+
+    let mut path = std::path::PathBuf::from(path);
+    if path.extension().is_none() {
+        path.set_extension(format.extension());
+    }
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create caption directory: {}", e))?;
+    }
+
+    std::fs::write(&path, captions::format_captions(&segments, format))
+        .map_err(|e| format!("Failed to write captions to {:?}: {}", path, e))?;
+
+    log::info!("Exported {} caption segments to {:?}", segments.len(), path);
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Default number of files transcribed at once by `transcribe_files`
+const DEFAULT_BATCH_CONCURRENCY: usize = 2;
+
+/// Result for one file of a batch transcription
+#[derive(Debug, Clone, Serialize)]
+pub struct FileTranscription {
+    pub path: String,
+    pub text: Option<String>,
+    pub language: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Payload of the `transcribe-file-progress` event
+#[derive(Debug, Clone, Serialize)]
+pub struct FileProgress {
+    /// Position of the file in the input list
+    pub index: usize,
+    pub total: usize,
+    pub path: String,
+    /// "started", "done" or "failed"
+    pub status: String,
+}
+
+/// Transcribe a list of audio files (WAV, MP3, FLAC or Ogg), at most `max_concurrent` (default 2) at a time
+///
+/// Emits `transcribe-file-progress` as each file starts and finishes. Results
+/// are returned in input order; a failed file does not stop the batch.
+#[tauri::command]
+async fn transcribe_files(
+    paths: Vec<String>,
+    max_concurrent: Option<usize>,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<Vec<FileTranscription>, String> {
+    let max_concurrent = max_concurrent.unwrap_or(DEFAULT_BATCH_CONCURRENCY).max(1);
+    let results = transcribe_paths(&app, &state, paths, max_concurrent).await;
+    Ok(results
+        .into_iter()
+        .map(|(path, outcome)| match outcome {
+            Ok(transcription) => FileTranscription {
+                path,
+                text: Some(transcription.text),
+                language: transcription.language,
+                error: None,
+            },
+            Err(e) => FileTranscription { path, text: None, language: None, error: Some(e) },
+        })
+        .collect())
+}
+
+/// Files transcribed at once by `transcribe_batch`
+const TRANSCRIBE_BATCH_CONCURRENCY: usize = 3;
+
+/// Full transcription of one file of `transcribe_batch`
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchTranscription {
+    /// File name without its directory
+    pub file: String,
+    pub path: String,
+    pub result: Option<TranscriptionResult>,
+    pub error: Option<String>,
+}
+
+/// Transcribe several audio files, three at a time, keeping segments and word timings
+///
+/// Like `transcribe_files`, emits `transcribe-file-progress` per file and
+/// reports failures per file instead of aborting the batch.
+#[tauri::command]
+async fn transcribe_batch(
+    files: Vec<String>,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<Vec<BatchTranscription>, String> {
+    let results = transcribe_paths(&app, &state, files, TRANSCRIBE_BATCH_CONCURRENCY).await;
+    Ok(results
+        .into_iter()
+        .map(|(path, outcome)| {
+            let file = std::path::Path::new(&path)
+                .file_name()
+                .map_or_else(|| path.clone(), |name| name.to_string_lossy().into_owned());
+            let (result, error) = match outcome {
+                Ok(transcription) => (Some(transcription), None),
+                Err(e) => (None, Some(e)),
+            };
+            BatchTranscription { file, path, result, error }
+        })
+        .collect())
+}
+
+/// A file path and the outcome of transcribing it
+type FileOutcome = (String, Result<TranscriptionResult, String>);
+
+/// Transcribe files at most `max_concurrent` at a time, returning each path
+/// with its outcome in input order
+async fn transcribe_paths(
+    app: &AppHandle,
+    state: &AppState,
+    paths: Vec<String>,
+    max_concurrent: usize,
+) -> Vec<FileOutcome> {
+    use futures::stream::{self, StreamExt};
+
+    let total = paths.len();
+
+    let progress = move |index: usize, path: &str, status: &str| {
+        let _ = app.emit("transcribe-file-progress", FileProgress {
+            index,
+            total,
+            path: path.to_string(),
+            status: status.to_string(),
+        });
+    };
+
+    let mut results: Vec<(usize, FileOutcome)> = stream::iter(paths.into_iter().enumerate())
+        .map(|(index, path)| async move {
+            progress(index, &path, "started");
+            let outcome = match tokio::fs::read(&path).await {
+                Ok(data) => transcribe_file(app, state, data).await,
+                Err(e) => Err(format!("Failed to read {}: {}", path, e)),
+            };
+            match &outcome {
+                Ok(_) => progress(index, &path, "done"),
+                Err(e) => {
+                    log::warn!("Batch transcription of {} failed: {}", path, e);
+                    progress(index, &path, "failed");
+                }
+            }
+            (index, (path, outcome))
+        })
+        .buffer_unordered(max_concurrent)
+        .collect()
+        .await;
+
+    results.sort_by_key(|(index, _)| *index);
+    log::info!("Transcribed {} files ({} at a time)", total, max_concurrent);
+    results.into_iter().map(|(_, outcome)| outcome).collect()
+}
+
+/// Decode a file in any supported format and transcribe it with the first
+/// backend of the service chain that succeeds
+async fn transcribe_file(app: &AppHandle, st: &AppState, data: Vec<u8>) -> Result<TranscriptionResult, String> {
+    let wav = tokio::task::spawn_blocking(move || audio::detect_and_convert_to_wav(&data).map(|wav| wav.into_owned()))
+        .await
+        .map_err(|e| format!("Failed to decode audio: {}", e))??;
+    let chain = st.chain_for("asr").await;
+    with_fallback("asr", &chain, |mode| transcribe_on(app, st, mode, &wav), |event| {
+        report_fallback(app, st, event);
+    }).await
+}
+
+/// Check if currently listening
+#[tauri::command]
+async fn is_listening(state: State<'_, AppState>) -> Result<bool, String> {
+    Ok(state.is_listening.load(Ordering::SeqCst))
+}
+
+/// Memory held by one loaded model
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelMemory {
+    pub name: String,
+    pub bytes: u64,
+}
+
+/// Process and system memory, for warning before loading more models
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryStats {
+    /// Resident set size of this process in bytes
+    pub process_rss: u64,
+    /// Memory available to new allocations system-wide, in bytes
+    pub available_system: u64,
+    /// Total memory held by loaded embedded models, in bytes
+    pub model_loaded_bytes: u64,
+    /// Per-model breakdown of `model_loaded_bytes`
+    pub models: Vec<ModelMemory>,
+}
+
+/// Report process and system memory usage
+#[tauri::command]
+async fn get_memory_stats(state: State<'_, AppState>) -> Result<MemoryStats, String> {
+    use sysinfo::{ProcessesToUpdate, System};
+
+    let pid = sysinfo::get_current_pid()
+        .map_err(|e| format!("Failed to get process id: {}", e))?;
+    let mut system = System::new();
+    system.refresh_memory();
+    system.refresh_processes(ProcessesToUpdate::Some(&[pid]), false);
+    let process_rss = system.process(pid).map(|p| p.memory()).unwrap_or(0);
+
+    #[allow(unused_mut)]
+    let mut models = Vec::new();
+    #[cfg(feature = "embedded-services")]
+    {
+        let llm_bytes = state.embedded_llm.lock().await.loaded_bytes();
+        if llm_bytes > 0 {
+            models.push(ModelMemory { name: "llm".to_string(), bytes: llm_bytes });
+        }
+    }
+    #[cfg(not(feature = "embedded-services"))]
+    let _ = &state;
+
+    Ok(MemoryStats {
+        process_rss,
+        available_system: system.available_memory(),
+        model_loaded_bytes: models.iter().map(|m| m.bytes).sum(),
+        models,
+    })
+}
+
+/// Get current service status
+///
+/// A service is ready when any backend of the fallback chain can serve it:
+/// remote servers answer their health check, and on-device models are loaded
+/// or downloaded.
+#[tauri::command]
+async fn get_service_status(state: State<'_, AppState>) -> Result<ServiceStatus, String> {
+    let mode = *state.service_mode.lock().await;
+    let chain = state.service_chain().await;
+
+    let ((asr_ready, asr_latency_ms), (llm_ready, llm_latency_ms), (tts_ready, tts_latency_ms)) =
+        if chain.contains(&ServiceMode::Remote) {
+            // Built under the locks, sent without them
+            let asr = state.asr.lock().await.ping();
+            let llm = state.llm.lock().await.ping();
+            let tts = state.tts.lock().await.ping();
+            tokio::join!(timed_ping("ASR", asr), timed_ping("LLM", llm), timed_ping("TTS", tts))
+        } else {
+            ((false, None), (false, None), (false, None))
+        };
+    let embedded = chain.contains(&ServiceMode::Embedded);
+
+    Ok(ServiceStatus {
+        mode: mode.as_str().to_string(),
+        asr_ready: asr_ready || (embedded && embedded_ready(&state, "asr")),
+        llm_ready: llm_ready || (embedded && embedded_ready(&state, "llm")),
+        tts_ready: tts_ready || (embedded && embedded_ready(&state, "tts")),
+        asr_latency_ms,
+        llm_latency_ms,
+        tts_latency_ms,
+        #[cfg(feature = "embedded-services")]
+        models_ready: state.model_manager.lock().await.are_models_ready(),
+    })
+}
+
+/// Switch between remote servers ("remote") and on-device models ("embedded")
+#[tauri::command]
+async fn set_service_mode(mode: String, state: State<'_, AppState>) -> Result<(), String> {
+    let mode = ServiceMode::parse(&mode)?;
+    *state.service_mode.lock().await = mode;
+    log::info!("Service mode set to {}", mode.as_str());
+    Ok(())
+}
+
+/// Backends tried in order for each service, e.g. ["embedded", "remote"]
+#[tauri::command]
+async fn get_fallback_chain(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    Ok(state.service_chain().await.iter().map(|m| m.as_str().to_string()).collect())
+}
+
+/// Set the order backends are tried in when one fails
+///
+/// The first entry becomes the service mode; the rest are tried in turn,
+/// emitting `fallback-used`, when it fails. A single entry disables fallback.
+#[tauri::command]
+async fn set_fallback_chain(chain: Vec<String>, state: State<'_, AppState>) -> Result<(), String> {
+    let chain = parse_chain(&chain)?;
+    *state.service_mode.lock().await = chain[0];
+    *state.fallback_modes.lock().await = chain[1..].to_vec();
+    let names: Vec<&str> = chain.iter().map(|m| m.as_str()).collect();
+    log::info!("Fallback chain set to {}", names.join(" -> "));
+    Ok(())
+}
+
+/// Whether the on-device backend can serve `service`: its model is loaded or
+/// downloaded
+///
+/// A model busy with another request counts as ready rather than waiting.
+#[cfg(feature = "embedded-services")]
+fn embedded_ready(state: &AppState, service: &str) -> bool {
+    if !backend_supports(ServiceMode::Embedded, service) {
+        return false;
+    }
+    match service {
+        "asr" => state.embedded_asr.try_lock().map_or(true, |asr| asr.is_ready() || asr.is_model_available()),
+        "llm" => state.embedded_llm.try_lock().map_or(true, |llm| llm.is_ready() || llm.is_model_available()),
+        _ => false,
+    }
+}
+
+#[cfg(not(feature = "embedded-services"))]
+fn embedded_ready(_state: &AppState, _service: &str) -> bool {
+    false
+}
+
+/// Run a health check, returning whether it passed and its round-trip time
+async fn timed_ping<Fut>(service: &str, ping: Fut) -> (bool, Option<u64>)
+where
+    Fut: Future<Output = Result<bool, String>>,
+{
+    let started = std::time::Instant::now();
+    match ping.await {
+        Ok(ready) => (ready, Some(started.elapsed().as_millis() as u64)),
+        Err(e) => {
+            log::warn!("{} health check failed: {}", service, e);
+            (false, None)
+        }
+    }
+}
+
+/// Run one pipeline stage and record it in the turn trace
+///
+/// Requests retry on their own, drawing on the shared retry budget; a stage
+/// that fails after the budget ran out emits `retry-budget-exhausted`.
+async fn run_stage<T, F, Fut>(app: &AppHandle, state: &AppState, stage: &str, op: F) -> Result<T, String>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, String>>,
+{
+    let started = std::time::Instant::now();
+    let result = op().await.map_err(|e| {
+        if state.retry_budget.is_exhausted() {
+            let _ = app.emit("retry-budget-exhausted", stage);
+            format!("{} (retry budget exhausted)", e)
+        } else {
+            e
+        }
+    });
+    trace::record_stage(stage, started, result.is_ok());
+    result
+}
+
+/// Whether `mode` can run `service` at all ("llm-stateless" is chat without
+/// history)
+///
+/// On-device synthesis and stateless chat are not implemented, so trying them
+/// would fail, and fall back, on every call.
+fn backend_supports(mode: ServiceMode, service: &str) -> bool {
+    match mode {
+        ServiceMode::Remote => true,
+        ServiceMode::Embedded => cfg!(feature = "embedded-services") && !matches!(service, "tts" | "llm-stateless"),
+    }
+}
+
+/// Emit `fallback-used`, once per turn for each service and failed backend
+fn report_fallback(app: &AppHandle, st: &AppState, event: &FallbackEvent) {
+    let key = format!("{}:{}", event.service, event.from);
+    let first = st.reported_fallbacks.lock().map_or(true, |mut reported| reported.insert(key));
+    if first {
+        let _ = app.emit("fallback-used", event);
+    }
+}
+
+/// Transcribe WAV audio on one backend
+///
+/// The embedded model is loaded on first use.
+async fn transcribe_on(app: &AppHandle, st: &AppState, mode: ServiceMode, audio: &[u8]) -> Result<TranscriptionResult, String> {
+    match mode {
+        ServiceMode::Remote => run_stage(app, st, "ASR", move || async move {
+            // A clone, so concurrent transcriptions do not wait on the lock
+            let asr = st.asr.lock().await.clone();
+            asr.transcribe_wav(audio).await
+        }).await,
+        #[cfg(feature = "embedded-services")]
+        ServiceMode::Embedded => {
+            let mut asr = st.embedded_asr.lock().await;
+            if !asr.is_ready() {
+                asr.initialize().await?;
+            }
+            asr.transcribe_wav_with_progress(audio, |progress| {
+                let _ = app.emit("transcription-progress", progress);
+            }).await.map(TranscriptionResult::from)
+        }
+        #[cfg(not(feature = "embedded-services"))]
+        ServiceMode::Embedded => Err("Embedded ASR not available in remote mode".to_string()),
+    }
+}
+
+/// Get a complete LLM reply from one backend
+///
+/// The on-device model keeps its own history, so stateless chat is only
+/// available remotely.
+async fn chat_on(app: &AppHandle, st: &AppState, mode: ServiceMode, message: &str, stateless: bool) -> Result<LLMResponse, String> {
+    match mode {
+        ServiceMode::Remote => run_stage(app, st, "LLM", move || async move {
+            let mut llm = st.llm.lock().await;
+            if stateless {
+                llm.chat_stateless(message).await
+            } else {
+                llm.chat(message).await
+            }
+        }).await,
+        #[cfg(feature = "embedded-services")]
+        ServiceMode::Embedded => {
+            if stateless {
+                return Err("Stateless chat is not supported by the embedded LLM".to_string());
+            }
+            let mut llm = st.embedded_llm.lock().await;
+            if !llm.is_ready() {
+                llm.initialize().await?;
+            }
+            llm.chat(message).await.map(LLMResponse::from)
+        }
+        #[cfg(not(feature = "embedded-services"))]
+        ServiceMode::Embedded => Err("Embedded LLM not available in remote mode".to_string()),
+    }
+}
+
+/// Synthesize one piece of speech on one backend
+async fn synthesize_on(app: &AppHandle, st: &AppState, mode: ServiceMode, text: &str) -> Result<TTSResult, String> {
+    match mode {
+        ServiceMode::Remote => run_stage(app, st, "TTS", move || async move {
+            st.tts.lock().await.synthesize(text).await
+        }).await,
+        #[cfg(feature = "embedded-services")]
+        ServiceMode::Embedded => {
+            let mut tts = st.embedded_tts.lock().await;
+            if !tts.is_ready() {
+                tts.initialize().await?;
+            }
+            tts.synthesize(text).await.map(TTSResult::from)
+        }
+        #[cfg(not(feature = "embedded-services"))]
+        ServiceMode::Embedded => Err("Embedded TTS not available in remote mode".to_string()),
+    }
+}
+
+/// Primary subtag of a language code ("en-US" -> "en")
+fn primary_language(code: &str) -> String {
+    code.split(['-', '_']).next().unwrap_or(code).trim().to_lowercase()
+}
+
+/// Report the language of a transcription and speak the reply in it
+///
+/// Emits `detected-language`, records it for `detect_conversation_language`,
+/// sets the embedded TTS language and switches the VoxCPM voice when the
+/// current voice is known to speak another language. The voice list is
+/// fetched in the background on first use, so the first turn keeps its voice.
+async fn apply_detected_language(app: &AppHandle, st: &AppState, language: Option<&str>) {
+    st.record_language(language).await;
+    let Some(language) = language.map(str::trim).filter(|l| !l.is_empty() && *l != "auto") else {
+        return;
+    };
+    let language = primary_language(language);
+    let _ = app.emit("detected-language", &language);
+
+    #[cfg(feature = "embedded-services")]
+    st.embedded_tts.lock().await.set_language(language.clone());
+
+    let voices = {
+        let mut cache = st.tts_voices.lock().await;
+        if cache.is_none() {
+            // An empty list until the fetch finishes; a failed fetch is not retried
+            *cache = Some(Vec::new());
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                let state = app.state::<AppState>();
+                let result = state.tts.lock().await.list_voices().await;
+                let voices = result.unwrap_or_else(|e| {
+                    log::warn!("Failed to list TTS voices for language matching: {}", e);
+                    Vec::new()
+                });
+                *state.tts_voices.lock().await = Some(voices);
+            });
+        }
+        cache.clone().unwrap_or_default()
+    };
+    let speaks = |voice: &VoiceInfo| voice.language.as_deref().is_some_and(|l| primary_language(l) == language);
+
+    let mut tts = st.tts.lock().await;
+    let current = voices.iter().find(|v| v.id == tts.config().voice);
+    if current.map_or(true, |v| v.language.is_none() || speaks(v)) {
+        return;
+    }
+    if let Some(voice) = voices.iter().find(|v| speaks(v)) {
+        log::info!("Switching TTS voice to {} for detected language {}", voice.id, language);
+        tts.set_voice(voice.id.clone());
+    }
+}
+
+/// Process audio data (received from frontend as base64 WAV, MP3, FLAC or Ogg)
+#[tauri::command]
+async fn process_audio(
+    audio_base64: String,
+    stateless: Option<bool>,
+    app: AppHandle,
+    state: State<'_, AppState>
+) -> Result<ProcessingResult, String> {
+    let _guard = ProcessingGuard::try_acquire(&state.is_processing)
+        .ok_or_else(|| "Already processing".to_string())?;
+
+    // Decode base64 audio
+    let audio_data = base64::engine::general_purpose::STANDARD
+        .decode(&audio_base64)
+        .map_err(|e| format!("Failed to decode audio: {}", e))?;
+    let audio_data = audio::detect_and_convert_to_wav(&audio_data)?;
+
+    process_wav(&audio_data, stateless, &app, state.inner()).await
+}
+
+/// Start recording from the default microphone until `stop_recording`
+#[tauri::command]
+async fn start_recording(app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+    if state.recording.lock().map_err(|e| e.to_string())?.is_some() {
+        return Err("Already recording".to_string());
+    }
+    // Opening the device waits for the stream to start, so it runs off the
+    // async runtime and without the lock held
+    let started = tokio::task::spawn_blocking(mic::start_recording)
+        .await
+        .map_err(|e| format!("Failed to start recording: {}", e))??;
+    {
+        let mut recording = state.recording.lock().map_err(|e| e.to_string())?;
+        if recording.is_some() {
+            // Another call got there first; dropping `started` stops its capture
+            return Err("Already recording".to_string());
+        }
+        *recording = Some(started);
+    }
+    let _ = app.emit("recording-started", ());
+    log::info!("Recording started");
+    Ok(())
+}
+
+/// Stop the microphone recording and run it through the voice pipeline
+#[tauri::command]
+async fn stop_recording(
+    stateless: Option<bool>,
+    app: AppHandle,
+    state: State<'_, AppState>
+) -> Result<ProcessingResult, String> {
+    let recording = state.recording.lock().map_err(|e| e.to_string())?
+        .take()
+        .ok_or_else(|| "Not recording".to_string())?;
+    let (samples, sample_rate) = recording.finish()?;
+    let _ = app.emit("recording-stopped", ());
+    log::info!("Recording stopped: {} samples at {} Hz", samples.len(), sample_rate);
+
+    if samples.is_empty() {
+        return Err("No audio was recorded".to_string());
+    }
+
+    let _guard = ProcessingGuard::try_acquire(&state.is_processing)
+        .ok_or_else(|| "Already processing".to_string())?;
+
+    let wav = audio::encode_wav(&samples, sample_rate, 1);
+    process_wav(&wav, stateless, &app, state.inner()).await
+}
+
+/// Run a turn on WAV audio, recording a trace if tracing is enabled
+async fn process_wav(
+    audio_data: &[u8],
+    stateless: Option<bool>,
+    app: &AppHandle,
+    st: &AppState,
+) -> Result<ProcessingResult, String> {
+    if !st.trace_turn.load(Ordering::SeqCst) {
+        return run_audio_turn(audio_data, stateless, app, st).await;
+    }
+
+    let started = std::time::Instant::now();
+    let started_at = conversations::now_secs();
+    let (result, stages, exchanges) = trace::capture(run_audio_turn(audio_data, stateless, app, st)).await;
+    let ok = result.as_ref().ok();
+    *st.last_turn_trace.lock().await = Some(TurnTrace {
+        turn_id: st.turn_id.load(Ordering::SeqCst),
+        started_at,
+        audio_hash: trace::hash_bytes(audio_data),
+        audio_bytes: audio_data.len(),
+        transcription: ok.and_then(|r| r.transcription.clone()),
+        response: ok.and_then(|r| r.response.clone()),
+        status: ok.map(|r| r.status.clone()),
+        error: result.as_ref().err().cloned(),
+        stages,
+        exchanges,
+        total_ms: started.elapsed().as_millis() as u64,
+    });
+    result
+}
+
+/// Run one turn of the voice pipeline on decoded WAV audio
+async fn run_audio_turn(
+    audio: &[u8],
+    stateless: Option<bool>,
+    app: &AppHandle,
+    st: &AppState,
+) -> Result<ProcessingResult, String> {
+    let turn_id = st.begin_turn(app);
+    let started = std::time::Instant::now();
+    let mut timing = PipelineTiming::default();
+
+    // Emit processing status
+    let _ = app.emit("processing-status", "Transcribing...");
+    
+    // Step 1: ASR - Transcribe speech to text
+    let chain = st.chain_for("asr").await;
+    let transcription = with_fallback("asr", &chain, |mode| transcribe_on(app, st, mode, audio), |event| {
+        report_fallback(app, st, event);
+    }).await?;
+    timing.asr_ms = Some(elapsed_ms(started));
+    
+    let transcribed_text = transcription.text.clone();
+    log::info!("Transcription: {}", transcribed_text);
+    apply_detected_language(app, st, transcription.language.as_deref()).await;
+    *st.last_transcription.lock().await = Some(transcription.clone());
+    
+    let _ = app.emit("transcription", &transcribed_text);
+    
+    if transcribed_text.trim().is_empty() {
+        return Ok(ProcessingResult {
+            status: "empty".to_string(),
+            transcription: Some(transcribed_text),
+            response: None,
+            audio_ready: false,
+            timing: timing.finish(app, started),
+        });
+    }
+
+    // In dictation mode, append to the buffer and wait for finalize_dictation
+    let mut dictation = st.dictation.lock().await;
+    if dictation.append_mode {
+        if !dictation.buffer.is_empty() {
+            dictation.buffer.push(' ');
+        }
+        dictation.buffer.push_str(transcribed_text.trim());
+        let _ = app.emit("dictation-updated", &dictation.buffer);
+
+        return Ok(ProcessingResult {
+            status: "dictation".to_string(),
+            transcription: Some(dictation.buffer.clone()),
+            response: None,
+            audio_ready: false,
+            timing: timing.finish(app, started),
+        });
+    }
+    drop(dictation);
+    
+    // Steps 2 and 3: LLM and TTS. Stateless replies cannot be streamed, so
+    // they are generated in full before being spoken.
+    let stateless = stateless.unwrap_or(false);
+    if stateless {
+        reply_sequential(app, st, turn_id, transcribed_text, timing, started).await
+    } else {
+        reply_pipelined(app, st, turn_id, transcribed_text, timing, started).await
+    }
+}
+
+/// Generate a stateless reply in full, then synthesize it
+async fn reply_sequential(
+    app: &AppHandle,
+    st: &AppState,
+    turn_id: u64,
+    transcribed_text: String,
+    mut timing: PipelineTiming,
+    started: std::time::Instant,
+) -> Result<ProcessingResult, String> {
+    let _ = app.emit("processing-status", "Thinking...");
+    
+    let user_text = transcribed_text.as_str();
+    let llm_started = std::time::Instant::now();
+    let llm_done = AtomicBool::new(false);
+    let llm_stage = async {
+        let chain = st.chain_for("llm-stateless").await;
+        let result = with_fallback("llm", &chain, |mode| chat_on(app, st, mode, user_text, true), |event| {
+            report_fallback(app, st, event);
+        }).await;
+        llm_done.store(true, Ordering::SeqCst);
+        result
+    };
+    let (llm_response, _) = tokio::join!(llm_stage, speak_filler(app, st, turn_id, &llm_done));
+    let llm_response = llm_response?;
+    timing.llm_ms = Some(elapsed_ms(llm_started));
+    
+    let response_text = llm_response.text.clone();
+    log::info!("LLM Response: {}", response_text);
+    
+    let _ = app.emit("llm-response", &response_text);
+    let truncated = report_truncation(app, &llm_response);
+    st.check_context_usage(app).await;
+    
+    if !st.is_current_turn(turn_id) {
+        return Ok(interrupted_result(transcribed_text, response_text));
+    }
+    let _ = app.emit("processing-status", "Generating audio...");
+
+    let tts_started = std::time::Instant::now();
+    let spoken = synthesize_response(app, st, turn_id, &response_text).await?;
+    timing.tts_ms = Some(elapsed_ms(tts_started));
+
+    // Drop audio that finished after the user interrupted
+    if !st.is_current_turn(turn_id) {
+        return Ok(interrupted_result(transcribed_text, response_text));
+    }
+
+    if llm_response.awaiting_input {
+        await_user_input(app, st, turn_id);
+    }
+    
+    Ok(ProcessingResult {
+        status: if truncated { "truncated" } else { "complete" }.to_string(),
+        transcription: Some(transcribed_text),
+        response: Some(response_text),
+        audio_ready: spoken > 0,
+        timing: timing.finish(app, started),
+    })
+}
+
+/// Stream the reply into speech synthesis, so the first sentences are spoken
+/// while the rest is still being generated
+///
+/// The LLM stage sends each completed sentence over a channel to the TTS
+/// stage. `tts_ms` is measured from the first sentence to the last audio.
+async fn reply_pipelined(
+    app: &AppHandle,
+    st: &AppState,
+    turn_id: u64,
+    transcribed_text: String,
+    mut timing: PipelineTiming,
+    started: std::time::Instant,
+) -> Result<ProcessingResult, String> {
+    let _ = app.emit("processing-status", "Thinking...");
+
+    let language = st.dominant_language().await.map_or_else(|| "auto".to_string(), |d| d.language);
+    let (sentence_tx, sentence_rx) = tokio::sync::mpsc::unbounded_channel();
+    let speech_started = AtomicBool::new(false);
+    let llm_started = std::time::Instant::now();
+    let llm_stage = async {
+        let result = reply_sentences(app, st, &transcribed_text, &language, sentence_tx, &speech_started).await;
+        speech_started.store(true, Ordering::SeqCst);
+        (result, elapsed_ms(llm_started))
+    };
+    let ((llm_response, llm_ms), spoken, _) = tokio::join!(
+        llm_stage,
+        speak_sentences(app, st, turn_id, sentence_rx),
+        speak_filler(app, st, turn_id, &speech_started),
+    );
+    let (llm_response, truncated) = llm_response?;
+    timing.llm_ms = Some(llm_ms);
+    let (spoken, tts_ms) = spoken?;
+    timing.tts_ms = tts_ms;
+
+    let response_text = llm_response.text.clone();
+    let cancelled = llm_response.finish_reason.as_deref() == Some(FINISH_CANCELLED);
+    if cancelled || !st.is_current_turn(turn_id) {
+        return Ok(interrupted_result(transcribed_text, response_text));
+    }
+
+    if llm_response.awaiting_input {
+        await_user_input(app, st, turn_id);
+    }
+
+    Ok(ProcessingResult {
+        status: if truncated { "truncated" } else { "complete" }.to_string(),
+        transcription: Some(transcribed_text),
+        response: Some(response_text),
+        audio_ready: spoken > 0,
+        timing: timing.finish(app, started),
+    })
+}
+
+/// LLM stage of a pipelined turn: stream the reply, sending each completed
+/// sentence to `sentences`
+///
+/// Emits `llm-chunk` as text arrives and `llm-response` when the reply is
+/// complete; `speech_started` is set once the first sentence is sent. Returns
+/// the reply and whether it was truncated. A cancelled reply's unfinished
+/// last sentence is not sent.
+async fn reply_sentences(
+    app: &AppHandle,
+    st: &AppState,
+    message: &str,
+    language: &str,
+    sentences: tokio::sync::mpsc::UnboundedSender<String>,
+    speech_started: &AtomicBool,
+) -> Result<(LLMResponse, bool), String> {
+    let max_chars = st.tts.lock().await.config().max_chunk_chars;
+    let splitter = std::sync::Mutex::new(text::SentenceStream::new(language, max_chars));
+    let send = |completed: Vec<String>| {
+        for sentence in completed {
+            // The follow-up marker is stripped from the reply text, so it is never spoken either
+            let sentence = match sentence.strip_suffix(FOLLOW_UP_MARKER) {
+                Some(spoken) => spoken.trim_end().to_string(),
+                None => sentence,
+            };
+            if sentence.is_empty() {
+                continue;
+            }
+            speech_started.store(true, Ordering::SeqCst);
+            let _ = sentences.send(sentence);
+        }
+    };
+
+    st.cancel_generation.store(false, Ordering::SeqCst);
+    let llm_response = stream_llm_reply(app, st, message, |chunk| {
+        let _ = app.emit("llm-chunk", chunk);
+        if let Ok(mut splitter) = splitter.lock() {
+            send(splitter.push(chunk));
+        }
+    }).await?;
+
+    if llm_response.finish_reason.as_deref() != Some(FINISH_CANCELLED) {
+        if let Ok(splitter) = splitter.into_inner() {
+            send(splitter.finish());
+        }
+    }
+
+    log::info!("LLM Response: {}", llm_response.text);
+    let _ = app.emit("llm-response", &llm_response.text);
+    let truncated = report_truncation(app, &llm_response);
+    st.check_context_usage(app).await;
+    Ok((llm_response, truncated))
+}
+
+/// TTS stage of a pipelined turn: synthesize sentences as they arrive
+///
+/// Audio is emitted like in `synthesize_response`, each piece as soon as it
+/// is synthesized. `tts-audio-chunk` events carry a `total` of 0 while the
+/// reply is still being generated; once it is complete, an empty piece with
+/// `is_last` set ends the stream. Returns the number of pieces emitted and
+/// the milliseconds from the first sentence to the last audio (None when
+/// nothing was spoken).
+async fn speak_sentences(
+    app: &AppHandle,
+    st: &AppState,
+    turn_id: u64,
+    mut sentences: tokio::sync::mpsc::UnboundedReceiver<String>,
+) -> Result<(usize, Option<u64>), String> {
+    let streaming = st.tts.lock().await.config().stream_sentences;
+    let chain = st.chain_for("tts").await;
+    st.last_tts_audio.lock().await.clear();
+
+    let mut tts_started = None;
+    let mut sample_rate = 0;
+    let mut index = 0;
+    while let Some(sentence) = sentences.recv().await {
+        let started = *tts_started.get_or_insert_with(|| {
+            let _ = app.emit("processing-status", "Generating audio...");
+            std::time::Instant::now()
+        });
+        if !st.is_current_turn(turn_id) {
+            return Ok((index, Some(elapsed_ms(started))));
+        }
+        let tts_result = with_fallback("tts", &chain, |mode| synthesize_on(app, st, mode, &sentence), |event| {
+            report_fallback(app, st, event);
+        }).await?;
+
+        // Drop audio that finished after the user interrupted
+        if !st.is_current_turn(turn_id) {
+            return Ok((index, Some(elapsed_ms(started))));
+        }
+        st.is_speaking.store(true, Ordering::SeqCst);
+        if streaming {
+            emit_tts_chunk(app, turn_id, index, 0, &tts_result);
+        } else {
+            emit_tts_audio(app, &tts_result);
+        }
+        sample_rate = tts_result.sample_rate;
+        st.last_tts_audio.lock().await.push(tts_result);
+        index += 1;
+    }
+
+    let Some(started) = tts_started else {
+        return Ok((0, None));
+    };
+    if streaming && index > 0 {
+        let end = TTSResult { audio_data: Vec::new(), sample_rate, duration: 0.0, alignment: None };
+        emit_tts_chunk(app, turn_id, index, index + 1, &end);
+    }
+    Ok((index, Some(elapsed_ms(started))))
+}
+
+/// Emit `response-truncated` with the reply text if it was cut off by
+/// `max_tokens`. Returns whether it was.
+fn report_truncation(app: &AppHandle, llm_response: &LLMResponse) -> bool {
+    if !llm_response.is_truncated() {
+        return false;
+    }
+    log::warn!("LLM reply was cut off by max_tokens ({} chars)", llm_response.text.len());
+    let _ = app.emit("response-truncated", &llm_response.text);
+    true
+}
+
+/// Synthesize a response piece by piece, emitting each as soon as it is ready
+///
+/// Pieces go out as `tts-audio` (or `tts-audio-chunk` with sentence streaming
+/// enabled) in order, so playback starts before the whole reply is
+/// synthesized. Stops early if the turn is interrupted. Returns the number of
+/// pieces emitted.
+async fn synthesize_response(app: &AppHandle, st: &AppState, turn_id: u64, text: &str) -> Result<usize, String> {
+    let language = st.dominant_language().await.map_or_else(|| "auto".to_string(), |d| d.language);
+    let (chunks, streaming) = {
+        let tts = st.tts.lock().await;
+        (tts.speech_chunks(text, &language), tts.config().stream_sentences)
+    };
+
+    st.last_tts_audio.lock().await.clear();
+    let chain = st.chain_for("tts").await;
+    let total = chunks.len();
+    for (index, chunk) in chunks.iter().enumerate() {
+        if !st.is_current_turn(turn_id) {
+            return Ok(index);
+        }
+        let tts_result = with_fallback("tts", &chain, |mode| synthesize_on(app, st, mode, chunk), |event| {
+            report_fallback(app, st, event);
+        }).await?;
+
+        // Drop audio that finished after the user interrupted
+        if !st.is_current_turn(turn_id) {
+            return Ok(index);
+        }
+        st.is_speaking.store(true, Ordering::SeqCst);
+        if streaming {
+            emit_tts_chunk(app, turn_id, index, total, &tts_result);
+        } else {
+            emit_tts_audio(app, &tts_result);
+        }
+        st.last_tts_audio.lock().await.push(tts_result);
+    }
+    Ok(total)
+}
+
+/// Save the audio of the most recent spoken response as a WAV file
+#[tauri::command]
+async fn save_last_audio(path: String, state: State<'_, AppState>) -> Result<(), String> {
+    let pieces = state.last_tts_audio.lock().await.clone();
+    if pieces.is_empty() {
+        return Err("No spoken response to save yet".to_string());
+    }
+    let channels = state.tts.lock().await.config().channels;
+    let wav = audio::join_wav_pieces(
+        pieces.iter().map(|piece| (piece.audio_data.as_slice(), piece.sample_rate)),
+        channels,
+    )?;
+
+    tokio::fs::write(&path, &wav)
+        .await
+        .map_err(|e| format!("Failed to save audio: {}", e))?;
+    log::info!("Saved last response audio to {} ({} bytes)", path, wav.len());
+    Ok(())
+}
+
+/// Record every request, response and stage timing of each `process_audio` turn
+#[tauri::command]
+async fn set_turn_tracing(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    state.trace_turn.store(enabled, Ordering::SeqCst);
+    if !enabled {
+        *state.last_turn_trace.lock().await = None;
+    }
+    log::info!("Turn tracing {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+/// Trace of the most recent traced turn, with secrets and audio payloads redacted
+#[tauri::command]
+async fn get_last_turn_trace(state: State<'_, AppState>) -> Result<Option<TurnTrace>, String> {
+    Ok(state.last_turn_trace.lock().await.clone())
+}
+
+/// Speak the thinking filler while the LLM generates, if one is configured
+///
+/// The filler is emitted as `filler-audio` ahead of the response's `tts-audio`,
+/// so it always finishes first. It is dropped if the LLM has already answered
+/// by the time it is synthesized.
+async fn speak_filler(app: &AppHandle, state: &AppState, turn_id: u64, llm_done: &AtomicBool) {
+    let Some(filler) = state.thinking_filler.lock().await.clone() else {
+        return;
+    };
+
+    let cached = state.filler_audio.lock().await.clone();
+    let audio = match cached {
+        Some(audio) => audio,
+        None => {
+            let chain = state.chain_for("tts").await;
+            let result = with_fallback("tts", &chain, |mode| synthesize_on(app, state, mode, &filler), |event| {
+                report_fallback(app, state, event);
+            }).await;
+            match result {
+                Ok(audio) => {
+                    *state.filler_audio.lock().await = Some(audio.clone());
+                    audio
+                }
+                Err(e) => {
+                    log::warn!("Failed to synthesize thinking filler: {}", e);
+                    return;
+                }
+            }
+        }
+    };
+
+    if llm_done.load(Ordering::SeqCst) || !state.is_current_turn(turn_id) {
+        return;
+    }
+    let audio_base64 = base64::engine::general_purpose::STANDARD.encode(&audio.audio_data);
+    let _ = app.emit("filler-audio", audio_base64);
+}
+
+/// Longest utterance accepted by `process_streaming_conversation`
+const MAX_STREAMING_UTTERANCE_MS: u64 = 60_000;
+
+/// Transcribe the microphone live over the ASR WebSocket
+///
+/// Emits `partial-transcription` with each update as it arrives, so text can
+/// be shown word by word. The microphone closes after `max_duration_ms`
+/// (default 60s) or as soon as the server reports the final transcript.
+#[tauri::command]
+async fn transcribe_live(
+    max_duration_ms: Option<u64>,
+    app: AppHandle,
+    state: State<'_, AppState>
+) -> Result<TranscriptionResult, String> {
+    let max_duration = Duration::from_millis(
+        max_duration_ms.unwrap_or(MAX_STREAMING_UTTERANCE_MS).min(mic::MAX_RECORD_MS)
+    );
+
+    let (audio_tx, mut audio_rx) = tokio::sync::mpsc::unbounded_channel::<Vec<i16>>();
+    let (capture, sample_rate) = mic::spawn_capture(move |samples| {
+        let _ = audio_tx.send(samples.iter().map(|&s| audio::f32_to_i16(s)).collect());
+    })?;
+    // Dropping the capture closes the channel, which ends the audio stream
+    let stop = tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(max_duration).await;
+        drop(capture);
+    });
+
+    // A clone so the session does not hold the shared ASR lock
+    let asr = state.asr.lock().await.clone();
+    let audio = futures::stream::poll_fn(move |cx| audio_rx.poll_recv(cx));
+    let result = asr.transcribe_stream(audio, sample_rate, |update| {
+        let _ = app.emit("partial-transcription", &update);
+    }).await;
+    stop.abort();
+
+    let result = result?;
+    log::info!("Live transcription: {}", result.text);
+    *state.last_transcription.lock().await = Some(result.clone());
+    Ok(result)
+}
+
+/// Time allowed for the final transcript after the mic is closed
+const FINAL_TRANSCRIPT_GRACE: Duration = Duration::from_secs(5);
+
+/// A speculative LLM request for an interim transcript
+type Speculation = (String, tauri::async_runtime::JoinHandle<Result<LLMResponse, String>>);
+
+/// Run one voice turn with streaming ASR and speculative LLM execution
+///
+/// Microphone audio is streamed to the ASR server over a WebSocket. Each time
+/// the interim transcript stabilizes, an LLM request for it is started in the
+/// background (replacing any earlier one). When ASR signals end of utterance,
+/// the speculative response is used if its text matches the final transcript;
+/// otherwise the final text is sent normally. Emits `interim-transcription`
+/// for every update and `speculation-started` for each speculative request.
+#[tauri::command]
+async fn process_streaming_conversation(
+    max_duration_ms: Option<u64>,
+    app: AppHandle,
+    state: State<'_, AppState>
+) -> Result<ProcessingResult, String> {
+    let _guard = ProcessingGuard::try_acquire(&state.is_processing)
+        .ok_or_else(|| "Already processing".to_string())?;
+
+    let st = state.inner();
+    let turn_id = st.begin_turn(&app);
+    let max_duration = Duration::from_millis(
+        max_duration_ms.unwrap_or(MAX_STREAMING_UTTERANCE_MS).min(mic::MAX_RECORD_MS)
+    );
+    // Speculative requests go to the remote LLM, so only when it is tried first
+    let chain = st.chain_for("llm").await;
+    let speculate = chain.first() == Some(&ServiceMode::Remote);
+
+    // Mic samples are forwarded to the ASR socket through a channel; dropping
+    // the capture handle closes the channel, which ends the ASR stream
+    let (audio_tx, mut audio_rx) = tokio::sync::mpsc::unbounded_channel::<Vec<f32>>();
+    let (capture, sample_rate) = mic::spawn_capture(move |samples| {
+        let _ = audio_tx.send(samples.to_vec());
+    })?;
+    let (mut sender, mut receiver) = st.asr.lock().await.open_stream(sample_rate).await?;
+    let agc_config = *st.agc.lock().map_err(|e| e.to_string())?;
+    let mut agc = agc_config.enabled.then(|| audio::AutomaticGainControl::new(agc_config, sample_rate));
+    let forward = tauri::async_runtime::spawn(async move {
+        while let Some(mut chunk) = audio_rx.recv().await {
+            if let Some(agc) = agc.as_mut() {
+                agc.process(&mut chunk);
+            }
+            let chunk: Vec<i16> = chunk.iter().map(|&s| audio::f32_to_i16(s)).collect();
+            if let Err(e) = sender.send_samples(&chunk).await {
+                log::warn!("{}", e);
+                return;
+            }
+        }
+        let _ = sender.finish().await;
+    });
+
+    let _ = app.emit("processing-status", "Listening...");
+
+    let mut capture = Some(capture);
+    let mut speculation: Option<Speculation> = None;
+    let mut last_interim = String::new();
+    let mut deadline = tokio::time::Instant::now() + max_duration;
+
+    let final_text: Result<String, String> = loop {
+        let update = match tokio::time::timeout_at(deadline, receiver.next()).await {
+            Ok(Some(Ok(update))) => update,
+            Ok(Some(Err(e))) => break Err(e),
+            Ok(None) => break Ok(last_interim.clone()),
+            Err(_) if capture.is_some() => {
+                // Out of time: stop the mic and wait briefly for the final transcript
+                log::info!("Streaming utterance reached {:?}, closing microphone", max_duration);
+                capture = None;
+                deadline = tokio::time::Instant::now() + FINAL_TRANSCRIPT_GRACE;
+                continue;
+            }
+            Err(_) => break Ok(last_interim.clone()),
+        };
+
+        let _ = app.emit("interim-transcription", &update);
+        if update.is_final {
+            break Ok(update.text);
+        }
+
+        // Text repeated across two updates is treated as stable too
+        let stable = update.stable || update.text == last_interim;
+        last_interim = update.text;
+        let already_speculating = speculation.as_ref().is_some_and(|(text, _)| *text == last_interim);
+        if speculate && stable && !last_interim.is_empty() && !already_speculating {
+            if let Some((_, handle)) = speculation.take() {
+                handle.abort();
+            }
+            let llm = st.llm.lock().await.clone();
+            let text = last_interim.clone();
+            let _ = app.emit("speculation-started", &text);
+            speculation = Some((
+                last_interim.clone(),
+                tauri::async_runtime::spawn(async move { llm.chat_speculative(&text).await }),
+            ));
+        }
+    };
+
+    drop(capture);
+    forward.abort();
+
+    let final_text = match final_text {
+        Ok(text) => text.trim().to_string(),
+        Err(e) => {
+            if let Some((_, handle)) = speculation {
+                handle.abort();
+            }
+            return Err(e);
+        }
+    };
+    log::info!("Transcription: {}", final_text);
+    let _ = app.emit("transcription", &final_text);
+
+    if final_text.is_empty() {
+        if let Some((_, handle)) = speculation {
+            handle.abort();
+        }
+        return Ok(ProcessingResult {
+            status: "empty".to_string(),
+            transcription: Some(final_text),
+            response: None,
+            audio_ready: false,
+            timing: PipelineTiming::default(),
+        });
+    }
+
+    // Reuse the speculative response if it was for exactly this text
+    let speculative = match speculation {
+        Some((text, handle)) if text == final_text => match handle.await {
+            Ok(Ok(response)) => Some(response),
+            Ok(Err(e)) => {
+                log::warn!("Speculative LLM request failed: {}", e);
+                None
+            }
+            Err(e) => {
+                log::warn!("Speculative LLM task failed: {}", e);
+                None
+            }
+        },
+        Some((_, handle)) => {
+            handle.abort();
+            None
+        }
+        None => None,
+    };
+
+    let llm_response = match speculative {
+        Some(response) => {
+            log::info!("Using speculative LLM response");
+            st.llm.lock().await.commit_turn(&final_text, &response);
+            response
+        }
+        None => {
+            let _ = app.emit("processing-status", "Thinking...");
+            with_fallback("llm", &chain, |mode| chat_on(&app, st, mode, &final_text, false), |event| {
+                report_fallback(&app, st, event);
+            }).await?
+        }
+    };
+
+    let response_text = llm_response.text.clone();
+    log::info!("LLM Response: {}", response_text);
+    let _ = app.emit("llm-response", &response_text);
+
+    speak_response(&app, st, turn_id, final_text, response_text, &llm_response).await
+}
+
+/// Resume listening after the assistant asked a follow-up question
+///
+/// `awaiting-user-input` carries the turn id so the frontend can start
+/// recording once that turn's audio has finished playing.
+fn await_user_input(app: &AppHandle, state: &AppState, turn_id: u64) {
+    state.start_listening(app);
+    let _ = app.emit("awaiting-user-input", turn_id);
+}
+
+/// Emit synthesized audio as base64, followed by its alignment for lip-sync
+fn emit_tts_audio(app: &AppHandle, tts_result: &TTSResult) {
+    let audio_base64 = base64::engine::general_purpose::STANDARD.encode(&tts_result.audio_data);
+    let _ = app.emit("tts-audio", audio_base64);
+    if let Some(alignment) = &tts_result.alignment {
+        let _ = app.emit("tts-alignment", alignment);
+    }
+}
+
+/// One sentence of a streamed response, sent as `tts-audio-chunk`
+#[derive(Debug, Clone, Serialize)]
+pub struct TtsAudioChunk {
+    pub turn_id: u64,
+    pub index: usize,
+    /// Number of pieces, or 0 while a pipelined reply is still being generated
+    /// (which then ends with an empty piece)
+    pub total: usize,
+    pub is_last: bool,
+    pub audio_base64: String,
+    pub sample_rate: u32,
+    pub duration: f64,
+    /// Timings relative to the start of this chunk
+    pub alignment: Option<Vec<AlignmentToken>>,
+}
+
+/// Emit one sentence of synthesized audio for gapless playback by the frontend
+fn emit_tts_chunk(app: &AppHandle, turn_id: u64, index: usize, total: usize, chunk: &TTSResult) {
+    let _ = app.emit("tts-audio-chunk", TtsAudioChunk {
+        turn_id,
+        index,
+        total,
+        is_last: index + 1 == total,
+        audio_base64: base64::engine::general_purpose::STANDARD.encode(&chunk.audio_data),
+        sample_rate: chunk.sample_rate,
+        duration: chunk.duration,
+        alignment: chunk.alignment.clone(),
+    });
+}
+
+/// Decode audio the same way the pipeline does and report what was detected
+///
+/// The audio is re-encoded as 16-bit PCM WAV so users can play it back and
+/// confirm it sounds right before blaming ASR.
+#[tauri::command]
+async fn test_audio_pipeline(base64_in: String) -> Result<AudioDiagnostics, String> {
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(&base64_in)
+        .map_err(|e| format!("Failed to decode audio: {}", e))?;
+    let wav = audio::parse_wav(&data)?;
+
+    let duration = wav.duration();
+    let peak = audio::peak_level(&wav.samples);
+    let clipping = audio::clipping_ratio(&wav.samples);
+
+    let mut warnings = Vec::new();
+    if wav.info.sample_rate != 16000 {
+        warnings.push(format!(
+            "Sample rate is {} Hz; speech recognition expects 16000 Hz and the audio will be resampled",
+            wav.info.sample_rate
+        ));
+    }
+    if wav.info.channels > 1 {
+        warnings.push(format!("Audio has {} channels; it will be downmixed to mono", wav.info.channels));
+    }
+    if wav.info.bits_per_sample != 16 {
+        warnings.push(format!("Audio is {}-bit; it was converted to 16-bit PCM", wav.info.bits_per_sample));
+    }
+    if clipping > CLIPPING_THRESHOLD {
+        warnings.push(format!("{:.2}% of samples are clipped; lower the input gain", clipping * 100.0));
+    }
+    if peak < 0.01 {
+        warnings.push("Audio is nearly silent; check the microphone".to_string());
+    }
+    if duration < 0.3 {
+        warnings.push(format!("Audio is very short ({:.2}s)", duration));
+    }
+
+    let reencoded = audio::encode_wav(&wav.samples, wav.info.sample_rate, wav.info.channels);
+
+    Ok(AudioDiagnostics {
+        decoded_rate: wav.info.sample_rate,
+        channels: wav.info.channels,
+        bits_per_sample: wav.info.bits_per_sample,
+        duration,
+        peak_level: peak,
+        clipping_ratio: clipping,
+        reencoded_base64: base64::engine::general_purpose::STANDARD.encode(&reencoded),
+        warnings,
+    })
+}
+
+/// Validate a recording and report whether it is likely silent or clipping
+///
+/// Cheap enough to run before every `process_audio` call. Accepts the same
+/// formats; MP3, FLAC and Ogg are decoded first (on a blocking thread) and
+/// described as the 16-bit PCM they decode to.
+#[tauri::command]
+async fn inspect_audio(audio_base64: String) -> Result<AudioInfo, String> {
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(&audio_base64)
+        .map_err(|e| format!("Failed to decode audio: {}", e))?;
+
+    tokio::task::spawn_blocking(move || {
+        let wav = audio::detect_and_convert_to_wav(&data)?;
+        let wav = audio::parse_wav(&wav)?;
+
+        let speech = audio::speech_ratio(&wav.samples, wav.info.sample_rate, wav.info.channels);
+        let format = match wav.info.audio_format {
+            3 => "float",
+            _ => "pcm",
+        };
+
+        Ok(AudioInfo {
+            format: format.to_string(),
+            sample_rate: wav.info.sample_rate,
+            channels: wav.info.channels,
+            bits_per_sample: wav.info.bits_per_sample,
+            duration: wav.duration(),
+            is_silent: speech < SILENT_SPEECH_RATIO,
+            is_clipping: audio::clipping_ratio(&wav.samples) > CLIPPING_THRESHOLD,
+            peak_db: audio::level_to_db(audio::peak_level(&wav.samples)),
+        })
+    })
+    .await
+    .map_err(|e| format!("Failed to decode audio: {}", e))?
+}
+
+/// Configure services
+#[tauri::command]
+async fn configure_services(config: ServiceConfig, state: State<'_, AppState>) -> Result<(), String> {
+    // Update ASR config
+    let mut asr = state.asr.lock().await;
+    asr.set_server_url(config.asr_url);
+    asr.set_ws_url(config.asr_ws_url);
+    if let Some(key) = config.asr_api_key {
+        asr.set_api_key(Some(key));
+    }
+    drop(asr);
+
+    // Update LLM config
+    let mut llm = state.llm.lock().await;
+    llm.set_server_url(config.llm_url);
+    if let Some(key) = config.llm_api_key {
+        llm.set_api_key(Some(key));
+    }
+    drop(llm);
+
+    // Update TTS config
+    let mut tts = state.tts.lock().await;
+    tts.set_server_url(config.tts_url);
+    if let Some(key) = config.tts_api_key {
+        tts.set_api_key(Some(key));
+    }
+    drop(tts);
+
+    log::info!("Services configured");
+    Ok(())
+}
+
+/// Revert one service ("asr", "llm" or "tts") to its default configuration
+///
+/// Emits `asr-config-changed`, `llm-config-changed` or `tts-config-changed`.
+/// LLM conversation history is kept. Server settings are only held in memory,
+/// so nothing is written for them; in embedded builds the embedded LLM
+/// settings, which are saved to disk, are reset and saved as well.
+#[tauri::command]
+async fn reset_service_config(service: String, app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+    match service.as_str() {
+        "asr" => {
+            let mut asr = state.asr.lock().await;
+            asr.reset_config();
+            let _ = app.emit("asr-config-changed", asr.config());
+        }
+        "llm" => {
+            let mut llm = state.llm.lock().await;
+            llm.reset_config();
+            #[cfg(feature = "embedded-services")]
+            {
+                let mut embedded = state.embedded_llm.lock().await;
+                embedded.reset_config().await?;
+                embedded.config().save()?;
+            }
+            let _ = app.emit("llm-config-changed", LlmLengthConfig {
+                max_tokens: llm.config().max_tokens,
+                response_length: llm.config().response_length,
+            });
+        }
+        "tts" => {
+            let mut tts = state.tts.lock().await;
+            tts.reset_config();
+            let _ = app.emit("tts-config-changed", tts.config());
+        }
+        other => return Err(format!("Unknown service: {} (expected \"asr\", \"llm\" or \"tts\")", other)),
+    }
+
+    log::info!("{} config reset to defaults (server settings are not saved)", service.to_uppercase());
+    Ok(())
+}
+
+/// Enable or disable punctuation restoration for raw ASR output
+///
+/// `method` is "heuristic" (default) or "llm"; the LLM method uses the
+/// currently configured LLM server.
+#[tauri::command]
+async fn set_punctuation_restoration(
+    enabled: bool,
+    method: Option<PunctuationMethod>,
+    state: State<'_, AppState>
+) -> Result<(), String> {
+    let method = method.unwrap_or_default();
+    if method == PunctuationMethod::Llm {
+        let llm = state.llm.lock().await;
+        let (url, model) = (llm.config().server_url.clone(), llm.config().model.clone());
+        drop(llm);
+        state.asr.lock().await.set_punctuation_llm(url, model);
+    }
+    state.asr.lock().await.set_punctuation(enabled, method);
+    log::info!("Punctuation restoration {} ({:?})", if enabled { "enabled" } else { "disabled" }, method);
+    Ok(())
+}
+
+/// Limit requests per minute to one service ("asr", "llm" or "tts")
+///
+/// A `requests_per_minute` of 0 removes the limit. Over the limit, requests
+/// wait for budget, or fail immediately with `fail_fast`. Emits
+/// `rate-limited-locally` whenever a request is throttled.
+#[tauri::command]
+async fn set_rate_limit(
+    service: String,
+    requests_per_minute: u32,
+    fail_fast: bool,
+    state: State<'_, AppState>
+) -> Result<(), String> {
+    match service.as_str() {
+        "asr" => state.asr.lock().await.set_rate_limit(requests_per_minute, fail_fast),
+        "llm" => state.llm.lock().await.set_rate_limit(requests_per_minute, fail_fast),
+        "tts" => state.tts.lock().await.set_rate_limit(requests_per_minute, fail_fast),
+        other => return Err(format!("Unknown service: {} (expected \"asr\", \"llm\" or \"tts\")", other)),
+    }
+    log::info!("{} rate limit set to {} requests/min (fail fast: {})", service.to_uppercase(), requests_per_minute, fail_fast);
+    Ok(())
+}
+
+/// Set the request timeout of one service ("asr", "llm" or "tts"); 0 disables it
+#[tauri::command]
+async fn set_service_timeout(service: String, timeout_secs: u64, state: State<'_, AppState>) -> Result<(), String> {
+    match service.as_str() {
+        "asr" => state.asr.lock().await.set_timeout(timeout_secs),
+        "llm" => state.llm.lock().await.set_timeout(timeout_secs),
+        "tts" => state.tts.lock().await.set_timeout(timeout_secs),
+        other => return Err(format!("Unknown service: {} (expected \"asr\", \"llm\" or \"tts\")", other)),
+    }
+    log::info!("{} timeout set to {}s", service.to_uppercase(), timeout_secs);
+    Ok(())
+}
+
+/// Answer every request with canned data instead of contacting the servers
+///
+/// ASR returns a fixed transcript, the LLM echoes the message and TTS returns
+/// half a second of silence, so `process_audio` runs end to end offline.
+#[tauri::command]
+async fn set_mock_mode(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    state.asr.lock().await.set_mock(enabled);
+    state.llm.lock().await.set_mock(enabled);
+    state.tts.lock().await.set_mock(enabled);
+    log::info!("Mock service mode {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+/// Set how many times requests to one service ("asr", "llm" or "tts") are
+/// retried on 5xx and connection errors
+#[tauri::command]
+async fn set_service_retries(service: String, max_retries: u32, state: State<'_, AppState>) -> Result<(), String> {
+    match service.as_str() {
+        "asr" => state.asr.lock().await.set_max_retries(max_retries),
+        "llm" => state.llm.lock().await.set_max_retries(max_retries),
+        "tts" => state.tts.lock().await.set_max_retries(max_retries),
+        other => return Err(format!("Unknown service: {} (expected \"asr\", \"llm\" or \"tts\")", other)),
+    }
+    log::info!("{} max retries set to {}", service.to_uppercase(), max_retries);
+    Ok(())
+}
+
+/// Set the API key sent as a bearer token to one service ("asr", "llm" or "tts")
+///
+/// None or an empty key stops sending the header. The key itself is never logged.
+#[tauri::command]
+async fn set_api_key(service: String, api_key: Option<String>, state: State<'_, AppState>) -> Result<(), String> {
+    let enabled = api_key.as_deref().is_some_and(|key| !key.trim().is_empty());
+    match service.as_str() {
+        "asr" => state.asr.lock().await.set_api_key(api_key),
+        "llm" => state.llm.lock().await.set_api_key(api_key),
+        "tts" => state.tts.lock().await.set_api_key(api_key),
+        other => return Err(format!("Unknown service: {} (expected \"asr\", \"llm\" or \"tts\")", other)),
+    }
+    log::info!("{} API key {}", service.to_uppercase(), if enabled { "set" } else { "cleared" });
+    Ok(())
+}
+
+/// Replace the additional headers sent with every request to one service
+#[tauri::command]
+async fn set_service_headers(
+    service: String,
+    headers: std::collections::HashMap<String, String>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let count = headers.len();
+    match service.as_str() {
+        "asr" => state.asr.lock().await.set_extra_headers(headers),
+        "llm" => state.llm.lock().await.set_extra_headers(headers),
+        "tts" => state.tts.lock().await.set_extra_headers(headers),
+        other => return Err(format!("Unknown service: {} (expected \"asr\", \"llm\" or \"tts\")", other)),
+    }
+    log::info!("{} extra headers set ({} headers)", service.to_uppercase(), count);
+    Ok(())
+}
+
+/// Normalize quiet recordings to a -3 dBFS peak before transcription
+#[tauri::command]
+async fn set_asr_normalize(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    state.asr.lock().await.set_normalize(enabled);
+    log::info!("ASR input normalization {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+/// Trim silence from recordings before transcription
+///
+/// `aggressiveness` ranges from 0 (keeps the most audio) to 3. With
+/// `skip_silent`, clips without speech return an empty transcription without
+/// contacting the server.
+#[tauri::command]
+async fn set_asr_vad(
+    enabled: bool,
+    aggressiveness: u8,
+    skip_silent: bool,
+    padding_ms: Option<u32>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    if aggressiveness > 3 {
+        return Err(format!("VAD aggressiveness must be 0-3, got {}", aggressiveness));
+    }
+    let mut asr = state.asr.lock().await;
+    let padding_ms = padding_ms.unwrap_or(asr.config().vad.padding_ms);
+    asr.set_vad(services::VadConfig { enabled, aggressiveness, padding_ms, skip_silent });
+    log::info!(
+        "ASR voice activity detection {} (aggressiveness {})",
+        if enabled { "enabled" } else { "disabled" },
+        aggressiveness
+    );
+    Ok(())
+}
+
+/// Gzip ASR uploads to save bandwidth (the server must accept gzip bodies)
+#[tauri::command]
+async fn set_asr_compression(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    state.asr.lock().await.set_compress_request(enabled);
+    log::info!("ASR request compression {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+/// Enable loudness normalization of synthesized speech
+///
+/// `target_rms` is a linear RMS level in (0.0, 1.0]; defaults to about -20 dBFS.
+#[tauri::command]
+async fn set_tts_normalization(
+    enabled: bool,
+    target_rms: Option<f32>,
+    state: State<'_, AppState>
+) -> Result<(), String> {
+    let target_rms = target_rms.unwrap_or(audio::DEFAULT_TARGET_RMS);
+    if !(target_rms > 0.0 && target_rms <= 1.0) {
+        return Err(format!("Target RMS must be in (0.0, 1.0], got {}", target_rms));
+    }
+    state.tts.lock().await.set_normalization(enabled, target_rms);
+    log::info!("TTS normalization {} (target RMS {})", if enabled { "enabled" } else { "disabled" }, target_rms);
+    Ok(())
+}
+
+/// Set a short phrase spoken while the LLM is thinking (None disables it)
+#[tauri::command]
+async fn set_thinking_filler(filler: Option<String>, state: State<'_, AppState>) -> Result<(), String> {
+    let filler = filler.map(|f| f.trim().to_string()).filter(|f| !f.is_empty());
+    log::info!("Thinking filler set to {:?}", filler);
+    *state.thinking_filler.lock().await = filler;
+    *state.filler_audio.lock().await = None;
+    Ok(())
+}
+
+/// Set whether synthesized speech is returned as mono (1) or stereo (2)
+#[tauri::command]
+async fn set_tts_channels(channels: u16, state: State<'_, AppState>) -> Result<(), String> {
+    if !(1..=2).contains(&channels) {
+        return Err(format!("Unsupported channel count: {} (expected 1 or 2)", channels));
+    }
+    state.tts.lock().await.set_channels(channels);
+    log::info!("TTS output channels set to {}", channels);
+    Ok(())
+}
+
+/// Request phoneme/word timings with synthesized speech
+///
+/// Timings are emitted as `tts-alignment` after each `tts-audio` event. Servers
+/// without alignment support get rough word timings derived from the text.
+#[tauri::command]
+async fn set_tts_alignment(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    state.tts.lock().await.set_return_alignment(enabled);
+    log::info!("TTS alignment {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+/// Emit response audio as `tts-audio-chunk` events
+///
+/// While enabled, each synthesized piece of a response is emitted with its
+/// index, total count and turn id instead of as a bare `tts-audio` event.
+#[tauri::command]
+async fn set_tts_streaming(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    state.tts.lock().await.set_stream_sentences(enabled);
+    log::info!("TTS sentence streaming {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+/// List the voices the TTS server offers
+#[tauri::command]
+async fn get_tts_voices(state: State<'_, AppState>) -> Result<Vec<VoiceInfo>, String> {
+    state.tts.lock().await.list_voices().await
+}
+
+/// Set the longest piece of a response synthesized in one request (0 = no limit)
+#[tauri::command]
+async fn set_tts_chunk_length(max_chars: usize, state: State<'_, AppState>) -> Result<(), String> {
+    state.tts.lock().await.set_max_chunk_chars(max_chars);
+    log::info!("TTS chunk length set to {} chars", max_chars);
+    Ok(())
+}
+
+/// Configure how `language: "auto"` is resolved
+///
+/// With `detect_once`, the language is detected from the first seconds of each
+/// recording and forced for the full transcription. With `sticky_language`,
+/// the first detection is reused for the rest of the session. Calling this
+/// clears any cached language.
+#[tauri::command]
+async fn set_language_detection(
+    detect_once: bool,
+    sticky_language: bool,
+    state: State<'_, AppState>
+) -> Result<(), String> {
+    state.asr.lock().await.set_language_detection(detect_once, sticky_language);
+    log::info!("Language detection: detect_once={}, sticky_language={}", detect_once, sticky_language);
+    Ok(())
+}
+
+/// Force the ASR language when auto-detection guesses wrong ("auto" restores detection)
+#[tauri::command]
+async fn set_asr_language(language: String, state: State<'_, AppState>) -> Result<(), String> {
+    let language = language.trim().to_lowercase();
+    if language.is_empty() {
+        return Err("ASR language cannot be empty".to_string());
+    }
+    state.asr.lock().await.set_language(language.clone());
+    #[cfg(feature = "embedded-services")]
+    state.embedded_asr.lock().await.set_language(language.clone());
+    log::info!("ASR language set to {}", language);
+    Ok(())
+}
+
+/// Set the User-Agent sent by all service clients (None restores the default)
+#[tauri::command]
+async fn set_user_agent(user_agent: Option<String>, state: State<'_, AppState>) -> Result<(), String> {
+    state.asr.lock().await.set_user_agent(user_agent.clone());
+    state.llm.lock().await.set_user_agent(user_agent.clone());
+    state.tts.lock().await.set_user_agent(user_agent.clone());
+    log::info!("User-Agent set to {}", user_agent.as_deref().unwrap_or(services::DEFAULT_USER_AGENT));
+    Ok(())
+}
+
+/// Get the pipeline retry budget in milliseconds
+#[tauri::command]
+async fn get_retry_budget(state: State<'_, AppState>) -> Result<u64, String> {
+    Ok(state.retry_budget.total_ms())
+}
+
+/// Set how long a single pipeline run may spend retrying before failing
+#[tauri::command]
+async fn set_retry_budget(total_retry_budget_ms: u64, state: State<'_, AppState>) -> Result<(), String> {
+    state.retry_budget.set_total_ms(total_retry_budget_ms);
+    log::info!("Retry budget set to {}ms", total_retry_budget_ms);
+    Ok(())
+}
+
+/// Payload of the `llm-config-changed` event
+#[derive(Debug, Clone, Serialize)]
+pub struct LlmLengthConfig {
+    pub max_tokens: u32,
+    pub response_length: Option<ResponseLength>,
+}
+
+/// Set a response length preset (Short/Medium/Long) on remote and embedded LLMs
+#[tauri::command]
+async fn set_response_length(
+    preset: ResponseLength,
+    app: AppHandle,
+    state: State<'_, AppState>
+) -> Result<LlmLengthConfig, String> {
+    let mut llm = state.llm.lock().await;
+    llm.set_response_length(preset);
+    let config = LlmLengthConfig {
+        max_tokens: llm.config().max_tokens,
+        response_length: llm.config().response_length,
+    };
+    drop(llm);
+
+    #[cfg(feature = "embedded-services")]
+    {
+        let mut embedded = state.embedded_llm.lock().await;
+        embedded.set_response_length(preset);
+        embedded.config().save()?;
+    }
+
+    let _ = app.emit("llm-config-changed", &config);
+    log::info!("Response length set to {:?} ({} tokens)", preset, config.max_tokens);
+    Ok(config)
+}
+
+/// Set the raw LLM token cap, overriding any length preset
+#[tauri::command]
+async fn set_max_tokens(
+    max_tokens: u32,
+    app: AppHandle,
+    state: State<'_, AppState>
+) -> Result<(), String> {
+    ChatOptions { max_tokens: Some(max_tokens), ..ChatOptions::default() }.validate()?;
+    state.llm.lock().await.set_max_tokens(max_tokens);
+
+    #[cfg(feature = "embedded-services")]
+    {
+        let mut embedded = state.embedded_llm.lock().await;
+        embedded.set_max_tokens(max_tokens);
+        embedded.config().save()?;
+    }
+
+    let _ = app.emit("llm-config-changed", LlmLengthConfig { max_tokens, response_length: None });
+    Ok(())
+}
+
+/// Set the model name sent to the LLM server, also in the active profile
+#[tauri::command]
+async fn set_llm_model(model: String, state: State<'_, AppState>) -> Result<(), String> {
+    let model = model.trim().to_string();
+    if model.is_empty() {
+        return Err("Model name cannot be empty".to_string());
+    }
+    log::info!("LLM model set to {}", model);
+    state.llm.lock().await.set_model(model);
+    Ok(())
+}
+
+/// Let the LLM continue replies cut off by `max_tokens` with follow-up requests
+#[tauri::command]
+async fn set_llm_auto_continue(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    state.llm.lock().await.set_auto_continue(enabled);
+    log::info!("LLM auto-continue {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+/// Update LLM sampling options; omitted values are left unchanged
+#[tauri::command]
+async fn set_llm_sampling(
+    top_p: Option<f32>,
+    presence_penalty: Option<f32>,
+    frequency_penalty: Option<f32>,
+    stop: Option<Vec<String>>,
+    state: State<'_, AppState>,
+) -> Result<QwenConfig, String> {
+    ChatOptions { top_p, ..ChatOptions::default() }.validate()?;
+    for penalty in [presence_penalty, frequency_penalty].into_iter().flatten() {
+        if !(-2.0..=2.0).contains(&penalty) {
+            return Err(format!("Penalty {} is outside -2.0 to 2.0", penalty));
+        }
+    }
+
+    let mut llm = state.llm.lock().await;
+    if let Some(top_p) = top_p {
+        llm.set_top_p(top_p);
+    }
+    let presence = presence_penalty.unwrap_or(llm.config().presence_penalty);
+    let frequency = frequency_penalty.unwrap_or(llm.config().frequency_penalty);
+    llm.set_penalties(presence, frequency);
+    if let Some(stop) = stop {
+        llm.set_stop(stop.into_iter().filter(|s| !s.is_empty()).collect());
+    }
+    log::info!("LLM sampling updated");
+    Ok(llm.config().clone())
+}
+
+/// Set the `response_format` sent with every LLM request (None = server default)
+#[tauri::command]
+async fn set_llm_response_format(response_format: Option<serde_json::Value>, state: State<'_, AppState>) -> Result<(), String> {
+    if response_format.as_ref().is_some_and(|format| !format.is_object()) {
+        return Err("response_format must be a JSON object".to_string());
+    }
+    state.llm.lock().await.set_response_format(response_format);
+    Ok(())
+}
+
+/// Send a message with a one-off system prompt, keeping the configured one
+#[tauri::command]
+async fn send_message_with_system(
+    message: String,
+    system_prompt: String,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<LLMResponse, String> {
+    let response = state.llm.lock().await.chat_with_system(&system_prompt, &message).await?;
+    let _ = app.emit("llm-response", &response.text);
+    Ok(response)
+}
+
+/// Send a message with one-off sampling (e.g. temperature 0 for a factual
+/// answer), keeping the configured values
+#[tauri::command]
+async fn send_message_with_options(
+    message: String,
+    options: ChatOptions,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<LLMResponse, String> {
+    options.validate()?;
+    let response = state.llm.lock().await.chat_with_options(&message, &options).await?;
+    let _ = app.emit("llm-response", &response.text);
+    Ok(response)
+}
+
+/// Send a message that must be answered with JSON, optionally matching `schema`
+#[tauri::command]
+async fn send_json_message(
+    message: String,
+    schema: Option<serde_json::Value>,
+    state: State<'_, AppState>,
+) -> Result<JsonResponse, String> {
+    state.llm.lock().await.chat_json(&message, schema.as_ref()).await
+}
+
+/// Registered LLM profiles and the active one
+#[derive(Debug, Clone, Serialize)]
+pub struct LlmProfiles {
+    pub names: Vec<String>,
+    pub active: Option<String>,
+}
+
+/// List the LLM profiles
+#[tauri::command]
+async fn list_llm_profiles(state: State<'_, AppState>) -> Result<LlmProfiles, String> {
+    let llm = state.llm.lock().await;
+    Ok(LlmProfiles {
+        names: llm.profile_names(),
+        active: llm.active_profile().map(str::to_string),
+    })
+}
+
+/// Save the current LLM configuration as a named profile
+#[tauri::command]
+async fn save_llm_profile(name: String, state: State<'_, AppState>) -> Result<(), String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Profile name cannot be empty".to_string());
+    }
+    state.llm.lock().await.save_profile(name);
+    log::info!("LLM profile saved: {}", name);
+    Ok(())
+}
+
+/// Switch to a named LLM profile, keeping the conversation history
+#[tauri::command]
+async fn switch_llm_profile(name: String, state: State<'_, AppState>) -> Result<QwenConfig, String> {
+    let mut llm = state.llm.lock().await;
+    llm.switch_profile(&name)?;
+    log::info!("Switched to LLM profile {} (model {})", name, llm.config().model);
+    Ok(llm.config().clone())
+}
+
+/// Delete a named LLM profile
+#[tauri::command]
+async fn delete_llm_profile(name: String, state: State<'_, AppState>) -> Result<(), String> {
+    state.llm.lock().await.remove_profile(&name)?;
+    log::info!("LLM profile deleted: {}", name);
+    Ok(())
+}
+
+/// Set the OpenAI-format tool definitions available to the LLM
+///
+/// Tools are only sent to the server while a tool dispatcher is registered.
+#[tauri::command]
+async fn set_llm_tools(
+    tools: Vec<serde_json::Value>,
+    tool_choice: Option<serde_json::Value>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut llm = state.llm.lock().await;
+    let count = tools.len();
+    llm.set_tools(tools);
+    llm.set_tool_choice(tool_choice);
+    log::info!("LLM tools updated ({} tools)", count);
+    Ok(())
+}
+
+/// Toggle describing tools and built-in capabilities in the system prompt
+#[tauri::command]
+async fn set_tool_prompt_injection(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    let mut llm = state.llm.lock().await;
+    llm.set_inject_tool_descriptions(enabled);
+    log::info!("Tool prompt injection {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+/// Let the assistant ask clarifying questions and resume listening for the answer
+#[tauri::command]
+async fn set_follow_up_questions(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    state.llm.lock().await.set_follow_up_questions(enabled);
+    log::info!("Follow-up questions {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+/// Limit how many history messages are sent to the LLM (0 = no limit)
+#[tauri::command]
+async fn set_max_history(max_messages: usize, state: State<'_, AppState>) -> Result<ContextUsage, String> {
+    let mut llm = state.llm.lock().await;
+    llm.set_max_history(max_messages);
+    let usage = llm.context_usage();
+    log::info!("History limited to {} messages ({} kept)", max_messages, usage.messages);
+    Ok(usage)
+}
+
+/// Estimated token usage of the conversation against the context window
+#[tauri::command]
+async fn get_context_usage(state: State<'_, AppState>) -> Result<ContextUsage, String> {
+    Ok(state.llm.lock().await.context_usage())
+}
+
+/// Conversation history, including each message's pin state
+#[tauri::command]
+async fn get_history(state: State<'_, AppState>) -> Result<Vec<ChatMessage>, String> {
+    get_conversation_history(state).await
+}
+
+/// Conversation turns for re-rendering the chat after a reload (same as `get_history`)
+#[tauri::command]
+async fn get_conversation_history(state: State<'_, AppState>) -> Result<Vec<ChatMessage>, String> {
+    Ok(state.llm.lock().await.get_conversation())
+}
+
+/// Pin a history message so it is never trimmed from the context
+#[tauri::command]
+async fn pin_message(index: usize, state: State<'_, AppState>) -> Result<(), String> {
+    state.llm.lock().await.set_pinned(index, true)?;
+    log::info!("Pinned message {}", index);
+    Ok(())
+}
+
+/// Allow a pinned history message to be trimmed again
+#[tauri::command]
+async fn unpin_message(index: usize, state: State<'_, AppState>) -> Result<(), String> {
+    state.llm.lock().await.set_pinned(index, false)?;
+    log::info!("Unpinned message {}", index);
+    Ok(())
+}
+
+/// Clear LLM conversation history
+#[tauri::command]
+async fn clear_conversation(state: State<'_, AppState>) -> Result<(), String> {
+    let mut llm = state.llm.lock().await;
+    llm.clear_history();
+    *state.current_conversation.lock().await = None;
+    log::info!("Conversation cleared");
+    Ok(())
+}
+
+/// Save the current conversation, overwriting it if it was saved or loaded before
+#[tauri::command]
+async fn save_conversation(
+    title: Option<String>,
+    state: State<'_, AppState>
+) -> Result<ConversationMeta, String> {
+    let messages = state.llm.lock().await.history().to_vec();
+    let mut current = state.current_conversation.lock().await;
+
+    let existing = current.as_deref().and_then(|id| conversations::load(id).ok());
+    let now = conversations::now_secs();
+    let conversation = match existing {
+        Some(mut conversation) => {
+            conversation.messages = messages;
+            conversation.updated_at = now;
+            if title.is_some() {
+                conversation.title = title;
+            }
+            conversation
+        }
+        None => Conversation {
+            id: conversations::new_id(),
+            title,
+            created_at: now,
+            updated_at: now,
+            messages,
+        },
+    };
+
+    conversations::save(&conversation)?;
+    *current = Some(conversation.id.clone());
+    log::info!("Conversation saved: {}", conversation.id);
+    Ok(conversation.meta())
+}
+
+/// List saved conversations, most recent first
+#[tauri::command]
+async fn list_conversations() -> Result<Vec<ConversationMeta>, String> {
+    conversations::list()
+}
+
+/// Load a saved conversation and make it the active history
+#[tauri::command]
+async fn load_conversation(id: String, state: State<'_, AppState>) -> Result<Conversation, String> {
+    let conversation = conversations::load(&id)?;
+    state.llm.lock().await.set_history(conversation.messages.clone());
+    *state.current_conversation.lock().await = Some(id);
+    log::info!("Conversation loaded: {}", conversation.id);
+    Ok(conversation)
+}
+
+/// Export the current conversation as Markdown, with an export timestamp
+/// in the title unless `include_timestamp` is false
+#[tauri::command]
+async fn export_conversation_markdown(include_timestamp: Option<bool>, state: State<'_, AppState>) -> Result<String, String> {
+    let timestamp = include_timestamp
+        .unwrap_or(true)
+        .then(|| conversations::format_utc(conversations::now_secs()));
+    Ok(state.llm.lock().await.export_conversation_markdown(timestamp.as_deref()))
+}
+
+/// Import a conversation from Markdown, save it and make it the active history
+///
+/// Text before the first message (or a "System" section) replaces the system prompt.
+#[tauri::command]
+async fn import_conversation_markdown(text: String, state: State<'_, AppState>) -> Result<ConversationMeta, String> {
+    let imported = conversations::parse_markdown(&text);
+    if imported.messages.is_empty() {
+        return Err("No messages found in Markdown".to_string());
+    }
+
+    let now = conversations::now_secs();
+    let conversation = Conversation {
+        id: conversations::new_id(),
+        title: imported.title,
+        created_at: now,
+        updated_at: now,
+        messages: imported.messages,
+    };
+    conversations::save(&conversation)?;
+
+    let mut llm = state.llm.lock().await;
+    llm.set_history(conversation.messages.clone());
+    if let Some(prompt) = imported.system_prompt {
+        llm.set_system_prompt(prompt);
+    }
+    drop(llm);
+
+    *state.current_conversation.lock().await = Some(conversation.id.clone());
+    log::info!("Conversation imported from Markdown: {} ({} messages)", conversation.id, conversation.messages.len());
+    Ok(conversation.meta())
+}
+
+/// Rename a saved conversation (an empty title restores the derived one)
+#[tauri::command]
+async fn rename_conversation(id: String, title: String) -> Result<ConversationMeta, String> {
+    conversations::rename(&id, &title)
+}
+
+/// Delete a saved conversation
+#[tauri::command]
+async fn delete_conversation(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    conversations::delete(&id)?;
+    let mut current = state.current_conversation.lock().await;
+    if current.as_deref() == Some(id.as_str()) {
+        *current = None;
+    }
+    log::info!("Conversation deleted: {}", id);
+    Ok(())
+}
+
+/// Send a text message to the LLM (without speech)
+///
+/// With `stateless`, only the system prompt and this message are sent and the
+/// exchange is not added to the conversation history.
+#[tauri::command]
+async fn send_text_message(
+    message: String,
+    stateless: Option<bool>,
+    app: AppHandle,
+    state: State<'_, AppState>
+) -> Result<ProcessingResult, String> {
+    let _guard = ProcessingGuard::try_acquire(&state.is_processing)
+        .ok_or_else(|| "Already processing".to_string())?;
+
+    respond_to_text(&app, state.inner(), message, stateless.unwrap_or(false)).await
+}
+
+/// Send a text message and stream the reply as `llm-chunk` events before speaking it
+///
+/// `stop_generation` cuts the reply off; the partial text is kept in history and
+/// returned with status "interrupted" without being synthesized.
+#[tauri::command]
+async fn send_text_message_streaming(
+    message: String,
+    app: AppHandle,
+    state: State<'_, AppState>
+) -> Result<ProcessingResult, String> {
+    let _guard = ProcessingGuard::try_acquire(&state.is_processing)
+        .ok_or_else(|| "Already processing".to_string())?;
+
+    let st = state.inner();
+    st.cancel_generation.store(false, Ordering::SeqCst);
+    let turn_id = st.begin_turn(&app);
+    let _ = app.emit("processing-status", "Thinking...");
+
+    let llm_response = stream_llm_reply(&app, st, &message, |chunk| {
+        let _ = app.emit("llm-chunk", chunk);
+    })
+    .await?;
+
+    let response_text = llm_response.text.clone();
+    let _ = app.emit("llm-response", &response_text);
+
+    if llm_response.finish_reason.as_deref() == Some(FINISH_CANCELLED) {
+        return Ok(interrupted_result(message, response_text));
+    }
+    speak_response(&app, st, turn_id, message, response_text, &llm_response).await
+}
+
+/// Stream a reply from the LLM selected by the service mode, falling back
+/// along the service chain
+///
+/// In embedded mode the on-device model is loaded on first use. Only a
+/// backend that fails before streaming anything falls back: chunks already
+/// sent cannot be taken back, so a later failure is returned as is.
+async fn stream_llm_reply<F>(app: &AppHandle, st: &AppState, message: &str, on_chunk: F) -> Result<LLMResponse, String>
+where
+    F: Fn(&str),
+{
+    let chain = st.chain_for("llm").await;
+    let streamed = AtomicBool::new(false);
+    let on_chunk = |chunk: &str| {
+        streamed.store(true, Ordering::SeqCst);
+        on_chunk(chunk);
+    };
+    with_fallback("llm", &chain, |mode| {
+        let on_chunk = &on_chunk;
+        let streamed = &streamed;
+        async move {
+            let result = match mode {
+                ServiceMode::Remote => run_stage(app, st, "LLM", move || async move {
+                    st.llm.lock().await.chat_stream(message, &st.cancel_generation, on_chunk).await
+                }).await,
+                #[cfg(feature = "embedded-services")]
+                ServiceMode::Embedded => {
+                    let mut llm = st.embedded_llm.lock().await;
+                    if !llm.is_ready() {
+                        llm.initialize().await?;
+                    }
+                    llm.chat_stream(message, &st.cancel_generation, on_chunk)
+                        .await
+                        .map(LLMResponse::from)
+                }
+                #[cfg(not(feature = "embedded-services"))]
+                ServiceMode::Embedded => Err("Embedded LLM not available in remote mode".to_string()),
+            };
+            match result {
+                // Succeed so the chain stops, and return the error below
+                Err(e) if streamed.load(Ordering::SeqCst) => Ok(Err(e)),
+                result => result.map(Ok),
+            }
+        }
+    }, |event| {
+        report_fallback(app, st, event);
+    }).await?
+}
+
+/// Stop a streaming LLM reply, keeping what was generated so far
+#[tauri::command]
+async fn stop_generation(state: State<'_, AppState>) -> Result<(), String> {
+    state.cancel_generation.store(true, Ordering::SeqCst);
+    log::info!("LLM generation stop requested");
+    Ok(())
+}
+
+/// Run a text message through LLM and TTS
+async fn respond_to_text(
+    app: &AppHandle,
+    state: &AppState,
+    message: String,
+    stateless: bool,
+) -> Result<ProcessingResult, String> {
+    let turn_id = state.begin_turn(app);
+
+    // LLM - Generate response
+    let _ = app.emit("processing-status", "Thinking...");
+    
+    let chain = state.chain_for(if stateless { "llm-stateless" } else { "llm" }).await;
+    let llm_response = with_fallback("llm", &chain, |mode| chat_on(app, state, mode, &message, stateless), |event| {
+        report_fallback(app, state, event);
+    }).await?;
+
+    let response_text = llm_response.text.clone();
+    let _ = app.emit("llm-response", &response_text);
+
+    speak_response(app, state, turn_id, message, response_text, &llm_response).await
+}
+
+/// Synthesize the response and emit it, unless the turn was interrupted.
+/// With `awaiting_input`, listening resumes once the audio is emitted.
+async fn speak_response(
+    app: &AppHandle,
+    state: &AppState,
+    turn_id: u64,
+    message: String,
+    response_text: String,
+    llm_response: &LLMResponse,
+) -> Result<ProcessingResult, String> {
+    let truncated = report_truncation(app, llm_response);
+    state.check_context_usage(app).await;
+
+    // TTS - Synthesize speech
+    if !state.is_current_turn(turn_id) {
+        return Ok(interrupted_result(message, response_text));
+    }
+    let _ = app.emit("processing-status", "Generating audio...");
+
+    let spoken = synthesize_response(app, state, turn_id, &response_text).await?;
+
+    // Drop audio that finished after the user interrupted
+    if !state.is_current_turn(turn_id) {
+        return Ok(interrupted_result(message, response_text));
+    }
+
+    if llm_response.awaiting_input {
+        await_user_input(app, state, turn_id);
+    }
+
+    Ok(ProcessingResult {
+        status: if truncated { "truncated" } else { "complete" }.to_string(),
+        transcription: Some(message),
+        response: Some(response_text),
+        audio_ready: spoken > 0,
+        timing: PipelineTiming::default(),
+    })
+}
+
+/// Interrupt playback of the current turn
+///
+/// Bumps the turn generation so any audio still being synthesized for the
+/// current turn is discarded, and emits `playback-interrupted` so the
+/// frontend can stop and flush whatever it has queued.
+#[tauri::command]
+async fn interrupt_playback(app: AppHandle, state: State<'_, AppState>) -> Result<u64, String> {
+    let turn_id = state.turn_id.fetch_add(1, Ordering::SeqCst) + 1;
+    let _ = app.emit("playback-interrupted", turn_id);
+    log::info!("Playback interrupted (turn {})", turn_id);
+    Ok(turn_id)
+}
+
+/// Stop the spoken response, including pieces not yet synthesized
+#[tauri::command]
+async fn stop_tts_playback(app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+    if state.stop_speaking(&app) {
+        log::info!("Response playback stopped");
+    }
+    Ok(())
+}
+
+/// Called by the frontend when its queued response audio has finished playing
+#[tauri::command]
+async fn tts_playback_finished(state: State<'_, AppState>) -> Result<(), String> {
+    state.is_speaking.store(false, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Enable or disable dictation (append) mode
+///
+/// While enabled, each `process_audio` call appends its transcription to a
+/// buffer instead of starting a new LLM turn. Disabling discards the buffer.
+#[tauri::command]
+async fn set_append_mode(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    let mut dictation = state.dictation.lock().await;
+    dictation.append_mode = enabled;
+    if !enabled {
+        dictation.buffer.clear();
+    }
+    log::info!("Dictation mode {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+/// Send the accumulated dictation to the LLM and start a new buffer
+///
+/// If the reply fails, the dictation is put back so it can be finalized again.
+#[tauri::command]
+async fn finalize_dictation(app: AppHandle, state: State<'_, AppState>) -> Result<ProcessingResult, String> {
+    let _guard = ProcessingGuard::try_acquire(&state.is_processing)
+        .ok_or_else(|| "Already processing".to_string())?;
+
+    let text = std::mem::take(&mut state.dictation.lock().await.buffer);
+    let _ = app.emit("dictation-updated", "");
+
+    if text.trim().is_empty() {
+        return Ok(ProcessingResult {
+            status: "empty".to_string(),
+            transcription: Some(text),
+            response: None,
+            audio_ready: false,
+            timing: PipelineTiming::default(),
+        });
+    }
+
+    let result = respond_to_text(&app, state.inner(), text.clone(), false).await;
+    if result.is_err() {
+        let mut dictation = state.dictation.lock().await;
+        if !dictation.buffer.is_empty() {
+            dictation.buffer.insert(0, ' ');
+        }
+        dictation.buffer.insert_str(0, &text);
+        let _ = app.emit("dictation-updated", &dictation.buffer);
+    }
+    result
+}
+
+/// Queue text messages for sequential LLM→TTS processing
+///
+/// Messages are processed in the background one at a time, waiting for any
+/// in-flight turn to finish. Each emits `queue-message-result`, and
+/// `queue-complete` is emitted once the queue drains. Returns the queue length.
+#[tauri::command]
+async fn enqueue_messages(
+    messages: Vec<String>,
+    app: AppHandle,
+    state: State<'_, AppState>
+) -> Result<usize, String> {
+    let mut queue = state.message_queue.lock().await;
+    queue.extend(messages.into_iter().filter(|m| !m.trim().is_empty()));
+    let queued = queue.len();
+
+    // Start a worker unless one is already draining the queue
+    if queued > 0 && state.queue_running
+        .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok()
+    {
+        tauri::async_runtime::spawn(run_message_queue(app));
+    }
+
+    log::info!("Message queue length: {}", queued);
+    Ok(queued)
+}
+
+/// Drop all messages still waiting in the queue. Returns how many were removed.
+#[tauri::command]
+async fn clear_message_queue(state: State<'_, AppState>) -> Result<usize, String> {
+    let mut queue = state.message_queue.lock().await;
+    let removed = queue.len();
+    queue.clear();
+    log::info!("Cleared {} queued messages", removed);
+    Ok(removed)
+}
+
+/// Drain the message queue, processing one message at a time
+async fn run_message_queue(app: AppHandle) {
+    let state = app.state::<AppState>();
+    let mut summary = QueueSummary { processed: 0, succeeded: 0, failed: 0 };
+
+    loop {
+        let message = {
+            let mut queue = state.message_queue.lock().await;
+            match queue.pop_front() {
+                Some(message) => message,
+                None => {
+                    // Cleared under the queue lock so a concurrent enqueue starts a new worker
+                    state.queue_running.store(false, Ordering::SeqCst);
+                    break;
+                }
+            }
+        };
+
+        // Wait for any in-flight turn so queued messages don't interleave
+        let _guard = loop {
+            if let Some(guard) = ProcessingGuard::try_acquire(&state.is_processing) {
+                break guard;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        };
+
+        let index = summary.processed;
+        let outcome = respond_to_text(&app, state.inner(), message.clone(), false).await;
+        summary.processed += 1;
+
+        let item = match outcome {
+            Ok(result) => {
+                summary.succeeded += 1;
+                QueuedMessageResult { index, message, result: Some(result), error: None }
+            }
+            Err(e) => {
+                summary.failed += 1;
+                log::error!("Queued message {} failed: {}", index, e);
+                QueuedMessageResult { index, message, result: None, error: Some(e) }
+            }
+        };
+        let _ = app.emit("queue-message-result", &item);
+    }
+
+    log::info!("Message queue drained: {} processed, {} failed", summary.processed, summary.failed);
+    let _ = app.emit("queue-complete", &summary);
+}
+
+// ============================================================================
+// Model Management Commands (for embedded/Android mode)
+// ============================================================================
+
+/// Get information about required models
+#[cfg(feature = "embedded-services")]
+#[tauri::command]
+async fn get_model_info(state: State<'_, AppState>) -> Result<Vec<ModelInfo>, String> {
+    Ok(state.model_manager.lock().await.get_model_info())
+}
+
+/// Check if all models are ready
+///
+/// With `verify`, each model is also hashed and checked against its expected
+/// SHA-256, which can take several seconds.
+#[cfg(feature = "embedded-services")]
+#[tauri::command]
+async fn are_models_ready(verify: Option<bool>, state: State<'_, AppState>) -> Result<bool, String> {
+    let manager = state.model_manager.lock().await.clone();
+    if !verify.unwrap_or(false) {
+        return Ok(manager.are_models_ready());
+    }
+    tauri::async_runtime::spawn_blocking(move || manager.are_models_verified())
+        .await
+        .map_err(|e| format!("Failed to verify models: {}", e))?
+}
+
+/// Hash a downloaded model and compare it with its expected SHA-256
+#[cfg(feature = "embedded-services")]
+#[tauri::command]
+async fn verify_model(file_name: String, state: State<'_, AppState>) -> Result<bool, String> {
+    let manager = state.model_manager.lock().await.clone();
+    let valid = tauri::async_runtime::spawn_blocking(move || manager.verify_model(&file_name))
+        .await
+        .map_err(|e| format!("Failed to verify model: {}", e))??;
+    Ok(valid)
+}
+
+/// Get model download URL
+#[cfg(feature = "embedded-services")]
+#[tauri::command]
+async fn get_model_download_url(file_name: String, state: State<'_, AppState>) -> Result<String, String> {
+    state.model_manager.lock().await.get_download_url(&file_name)
+        .ok_or_else(|| format!("Unknown model: {}", file_name))
+}
+
+/// Download a model from `url` (e.g. a mirror) instead of its built-in source
+#[cfg(feature = "embedded-services")]
+#[tauri::command]
+async fn set_model_url(file_name: String, url: String, state: State<'_, AppState>) -> Result<(), String> {
+    state.model_manager.lock().await.set_model_url(&file_name, url)
+}
+
+/// Get model directory path
+#[cfg(feature = "embedded-services")]
+#[tauri::command]
+async fn get_model_dir(state: State<'_, AppState>) -> Result<String, String> {
+    let manager = state.model_manager.lock().await;
+    manager.ensure_model_dir()?;
+    Ok(manager.model_dir().to_string_lossy().to_string())
+}
+
+/// Keep models in `path` (e.g. on an SD card) instead of the default directory
+///
+/// The directory must be writable and is kept across restarts. Models already
+/// downloaded elsewhere are not moved; the on-device models are loaded from
+/// the new directory on next use.
+#[cfg(feature = "embedded-services")]
+#[tauri::command]
+async fn set_model_directory(path: String, state: State<'_, AppState>) -> Result<(), String> {
+    let dir = std::path::PathBuf::from(path.trim());
+    if dir.as_os_str().is_empty() {
+        return Err("Model directory cannot be empty".to_string());
+    }
+    ModelManager::check_writable(&dir)?;
+
+    let mut manager = state.model_manager.lock().await;
+    if manager.is_downloading() {
+        return Err("Cannot change the model directory while a model is downloading".to_string());
+    }
+    save_model_dir(&dir)?;
+    *manager = manager.moved_to(dir.clone());
+    drop(manager);
+
+    let mut llm = state.embedded_llm.lock().await;
+    llm.set_model_path(dir.join(LLM_MODEL_FILE));
+    llm.config().save()?;
+    drop(llm);
+    state.embedded_asr.lock().await.set_model_path(dir.join(WhisperVariant::load_active().file_name()));
+
+    log::info!("Model directory set to {}", dir.display());
+    Ok(())
+}
+
+/// Download a model into the model directory
+///
+/// Emits `model-download-progress` about every 250ms while downloading.
+#[cfg(feature = "embedded-services")]
+#[tauri::command]
+async fn download_model(file_name: String, app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.model_manager.lock().await.clone();
+    manager.download_model(&file_name, |progress| {
+        let _ = app.emit("model-download-progress", &progress);
+    }).await?;
+    log::info!("Model downloaded: {}", file_name);
+    Ok(())
+}
+
+/// Download every required model not yet present, concurrently
+///
+/// Emits `model-download-progress` for each model (tagged with its name) so
+/// the downloads can be shown side by side. Failures are listed in the summary.
+#[cfg(feature = "embedded-services")]
+#[tauri::command]
+async fn download_all_models(app: AppHandle, state: State<'_, AppState>) -> Result<DownloadSummary, String> {
+    let manager = state.model_manager.lock().await.clone();
+    let summary = manager.download_all_models(|progress| {
+        let _ = app.emit("model-download-progress", &progress);
+    }).await;
+    log::info!(
+        "Model downloads finished: {} downloaded, {} already present, {} failed",
+        summary.downloaded.len(),
+        summary.already_present.len(),
+        summary.failed.len()
+    );
+    Ok(summary)
+}
+
+/// Stop an in-flight model download and delete its partial file
+#[cfg(feature = "embedded-services")]
+#[tauri::command]
+async fn cancel_download(file_name: String, state: State<'_, AppState>) -> Result<(), String> {
+    if !state.model_manager.lock().await.cancel_download(&file_name) {
+        return Err(format!("{} is not downloading", file_name));
+    }
+    log::info!("Download of {} cancelled", file_name);
+    Ok(())
+}
+
+/// Run `ModelManager::repair` off the async runtime, since it hashes every model
+#[cfg(feature = "embedded-services")]
+async fn repair_models(manager: ModelManager) -> Result<RepairReport, String> {
+    tauri::async_runtime::spawn_blocking(move || manager.repair())
+        .await
+        .map_err(|e| format!("Failed to repair model directory: {}", e))?
+}
+
+/// Clean up the model directory and report what is still missing
+///
+/// Deletes partial downloads, fixes misnamed model files and deletes model
+/// files that fail verification.
+#[cfg(feature = "embedded-services")]
+#[tauri::command]
+async fn repair_model_dir(state: State<'_, AppState>) -> Result<RepairReport, String> {
+    let report = repair_models(state.model_manager.lock().await.clone()).await?;
+    log::info!(
+        "Model directory repaired: {} partial, {} renamed, {} corrupt removed, {} missing",
+        report.removed_partial.len(),
+        report.renamed.len(),
+        report.removed_corrupt.len(),
+        report.missing.len()
+    );
+    Ok(report)
+}
+
+/// Repair the model directory, then download every required model still missing
+///
+/// Emits `model-download-progress` while downloading. A failed download does
+/// not stop the others; the returned report lists what is still missing.
+#[cfg(feature = "embedded-services")]
+#[tauri::command]
+async fn download_missing_after_repair(app: AppHandle, state: State<'_, AppState>) -> Result<RepairReport, String> {
+    let manager = state.model_manager.lock().await.clone();
+    let mut report = repair_models(manager.clone()).await?;
+
+    for info in std::mem::take(&mut report.missing) {
+        log::info!("Downloading {} from {}", info.name, info.download_url);
+        let result = manager.download_model(&info.file_name, |progress| {
+            let _ = app.emit("model-download-progress", &progress);
+        }).await;
+        if let Err(e) = result {
+            log::warn!("{}", e);
+        }
+    }
+
+    report.missing = manager.get_model_info()
+        .into_iter()
+        .filter(|info| !info.is_downloaded)
+        .collect();
+    Ok(report)
+}
+
+/// Set the embedded LLM inference thread count (0 = auto) and persist it
+///
+/// Reloads the model if it is already loaded so the change takes effect.
+#[cfg(feature = "embedded-services")]
+#[tauri::command]
+async fn set_embedded_threads(n: u32, state: State<'_, AppState>) -> Result<(), String> {
+    let cores = std::thread::available_parallelism()
+        .map(|c| c.get() as u32)
+        .unwrap_or(1);
+    if n > cores {
+        return Err(format!("Thread count {} exceeds available cores ({})", n, cores));
+    }
+
+    let mut llm = state.embedded_llm.lock().await;
+    llm.set_n_threads(n).await?;
+    llm.config().save()?;
+    log::info!("Embedded LLM threads set to {}", n);
+    Ok(())
+}
+
+/// Default real-time factor a Whisper variant must reach to be auto-selected
+#[cfg(feature = "embedded-services")]
+const DEFAULT_TARGET_RTF: f32 = 0.5;
+
+/// Whisper variant chosen by `auto_select_whisper_model`
+#[cfg(feature = "embedded-services")]
+#[derive(Debug, Clone, Serialize)]
+pub struct WhisperSelection {
+    pub variant: WhisperVariant,
+    pub rtf: f32,
+    /// The RTF was estimated from device specs rather than measured
+    pub estimated: bool,
+    pub is_downloaded: bool,
+    pub download_url: String,
+}
+
+/// Pick the largest Whisper variant that meets `target_rtf` (default 0.5) and make it active
+///
+/// Nothing is benchmarked: each variant's real-time factor is a rough guess
+/// from the CPU core count, so the result is always marked `estimated`.
+/// Emits `whisper-estimate-progress` for each variant considered.
+#[cfg(feature = "embedded-services")]
+#[tauri::command]
+async fn auto_select_whisper_model(
+    target_rtf: Option<f32>,
+    app: AppHandle,
+    state: State<'_, AppState>
+) -> Result<WhisperSelection, String> {
+    let target_rtf = target_rtf.unwrap_or(DEFAULT_TARGET_RTF);
+    let cores = std::thread::available_parallelism()
+        .map(|c| c.get() as u32)
+        .unwrap_or(1);
+
+    let mut chosen = (WhisperVariant::Tiny, WhisperVariant::Tiny.estimate_rtf(cores));
+    for (index, variant) in WhisperVariant::ALL.iter().copied().enumerate() {
+        let rtf = variant.estimate_rtf(cores);
+        let _ = app.emit("whisper-estimate-progress", serde_json::json!({
+            "index": index,
+            "total": WhisperVariant::ALL.len(),
+            "variant": variant,
+            "rtf": rtf,
+        }));
+        if rtf <= target_rtf {
+            chosen = (variant, rtf);
+        }
+    }
+
+    let (variant, rtf) = chosen;
+    variant.save_active()?;
+    let (model_path, is_downloaded) = {
+        let manager = state.model_manager.lock().await;
+        (manager.model_dir().join(variant.file_name()), manager.is_model_downloaded(variant.file_name()))
+    };
+    state.embedded_asr.lock().await.set_model_path(model_path);
+    log::info!("Selected Whisper {:?} (estimated RTF {:.2} on {} cores)", variant, rtf, cores);
+
+    Ok(WhisperSelection {
+        variant,
+        rtf,
+        estimated: true,
+        is_downloaded,
+        download_url: variant.download_url().to_string(),
+    })
 }
 
-/// Get model download URL
+/// Tokens held in the embedded LLM's KV cache and the context capacity
 #[cfg(feature = "embedded-services")]
 #[tauri::command]
-async fn get_model_download_url(file_name: String, state: State<'_, AppState>) -> Result<String, String> {
-    state.model_manager.get_download_url(&file_name)
-        .map(|s| s.to_string())
-        .ok_or_else(|| format!("Unknown model: {}", file_name))
+async fn get_kv_cache_usage(state: State<'_, AppState>) -> Result<KvCacheUsage, String> {
+    Ok(state.embedded_llm.lock().await.kv_cache_usage())
 }
 
-/// Get model directory path
+/// Clear the embedded LLM's KV cache without forgetting the conversation
 #[cfg(feature = "embedded-services")]
 #[tauri::command]
-async fn get_model_dir(state: State<'_, AppState>) -> Result<String, String> {
-    state.model_manager.ensure_model_dir()?;
-    Ok(state.model_manager.model_dir().to_string_lossy().to_string())
+async fn reset_kv_cache(state: State<'_, AppState>) -> Result<(), String> {
+    state.embedded_llm.lock().await.reset_kv_cache();
+    log::info!("Embedded LLM KV cache reset");
+    Ok(())
 }
 
 // Placeholder commands for non-embedded builds
@@ -297,22 +3504,94 @@ async fn get_model_info() -> Result<Vec<serde_json::Value>, String> {
 
 #[cfg(not(feature = "embedded-services"))]
 #[tauri::command]
-async fn are_models_ready() -> Result<bool, String> {
+async fn are_models_ready(_verify: Option<bool>) -> Result<bool, String> {
     Ok(true) // Remote mode doesn't need local models
 }
 
+#[cfg(not(feature = "embedded-services"))]
+#[tauri::command]
+async fn verify_model(_file_name: String) -> Result<bool, String> {
+    Err("Model verification not available in remote mode".to_string())
+}
+
 #[cfg(not(feature = "embedded-services"))]
 #[tauri::command]
 async fn get_model_download_url(_file_name: String) -> Result<String, String> {
     Err("Model downloads not available in remote mode".to_string())
 }
 
+#[cfg(not(feature = "embedded-services"))]
+#[tauri::command]
+async fn set_model_url(_file_name: String, _url: String) -> Result<(), String> {
+    Err("Model downloads not available in remote mode".to_string())
+}
+
 #[cfg(not(feature = "embedded-services"))]
 #[tauri::command]
 async fn get_model_dir() -> Result<String, String> {
     Err("Model directory not available in remote mode".to_string())
 }
 
+#[cfg(not(feature = "embedded-services"))]
+#[tauri::command]
+async fn set_model_directory(_path: String) -> Result<(), String> {
+    Err("Model directory not available in remote mode".to_string())
+}
+
+#[cfg(not(feature = "embedded-services"))]
+#[tauri::command]
+async fn download_model(_file_name: String) -> Result<(), String> {
+    Err("Model downloads not available in remote mode".to_string())
+}
+
+#[cfg(not(feature = "embedded-services"))]
+#[tauri::command]
+async fn download_all_models() -> Result<serde_json::Value, String> {
+    Err("Model downloads not available in remote mode".to_string())
+}
+
+#[cfg(not(feature = "embedded-services"))]
+#[tauri::command]
+async fn cancel_download(_file_name: String) -> Result<(), String> {
+    Err("Model downloads not available in remote mode".to_string())
+}
+
+#[cfg(not(feature = "embedded-services"))]
+#[tauri::command]
+async fn repair_model_dir() -> Result<serde_json::Value, String> {
+    Err("Model directory not available in remote mode".to_string())
+}
+
+#[cfg(not(feature = "embedded-services"))]
+#[tauri::command]
+async fn download_missing_after_repair() -> Result<serde_json::Value, String> {
+    Err("Model downloads not available in remote mode".to_string())
+}
+
+#[cfg(not(feature = "embedded-services"))]
+#[tauri::command]
+async fn set_embedded_threads(_n: u32) -> Result<(), String> {
+    Err("Embedded LLM not available in remote mode".to_string())
+}
+
+#[cfg(not(feature = "embedded-services"))]
+#[tauri::command]
+async fn auto_select_whisper_model(_target_rtf: Option<f32>) -> Result<serde_json::Value, String> {
+    Err("Embedded ASR not available in remote mode".to_string())
+}
+
+#[cfg(not(feature = "embedded-services"))]
+#[tauri::command]
+async fn get_kv_cache_usage() -> Result<serde_json::Value, String> {
+    Err("Embedded LLM not available in remote mode".to_string())
+}
+
+#[cfg(not(feature = "embedded-services"))]
+#[tauri::command]
+async fn reset_kv_cache() -> Result<(), String> {
+    Err("Embedded LLM not available in remote mode".to_string())
+}
+
 /// Screenshot result sent to frontend
 #[derive(Debug, Clone, Serialize)]
 pub struct ScreenshotResult {
@@ -321,11 +3600,71 @@ pub struct ScreenshotResult {
     pub width: Option<u32>,
     pub height: Option<u32>,
     pub error: Option<String>,
+    /// Cursor position in image pixels (None if not requested or on another monitor)
+    pub cursor_x: Option<i32>,
+    pub cursor_y: Option<i32>,
+}
+
+/// Locate the cursor relative to a monitor, scaled to the captured image size.
+/// Returns None if the cursor is on a different monitor.
+fn cursor_in_monitor(app: &AppHandle, monitor: &Monitor, image_width: u32, image_height: u32) -> Option<(i32, i32)> {
+    let position = app.cursor_position()
+        .map_err(|e| log::warn!("Failed to get cursor position: {}", e))
+        .ok()?;
+
+    let rel_x = position.x - monitor.x() as f64;
+    let rel_y = position.y - monitor.y() as f64;
+    let (width, height) = (monitor.width() as f64, monitor.height() as f64);
+    if rel_x < 0.0 || rel_y < 0.0 || rel_x >= width || rel_y >= height {
+        return None;
+    }
+
+    // Monitor geometry may be in logical units while the capture is in pixels
+    let x = rel_x * image_width as f64 / width;
+    let y = rel_y * image_height as f64 / height;
+    Some((x as i32, y as i32))
+}
+
+/// Draw a crosshair-and-ring marker at the cursor position
+fn draw_cursor_marker(image: &mut image::RgbaImage, cx: i32, cy: i32) {
+    const RADIUS: i32 = 12;
+    const ARM: i32 = 18;
+    let color = image::Rgba([255, 40, 40, 255]);
+    let (width, height) = (image.width() as i32, image.height() as i32);
+    let mut put = |x: i32, y: i32| {
+        if x >= 0 && y >= 0 && x < width && y < height {
+            image.put_pixel(x as u32, y as u32, color);
+        }
+    };
+
+    for d in -ARM..=ARM {
+        for t in -1..=1 {
+            put(cx + d, cy + t);
+            put(cx + t, cy + d);
+        }
+    }
+    for dy in -RADIUS - 1..=RADIUS + 1 {
+        for dx in -RADIUS - 1..=RADIUS + 1 {
+            let dist = ((dx * dx + dy * dy) as f64).sqrt();
+            if (dist - RADIUS as f64).abs() <= 1.0 {
+                put(cx + dx, cy + dy);
+            }
+        }
+    }
 }
 
 /// Take a screenshot of a specific monitor
+///
+/// With `include_cursor`, the cursor position relative to the image is also
+/// returned; `annotate_cursor` additionally draws a marker at that position.
 #[tauri::command]
-async fn take_screenshot(monitor_index: Option<usize>) -> Result<ScreenshotResult, String> {
+async fn take_screenshot(
+    monitor_index: Option<usize>,
+    include_cursor: Option<bool>,
+    annotate_cursor: Option<bool>,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<ScreenshotResult, String> {
     // Get all monitors
     let monitors = Monitor::all()
         .map_err(|e| format!("Failed to get monitors: {}", e))?;
@@ -337,30 +3676,30 @@ async fn take_screenshot(monitor_index: Option<usize>) -> Result<ScreenshotResul
             width: None,
             height: None,
             error: Some("No monitors found".to_string()),
+            cursor_x: None,
+            cursor_y: None,
         });
     }
     
-    // Select monitor (default to primary/first monitor)
-    let index = monitor_index.unwrap_or(0);
-    let monitor = monitors.get(index)
-        .ok_or_else(|| format!("Monitor index {} out of range (available: {})", index, monitors.len()))?;
+    // Select monitor (default monitor, else primary)
+    let (_, monitor) = pick_monitor(&monitors, monitor_index, *state.default_monitor.lock().await)?;
     
     // Capture screenshot
-    let image = monitor.capture_image()
+    let mut image = monitor.capture_image()
         .map_err(|e| format!("Failed to capture screenshot: {}", e))?;
+
+    let annotate_cursor = annotate_cursor.unwrap_or(false);
+    let cursor = if include_cursor.unwrap_or(false) || annotate_cursor {
+        cursor_in_monitor(&app, monitor, image.width(), image.height())
+    } else {
+        None
+    };
+    if let (true, Some((x, y))) = (annotate_cursor, cursor) {
+        draw_cursor_marker(&mut image, x, y);
+    }
     
-    // Convert to PNG and encode as base64
-    let mut png_data = Vec::new();
-    let encoder = PngEncoder::new(&mut png_data);
-    encoder.write_image(
-        image.as_raw(),
-        image.width(),
-        image.height(),
-        image::ExtendedColorType::Rgba8,
-    ).map_err(|e| format!("Failed to encode image: {}", e))?;
-    
-    let base64_image = base64::engine::general_purpose::STANDARD.encode(&png_data);
-    
+    let base64_image = encode_png_base64(&image)?;
+
     log::info!("Screenshot captured: {}x{}", image.width(), image.height());
     
     Ok(ScreenshotResult {
@@ -369,7 +3708,243 @@ async fn take_screenshot(monitor_index: Option<usize>) -> Result<ScreenshotResul
         width: Some(image.width()),
         height: Some(image.height()),
         error: None,
+        cursor_x: cursor.map(|(x, _)| x),
+        cursor_y: cursor.map(|(_, y)| y),
+    })
+}
+
+/// Take a screenshot of a rectangle of a monitor
+///
+/// The region is given in captured image pixels and must lie within the monitor.
+#[tauri::command]
+async fn take_screenshot_region(
+    monitor_index: Option<usize>,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    state: State<'_, AppState>,
+) -> Result<ScreenshotResult, String> {
+    if width == 0 || height == 0 {
+        return Err("Screenshot region must have a non-zero width and height".to_string());
+    }
+
+    let monitors = Monitor::all()
+        .map_err(|e| format!("Failed to get monitors: {}", e))?;
+    let (index, monitor) = pick_monitor(&monitors, monitor_index, *state.default_monitor.lock().await)?;
+
+    let image = monitor.capture_image()
+        .map_err(|e| format!("Failed to capture screenshot: {}", e))?;
+
+    let fits = x as u64 + width as u64 <= image.width() as u64
+        && y as u64 + height as u64 <= image.height() as u64;
+    if !fits {
+        return Err(format!(
+            "Region {}x{} at ({}, {}) is outside monitor {} ({}x{})",
+            width, height, x, y, index, image.width(), image.height()
+        ));
+    }
+
+    let region = image::imageops::crop_imm(&image, x, y, width, height).to_image();
+    let base64_image = encode_png_base64(&region)?;
+
+    log::info!("Screenshot region captured: {}x{} at ({}, {})", width, height, x, y);
+
+    Ok(ScreenshotResult {
+        success: true,
+        image_base64: Some(base64_image),
+        width: Some(width),
+        height: Some(height),
+        error: None,
+        cursor_x: None,
+        cursor_y: None,
+    })
+}
+
+/// Encode an image as base64 PNG
+fn encode_png_base64(image: &image::RgbaImage) -> Result<String, String> {
+    Ok(base64::engine::general_purpose::STANDARD.encode(encode_png(image)?))
+}
+
+/// Encode an RGBA image as PNG
+fn encode_png(image: &image::RgbaImage) -> Result<Vec<u8>, String> {
+    let mut png_data = Vec::new();
+    PngEncoder::new(&mut png_data).write_image(
+        image.as_raw(),
+        image.width(),
+        image.height(),
+        image::ExtendedColorType::Rgba8,
+    ).map_err(|e| format!("Failed to encode image: {}", e))?;
+    Ok(png_data)
+}
+
+/// Capture a monitor as PNG on a blocking thread
+///
+/// The monitor is chosen like `pick_monitor` does.
+async fn capture_png(monitor_index: Option<usize>, default: Option<usize>) -> Result<Vec<u8>, String> {
+    tokio::task::spawn_blocking(move || {
+        let monitors = Monitor::all()
+            .map_err(|e| format!("Failed to get monitors: {}", e))?;
+        let (_, monitor) = pick_monitor(&monitors, monitor_index, default)?;
+        let image = monitor.capture_image()
+            .map_err(|e| format!("Failed to capture screenshot: {}", e))?;
+
+        encode_png(&image)
     })
+    .await
+    .map_err(|e| format!("Screenshot task failed: {}", e))?
+}
+
+/// Capture a monitor as base64 PNG on a blocking thread
+async fn capture_png_base64(monitor_index: Option<usize>, default: Option<usize>) -> Result<String, String> {
+    let png = capture_png(monitor_index, default).await?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(png))
+}
+
+/// Process a spoken question about the screen
+///
+/// Transcribes the audio and captures the screen concurrently, then sends
+/// both to the LLM. If the screenshot fails, a `screenshot-error` event is
+/// emitted and the turn continues text-only.
+#[tauri::command]
+async fn process_multimodal_audio(
+    audio_base64: String,
+    monitor_index: Option<usize>,
+    app: AppHandle,
+    state: State<'_, AppState>
+) -> Result<ProcessingResult, String> {
+    let _guard = ProcessingGuard::try_acquire(&state.is_processing)
+        .ok_or_else(|| "Already processing".to_string())?;
+
+    let audio_data = base64::engine::general_purpose::STANDARD
+        .decode(&audio_base64)
+        .map_err(|e| format!("Failed to decode audio: {}", e))?;
+
+    let st = state.inner();
+    let turn_id = st.begin_turn(&app);
+    let _ = app.emit("processing-status", "Transcribing...");
+
+    let default_monitor = *st.default_monitor.lock().await;
+    let (transcription, screenshot) = tokio::join!(
+        async { st.asr.lock().await.transcribe_wav(&audio_data).await },
+        capture_png_base64(monitor_index, default_monitor),
+    );
+
+    let transcription = transcription.map_err(|e| format!("Transcription failed: {}", e))?;
+    let transcribed_text = transcription.text.clone();
+    apply_detected_language(&app, st, transcription.language.as_deref()).await;
+    *st.last_transcription.lock().await = Some(transcription.clone());
+    let _ = app.emit("transcription", &transcribed_text);
+
+    if transcribed_text.trim().is_empty() {
+        return Ok(ProcessingResult {
+            status: "empty".to_string(),
+            transcription: Some(transcribed_text),
+            response: None,
+            audio_ready: false,
+            timing: PipelineTiming::default(),
+        });
+    }
+
+    let screenshot = match screenshot {
+        Ok(image) => Some(image),
+        Err(e) => {
+            log::warn!("Screenshot failed, continuing text-only: {}", e);
+            let _ = app.emit("screenshot-error", &e);
+            None
+        }
+    };
+
+    let _ = app.emit("processing-status", "Thinking...");
+    let mut llm = st.llm.lock().await;
+    let llm_response = match &screenshot {
+        Some(image) => llm.chat_with_image(&transcribed_text, image).await,
+        None => llm.chat(&transcribed_text).await,
+    }.map_err(|e| format!("LLM request failed: {}", e))?;
+    drop(llm);
+
+    let response_text = llm_response.text.clone();
+    let _ = app.emit("llm-response", &response_text);
+
+    speak_response(&app, st, turn_id, transcribed_text, response_text, &llm_response).await
+}
+
+/// Capture a monitor and ask the LLM a question about it, speaking the answer
+#[tauri::command]
+async fn ask_about_screenshot(
+    monitor_index: Option<usize>,
+    question: String,
+    app: AppHandle,
+    state: State<'_, AppState>
+) -> Result<ProcessingResult, String> {
+    let _guard = ProcessingGuard::try_acquire(&state.is_processing)
+        .ok_or_else(|| "Already processing".to_string())?;
+
+    let st = state.inner();
+    {
+        let llm = st.llm.lock().await;
+        if !llm.supports_vision() {
+            return Err(format!(
+                "Model '{}' does not accept images. Switch to a vision model (e.g. Qwen2-VL) or enable vision with set_llm_vision if it does.",
+                llm.config().model
+            ));
+        }
+    }
+
+    let turn_id = st.begin_turn(&app);
+    let image = capture_png_base64(monitor_index, *st.default_monitor.lock().await).await?;
+
+    let _ = app.emit("processing-status", "Thinking...");
+    let llm_response = st.llm.lock().await
+        .chat_with_image(&question, &image)
+        .await
+        .map_err(|e| format!("LLM request failed: {}", e))?;
+
+    let response_text = llm_response.text.clone();
+    let _ = app.emit("llm-response", &response_text);
+
+    speak_response(&app, st, turn_id, question, response_text, &llm_response).await
+}
+
+/// Capture a monitor and extract its text with OCR, without involving the LLM
+///
+/// `language` is a Tesseract code ("eng" by default). With `include_boxes`,
+/// each recognized word comes with its bounding box in screenshot pixels.
+#[cfg(feature = "ocr")]
+#[tauri::command]
+async fn ocr_screenshot(
+    monitor_index: Option<usize>,
+    language: Option<String>,
+    include_boxes: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<ocr::OcrResult, String> {
+    let png = capture_png(monitor_index, *state.default_monitor.lock().await).await?;
+    let language = language.unwrap_or_else(|| ocr::DEFAULT_OCR_LANGUAGE.to_string());
+    let include_boxes = include_boxes.unwrap_or(false);
+
+    let result = tokio::task::spawn_blocking(move || ocr::recognize(&png, &language, include_boxes))
+        .await
+        .map_err(|e| format!("OCR task failed: {}", e))??;
+    log::info!("OCR recognized {} characters", result.text.chars().count());
+    Ok(result)
+}
+
+#[cfg(not(feature = "ocr"))]
+#[tauri::command]
+async fn ocr_screenshot(
+    _monitor_index: Option<usize>,
+    _language: Option<String>,
+    _include_boxes: Option<bool>,
+) -> Result<serde_json::Value, String> {
+    Err("OCR not available: build with the ocr feature".to_string())
+}
+
+/// Declare whether the LLM model accepts images (None = guess from its name)
+#[tauri::command]
+async fn set_llm_vision(vision: Option<bool>, state: State<'_, AppState>) -> Result<bool, String> {
+    let mut llm = state.llm.lock().await;
+    llm.set_vision(vision);
+    Ok(llm.supports_vision())
 }
 
 /// Get list of available monitors for screenshot
@@ -393,6 +3968,61 @@ async fn get_monitors() -> Result<Vec<MonitorInfo>, String> {
     Ok(monitor_infos)
 }
 
+/// A display that may be the primary one
+trait Screen {
+    fn is_primary(&self) -> bool;
+}
+
+impl Screen for Monitor {
+    fn is_primary(&self) -> bool {
+        Monitor::is_primary(self)
+    }
+}
+
+/// Pick a monitor and its index: `index` if given, else the primary monitor,
+/// else the first one
+///
+/// The primary monitor is not index 0 on every platform. Fails if there are
+/// no monitors or `index` is out of range.
+fn select_monitor<M: Screen>(monitors: &[M], index: Option<usize>) -> Result<(usize, &M), String> {
+    if monitors.is_empty() {
+        return Err("No monitors found".to_string());
+    }
+    let index = index.unwrap_or_else(|| monitors.iter().position(|m| m.is_primary()).unwrap_or(0));
+    let monitor = monitors.get(index)
+        .ok_or_else(|| format!("Monitor index {} out of range (available: {})", index, monitors.len()))?;
+    Ok((index, monitor))
+}
+
+/// Pick the monitor to capture: the requested index, else the stored default
+/// if that monitor still exists, else the primary monitor
+fn pick_monitor(monitors: &[Monitor], requested: Option<usize>, default: Option<usize>) -> Result<(usize, &Monitor), String> {
+    select_monitor(monitors, requested.or(default.filter(|&index| index < monitors.len())))
+}
+
+/// Get the monitor used when screenshots are taken without an index
+#[tauri::command]
+async fn get_default_monitor(state: State<'_, AppState>) -> Result<Option<usize>, String> {
+    Ok(*state.default_monitor.lock().await)
+}
+
+/// Set the monitor used when screenshots are taken without an index
+/// (None goes back to the primary monitor)
+#[tauri::command]
+async fn set_default_monitor(index: Option<usize>, state: State<'_, AppState>) -> Result<(), String> {
+    if let Some(index) = index {
+        let count = Monitor::all()
+            .map_err(|e| format!("Failed to get monitors: {}", e))?
+            .len();
+        if index >= count {
+            return Err(format!("Monitor index {} out of range (available: {})", index, count));
+        }
+    }
+    *state.default_monitor.lock().await = index;
+    log::info!("Default monitor set to {:?}", index);
+    Ok(())
+}
+
 /// Monitor information for frontend
 #[derive(Debug, Clone, Serialize)]
 pub struct MonitorInfo {
@@ -405,6 +4035,82 @@ pub struct MonitorInfo {
     pub is_primary: bool,
 }
 
+/// Screenshot written to disk instead of returned as base64
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureFileResult {
+    pub path: String,
+    pub format: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Capture a monitor and write the encoded image straight to disk.
+///
+/// Avoids pushing large base64 payloads over IPC; the frontend can load the
+/// returned path via the asset protocol. Defaults to PNG in the temp directory.
+/// The asset protocol only serves `$TEMP/assidenter`, so a `path` elsewhere is
+/// written but cannot be loaded by the frontend.
+#[tauri::command]
+async fn capture_to_file(
+    monitor_index: Option<usize>,
+    path: Option<String>,
+    format: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<CaptureFileResult, String> {
+    let format = format.unwrap_or_else(|| "png".to_string()).to_lowercase();
+    let (image_format, extension) = match format.as_str() {
+        "png" => (image::ImageFormat::Png, "png"),
+        "jpg" | "jpeg" => (image::ImageFormat::Jpeg, "jpg"),
+        other => return Err(format!("Unsupported image format: {}", other)),
+    };
+    let default = *state.default_monitor.lock().await;
+
+    tokio::task::spawn_blocking(move || {
+        let monitors = Monitor::all()
+            .map_err(|e| format!("Failed to get monitors: {}", e))?;
+
+        let (index, monitor) = pick_monitor(&monitors, monitor_index, default)?;
+
+        let image = monitor.capture_image()
+            .map_err(|e| format!("Failed to capture screenshot: {}", e))?;
+        let (width, height) = (image.width(), image.height());
+
+        let path = match path {
+            Some(p) => std::path::PathBuf::from(p),
+            None => {
+                let dir = std::env::temp_dir().join("assidenter");
+                std::fs::create_dir_all(&dir)
+                    .map_err(|e| format!("Failed to create capture directory: {}", e))?;
+                let timestamp = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_millis())
+                    .unwrap_or(0);
+                dir.join(format!("capture-{}-{}.{}", index, timestamp, extension))
+            }
+        };
+
+        // JPEG has no alpha channel
+        let result = match image_format {
+            image::ImageFormat::Jpeg => image::DynamicImage::ImageRgba8(image)
+                .to_rgb8()
+                .save_with_format(&path, image_format),
+            _ => image.save_with_format(&path, image_format),
+        };
+        result.map_err(|e| format!("Failed to write screenshot to {:?}: {}", path, e))?;
+
+        log::info!("Screenshot written to {:?} ({}x{})", path, width, height);
+
+        Ok(CaptureFileResult {
+            path: path.to_string_lossy().to_string(),
+            format: extension.to_string(),
+            width,
+            height,
+        })
+    })
+    .await
+    .map_err(|e| format!("Screenshot task failed: {}", e))?
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
@@ -417,26 +4123,215 @@ pub fn run() {
                         .build(),
                 )?;
             }
+            let handle = app.handle().clone();
+            services::rate_limit::set_listener(move |event| {
+                let _ = handle.emit("rate-limited-locally", event);
+            });
+            restore_session(app.state::<AppState>().inner());
+            #[cfg(desktop)]
+            {
+                app.handle().plugin(hotkey::plugin(toggle_listening))?;
+                let accelerator = hotkey::load();
+                match hotkey::register(app.handle(), &accelerator) {
+                    Ok(()) => {
+                        if let Ok(mut current) = app.state::<AppState>().hotkey.lock() {
+                            *current = Some(accelerator);
+                        }
+                    }
+                    Err(e) => log::warn!("{}", e),
+                }
+            }
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             start_listening,
             stop_listening,
+            push_audio_chunk,
             is_listening,
+            get_hotkey,
+            set_hotkey,
+            detect_conversation_language,
+            export_captions,
+            transcribe_files,
+            transcribe_batch,
+            capture_raw_audio,
+            start_recording,
+            stop_recording,
+            start_mic_monitor,
+            stop_mic_monitor,
+            get_mic_level_smoothing,
+            set_mic_level_smoothing,
+            get_agc,
+            set_agc,
             get_service_status,
+            set_service_mode,
+            get_fallback_chain,
+            set_fallback_chain,
+            get_memory_stats,
             process_audio,
+            set_turn_tracing,
+            get_last_turn_trace,
+            process_streaming_conversation,
+            transcribe_live,
+            test_audio_pipeline,
+            inspect_audio,
             configure_services,
+            reset_service_config,
+            set_punctuation_restoration,
+            set_language_detection,
+            set_asr_language,
+            set_asr_compression,
+            set_asr_vad,
+            set_asr_normalize,
+            set_rate_limit,
+            set_service_timeout,
+            set_service_retries,
+            set_mock_mode,
+            set_api_key,
+            set_service_headers,
+            set_tts_normalization,
+            set_tts_alignment,
+            set_tts_streaming,
+            set_tts_chunk_length,
+            get_tts_voices,
+            save_last_audio,
+            set_tts_channels,
+            set_thinking_filler,
+            set_user_agent,
+            get_retry_budget,
+            set_retry_budget,
             clear_conversation,
+            save_conversation,
+            list_conversations,
+            load_conversation,
+            export_conversation_markdown,
+            import_conversation_markdown,
+            rename_conversation,
+            delete_conversation,
+            set_response_length,
+            set_max_tokens,
+            set_llm_model,
+            set_llm_auto_continue,
+            set_llm_sampling,
+            set_llm_response_format,
+            send_json_message,
+            send_message_with_system,
+            send_message_with_options,
+            list_llm_profiles,
+            save_llm_profile,
+            switch_llm_profile,
+            delete_llm_profile,
+            set_llm_tools,
+            set_tool_prompt_injection,
+            set_follow_up_questions,
+            set_max_history,
+            get_context_usage,
+            get_history,
+            get_conversation_history,
+            pin_message,
+            unpin_message,
             send_text_message,
+            send_text_message_streaming,
+            stop_generation,
+            interrupt_playback,
+            stop_tts_playback,
+            tts_playback_finished,
+            set_append_mode,
+            finalize_dictation,
+            enqueue_messages,
+            clear_message_queue,
             // Model management
             get_model_info,
             are_models_ready,
+            verify_model,
             get_model_download_url,
+            set_model_url,
             get_model_dir,
+            set_model_directory,
+            download_model,
+            download_all_models,
+            cancel_download,
+            repair_model_dir,
+            download_missing_after_repair,
+            set_embedded_threads,
+            auto_select_whisper_model,
+            get_kv_cache_usage,
+            reset_kv_cache,
             // Screenshot
             take_screenshot,
+            take_screenshot_region,
+            ask_about_screenshot,
+            ocr_screenshot,
+            set_llm_vision,
             get_monitors,
+            get_default_monitor,
+            set_default_monitor,
+            capture_to_file,
+            process_multimodal_audio,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                save_session(app.state::<AppState>().inner());
+            }
+        });
+}
+
+/// Restore the history saved by `save_session`, so a restart resumes the chat
+fn restore_session(state: &AppState) {
+    let path = conversations::session_path();
+    if !path.exists() {
+        return;
+    }
+    let mut llm = state.llm.blocking_lock();
+    match llm.load_history(&path) {
+        Ok(()) => log::info!("Restored {} messages from previous session", llm.history().len()),
+        Err(e) => log::warn!("Failed to restore previous session: {}", e),
+    }
+}
+
+/// Save the live history on exit
+fn save_session(state: &AppState) {
+    let llm = state.llm.blocking_lock();
+    if let Err(e) = llm.save_history(&conversations::session_path()) {
+        log::warn!("Failed to save session: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct FakeScreen(bool);
+
+    impl Screen for FakeScreen {
+        fn is_primary(&self) -> bool {
+            self.0
+        }
+    }
+
+    #[test]
+    fn select_monitor_prefers_primary() {
+        let monitors = [FakeScreen(false), FakeScreen(true), FakeScreen(false)];
+        assert_eq!(select_monitor(&monitors, None).unwrap().0, 1);
+        assert_eq!(select_monitor(&monitors, Some(2)).unwrap().0, 2);
+    }
+
+    #[test]
+    fn select_monitor_falls_back_to_first() {
+        let monitors = [FakeScreen(false), FakeScreen(false)];
+        assert_eq!(select_monitor(&monitors, None).unwrap().0, 0);
+    }
+
+    #[test]
+    fn select_monitor_rejects_bad_index() {
+        let monitors = [FakeScreen(true)];
+        assert_eq!(
+            select_monitor(&monitors, Some(1)).unwrap_err(),
+            "Monitor index 1 out of range (available: 1)"
+        );
+        assert_eq!(select_monitor::<FakeScreen>(&[], None).unwrap_err(), "No monitors found");
+    }
 }
@@ -0,0 +1,223 @@
+//! Native microphone capture
+//!
+//! cpal streams are not `Send`, so each capture runs on its own thread and is
+//! controlled through a `CaptureHandle`. Samples are delivered as mono f32.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+use crate::audio::{f32_to_i16, rms_level, LevelSmoother};
+
+/// Interval between `mic-level` events
+const LEVEL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Payload of the `mic-level` event
+#[derive(Debug, Clone, Serialize)]
+pub struct MicLevel {
+    /// Smoothed RMS level for meters
+    pub level: f32,
+    /// Unsmoothed RMS level of the last interval
+    pub raw: f32,
+}
+
+/// Keeps a capture thread alive; dropping it stops the capture
+pub struct CaptureHandle {
+    _stop: mpsc::Sender<()>,
+}
+
+/// Average interleaved channels down to mono
+fn downmix(data: &[f32], channels: usize) -> Vec<f32> {
+    if channels <= 1 {
+        return data.to_vec();
+    }
+    data.chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect()
+}
+
+/// Turn a cpal error into a message that says what the user can do about it
+fn describe_mic_error(action: &str, e: impl std::fmt::Display) -> String {
+    let message = e.to_string();
+    let lower = message.to_lowercase();
+    if lower.contains("permission") || lower.contains("denied") || lower.contains("not authorized") {
+        format!("Microphone access denied: allow microphone access for this app in system settings ({})", message)
+    } else if lower.contains("not available") || lower.contains("no longer available") {
+        format!("Microphone unavailable: it may have been disconnected or be in use by another app ({})", message)
+    } else {
+        format!("Failed to {}: {}", action, message)
+    }
+}
+
+/// Open the default input device and start streaming mono samples to `on_samples`
+fn build_stream<F>(mut on_samples: F) -> Result<(cpal::Stream, u32), String>
+where
+    F: FnMut(&[f32]) + Send + 'static,
+{
+    let host = cpal::default_host();
+    let device = host.default_input_device()
+        .ok_or("No microphone found: connect an input device or select one in system settings")?;
+    let config = device.default_input_config()
+        .map_err(|e| describe_mic_error("get microphone config", e))?;
+
+    let sample_rate = config.sample_rate().0;
+    let channels = config.channels() as usize;
+    let stream_config: cpal::StreamConfig = config.config();
+    let err_fn = |e: cpal::StreamError| log::error!("Microphone stream error: {}", e);
+
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::F32 => device.build_input_stream(
+            &stream_config,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| on_samples(&downmix(data, channels)),
+            err_fn,
+            None,
+        ),
+        cpal::SampleFormat::I16 => device.build_input_stream(
+            &stream_config,
+            move |data: &[i16], _: &cpal::InputCallbackInfo| {
+                let samples: Vec<f32> = data.iter().map(|&s| s as f32 / 32768.0).collect();
+                on_samples(&downmix(&samples, channels))
+            },
+            err_fn,
+            None,
+        ),
+        cpal::SampleFormat::U16 => device.build_input_stream(
+            &stream_config,
+            move |data: &[u16], _: &cpal::InputCallbackInfo| {
+                let samples: Vec<f32> = data.iter().map(|&s| (s as f32 - 32768.0) / 32768.0).collect();
+                on_samples(&downmix(&samples, channels))
+            },
+            err_fn,
+            None,
+        ),
+        other => return Err(format!("Unsupported microphone sample format: {:?}", other)),
+    }
+    .map_err(|e| describe_mic_error("open microphone", e))?;
+
+    stream.play()
+        .map_err(|e| describe_mic_error("start microphone", e))?;
+
+    Ok((stream, sample_rate))
+}
+
+/// Start capturing on a dedicated thread. Returns the handle and the device sample rate.
+pub fn spawn_capture<F>(on_samples: F) -> Result<(CaptureHandle, u32), String>
+where
+    F: FnMut(&[f32]) + Send + 'static,
+{
+    let (stop_tx, stop_rx) = mpsc::channel::<()>();
+    let (ready_tx, ready_rx) = mpsc::channel::<Result<u32, String>>();
+
+    std::thread::spawn(move || match build_stream(on_samples) {
+        Ok((stream, sample_rate)) => {
+            let _ = ready_tx.send(Ok(sample_rate));
+            // Blocks until the handle is dropped
+            let _ = stop_rx.recv();
+            drop(stream);
+        }
+        Err(e) => {
+            let _ = ready_tx.send(Err(e));
+        }
+    });
+
+    let sample_rate = ready_rx.recv()
+        .map_err(|_| "Microphone thread exited unexpectedly".to_string())??;
+
+    Ok((CaptureHandle { _stop: stop_tx }, sample_rate))
+}
+
+/// Emit smoothed `mic-level` events until the returned handle is dropped
+pub fn start_level_monitor(
+    app: AppHandle,
+    smoother: Arc<Mutex<LevelSmoother>>,
+) -> Result<CaptureHandle, String> {
+    let mut pending: Vec<f32> = Vec::new();
+    let mut last_emit = Instant::now();
+
+    let (handle, _) = spawn_capture(move |samples| {
+        pending.extend_from_slice(samples);
+        if last_emit.elapsed() < LEVEL_INTERVAL {
+            return;
+        }
+
+        let raw = rms_level(&pending);
+        pending.clear();
+        last_emit = Instant::now();
+
+        let level = smoother.lock()
+            .map(|mut s| s.update(raw))
+            .unwrap_or(raw);
+        let _ = app.emit("mic-level", MicLevel { level, raw });
+    })?;
+
+    Ok(handle)
+}
+
+/// Longest fixed-duration recording allowed
+pub const MAX_RECORD_MS: u64 = 5 * 60 * 1000;
+
+/// Record mono f32 samples from the default microphone for `duration`.
+/// Returns the samples and the device sample rate.
+pub async fn record_for(duration: Duration) -> Result<(Vec<f32>, u32), String> {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let sink = buffer.clone();
+
+    let (handle, sample_rate) = spawn_capture(move |samples| {
+        if let Ok(mut buffer) = sink.lock() {
+            buffer.extend_from_slice(samples);
+        }
+    })?;
+
+    tokio::time::sleep(duration).await;
+    drop(handle);
+
+    let samples = std::mem::take(&mut *buffer.lock().map_err(|e| e.to_string())?);
+    Ok((samples, sample_rate))
+}
+
+/// An open-ended recording started by `start_recording`
+pub struct Recording {
+    _handle: CaptureHandle,
+    buffer: Arc<Mutex<Vec<i16>>>,
+    sample_rate: u32,
+}
+
+/// Start recording 16-bit mono samples from the default microphone
+///
+/// Samples beyond `MAX_RECORD_MS` are dropped so a forgotten recording
+/// cannot grow without bound.
+pub fn start_recording() -> Result<Recording, String> {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let sink = buffer.clone();
+    // The sample rate is only known once the stream is open; 0 means unlimited until then
+    let max_samples = Arc::new(AtomicUsize::new(0));
+    let limit = max_samples.clone();
+
+    let (handle, sample_rate) = spawn_capture(move |samples| {
+        let Ok(mut buffer) = sink.lock() else {
+            return;
+        };
+        let room = match limit.load(Ordering::Relaxed) {
+            0 => samples.len(),
+            max => max.saturating_sub(buffer.len()),
+        };
+        buffer.extend(samples.iter().take(room).map(|&s| f32_to_i16(s)));
+    })?;
+    max_samples.store((sample_rate as u64 * MAX_RECORD_MS / 1000) as usize, Ordering::Relaxed);
+
+    Ok(Recording { _handle: handle, buffer, sample_rate })
+}
+
+impl Recording {
+    /// Stop the microphone and return the samples and sample rate
+    pub fn finish(self) -> Result<(Vec<i16>, u32), String> {
+        let Recording { _handle: handle, buffer, sample_rate } = self;
+        drop(handle);
+        let samples = std::mem::take(&mut *buffer.lock().map_err(|e| e.to_string())?);
+        Ok((samples, sample_rate))
+    }
+}
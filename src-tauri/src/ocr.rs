@@ -0,0 +1,79 @@
+//! Text recognition for screenshots
+//!
+//! Runs Tesseract (through leptess) on a PNG image. Only built with the `ocr`
+//! feature; the Tesseract language data must be installed on the system.
+
+use serde::Serialize;
+
+/// Tesseract language used when none is given
+pub const DEFAULT_OCR_LANGUAGE: &str = "eng";
+
+/// A recognized word and where it is in the image, in pixels
+#[derive(Debug, Clone, Serialize)]
+pub struct OcrWord {
+    pub text: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Tesseract confidence (0-100)
+    pub confidence: f32,
+}
+
+/// Text recognized in an image
+#[derive(Debug, Clone, Serialize)]
+pub struct OcrResult {
+    pub text: String,
+    /// Word boxes, when requested
+    pub words: Option<Vec<OcrWord>>,
+}
+
+/// Recognize the text in a PNG image
+///
+/// `language` is a Tesseract code such as "eng" or "chi_sim" (several may be
+/// joined with '+'). With `include_boxes`, each word's bounding box is returned.
+pub fn recognize(png: &[u8], language: &str, include_boxes: bool) -> Result<OcrResult, String> {
+    let mut tesseract = leptess::LepTess::new(None, language)
+        .map_err(|e| format!("Failed to initialize Tesseract for '{}': {}", language, e))?;
+    tesseract.set_image_from_mem(png)
+        .map_err(|e| format!("Failed to load image for OCR: {}", e))?;
+
+    let text = tesseract.get_utf8_text()
+        .map_err(|e| format!("Failed to recognize text: {}", e))?;
+    let words = if include_boxes {
+        let tsv = tesseract.get_tsv_text(0)
+            .map_err(|e| format!("Failed to read word boxes: {}", e))?;
+        Some(parse_tsv_words(&tsv))
+    } else {
+        None
+    };
+
+    Ok(OcrResult { text: text.trim().to_string(), words })
+}
+
+/// Word rows of Tesseract TSV output
+///
+/// Columns: level, page, block, paragraph, line, word, left, top, width,
+/// height, confidence, text. Level 5 rows are words.
+fn parse_tsv_words(tsv: &str) -> Vec<OcrWord> {
+    tsv.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.splitn(12, '\t').collect();
+            if fields.len() < 12 || fields[0] != "5" {
+                return None;
+            }
+            let text = fields[11].trim();
+            if text.is_empty() {
+                return None;
+            }
+            Some(OcrWord {
+                text: text.to_string(),
+                x: fields[6].parse().ok()?,
+                y: fields[7].parse().ok()?,
+                width: fields[8].parse().ok()?,
+                height: fields[9].parse().ok()?,
+                confidence: fields[10].parse().ok()?,
+            })
+        })
+        .collect()
+}
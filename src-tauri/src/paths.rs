@@ -0,0 +1,11 @@
+//! Locations of app data on disk
+
+use std::path::PathBuf;
+use once_cell::sync::Lazy;
+
+/// App data directory (settings, conversations and models)
+pub static APP_DATA_DIR: Lazy<PathBuf> = Lazy::new(|| {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("assidenter")
+});
@@ -1,25 +1,159 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use serde::{Deserialize, Serialize};
 use reqwest::Client;
+use super::{build_client, ping_url, with_auth, read_json, request_error, status_error, RateLimiter, VadConfig, DEFAULT_TIMEOUT_SECS};
+use super::retry::{send_with_retry, RetryBudget};
+use crate::text::has_sentence_punctuation;
+use crate::trace;
 use base64::{Engine as _, engine::general_purpose::STANDARD};
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{header::{AUTHORIZATION, USER_AGENT}, HeaderName, HeaderValue};
+use tokio_tungstenite::tungstenite::Message;
+
+type WsStream = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Audio left after trimming silence, and the seconds cut from its start
+type TrimmedAudio<'a> = (Cow<'a, [u8]>, f64);
+
+/// Length of the leading slice used to detect the language (`detect_once`)
+const DETECTION_SLICE_SECS: f64 = 3.0;
+
+/// How missing punctuation is restored in transcripts
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PunctuationMethod {
+    /// Local capitalization and terminal punctuation rules
+    #[default]
+    Heuristic,
+    /// Ask an OpenAI-compatible LLM to punctuate the transcript
+    Llm,
+}
 
 /// WhisperLiveKit ASR service configuration
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WhisperConfig {
     pub server_url: String,
+    /// WebSocket endpoint for streaming transcription (derived from
+    /// `server_url` when unset)
+    #[serde(default)]
+    pub ws_url: Option<String>,
     pub language: String,
     pub model: String,
+    /// User-Agent header (defaults to assidenter/<version>)
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// Sent as `Authorization: Bearer <key>`; never logged or sent to the webview
+    #[serde(default, skip_serializing)]
+    pub api_key: Option<String>,
+    /// Additional headers sent with every request
+    #[serde(default)]
+    pub extra_headers: HashMap<String, String>,
+    /// Request timeout in seconds (0 = none)
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Restore punctuation/capitalization when the server returns raw text
+    #[serde(default)]
+    pub restore_punctuation: bool,
+    #[serde(default)]
+    pub punctuation_method: PunctuationMethod,
+    /// LLM server used by `PunctuationMethod::Llm`
+    #[serde(default)]
+    pub punctuation_llm_url: String,
+    #[serde(default)]
+    pub punctuation_llm_model: String,
+    /// With `language: "auto"`, detect the language from a short leading
+    /// slice and force it for the full transcription
+    #[serde(default)]
+    pub detect_once: bool,
+    /// Reuse the first detected language for the rest of the session
+    #[serde(default)]
+    pub sticky_language: bool,
+    /// Gzip transcription request bodies (the server must accept
+    /// `Content-Encoding: gzip`; falls back to plain bodies if rejected)
+    #[serde(default)]
+    pub compress_request: bool,
+    /// Client-side request limit per minute (0 = unlimited)
+    #[serde(default)]
+    pub requests_per_minute: u32,
+    /// Fail requests over the limit instead of waiting for budget
+    #[serde(default)]
+    pub rate_limit_fail_fast: bool,
+    /// Retries of a request on 5xx and connection errors
+    #[serde(default = "super::retry::default_http_retries")]
+    pub max_retries: u32,
+    /// Return a canned transcript instead of contacting the server (for offline development)
+    #[serde(default)]
+    pub mock: bool,
+    /// Silence trimming before transcription
+    #[serde(default)]
+    pub vad: VadConfig,
+    /// Bring the input peak to -3 dBFS before transcription, for quiet microphones
+    #[serde(default)]
+    pub normalize: bool,
+}
+
+fn default_timeout_secs() -> u64 {
+    DEFAULT_TIMEOUT_SECS
 }
 
 impl Default for WhisperConfig {
     fn default() -> Self {
         Self {
             server_url: "http://localhost:9090".to_string(),
+            ws_url: None,
             language: "auto".to_string(),
             model: "whisper-large-v3".to_string(),
+            user_agent: None,
+            api_key: None,
+            extra_headers: HashMap::new(),
+            timeout_secs: default_timeout_secs(),
+            restore_punctuation: false,
+            punctuation_method: PunctuationMethod::Heuristic,
+            punctuation_llm_url: "http://localhost:8080".to_string(),
+            punctuation_llm_model: "qwen-0.5b".to_string(),
+            detect_once: false,
+            sticky_language: false,
+            compress_request: false,
+            requests_per_minute: 0,
+            rate_limit_fail_fast: false,
+            max_retries: super::retry::DEFAULT_HTTP_RETRIES,
+            mock: false,
+            vad: VadConfig::default(),
+            normalize: false,
         }
     }
 }
 
+/// Transcript returned in mock mode
+const MOCK_TRANSCRIPT: &str = "This is a mock transcription.";
+
+/// Timed segment of a transcription
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptSegment {
+    /// Start time in seconds
+    pub start: f64,
+    /// End time in seconds
+    pub end: f64,
+    pub text: String,
+}
+
+/// Timing of a single transcribed word
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WordTiming {
+    pub word: String,
+    /// Start time in seconds
+    pub start: f64,
+    /// End time in seconds
+    pub end: f64,
+}
+
 /// ASR transcription result
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TranscriptionResult {
@@ -27,100 +161,579 @@ pub struct TranscriptionResult {
     pub language: Option<String>,
     pub duration: Option<f64>,
     pub is_final: bool,
+    /// Timed segments, when the server reports them
+    #[serde(default)]
+    pub segments: Vec<TranscriptSegment>,
+    /// Per-word timings, when the server reports them
+    #[serde(default)]
+    pub words: Option<Vec<WordTiming>>,
+}
+
+/// Parse one word entry (`word` or `text`, with `start` and `end`)
+fn parse_word(word: &serde_json::Value) -> Option<WordTiming> {
+    let text = word["word"].as_str().or_else(|| word["text"].as_str())?.trim();
+    if text.is_empty() {
+        return None;
+    }
+    Some(WordTiming {
+        word: text.to_string(),
+        start: word["start"].as_f64()?,
+        end: word["end"].as_f64()?,
+    })
+}
+
+/// Word timings from a top-level `words` array, or from each segment's `words`
+fn parse_words(result: &serde_json::Value) -> Option<Vec<WordTiming>> {
+    let words: Vec<WordTiming> = match result["words"].as_array() {
+        Some(words) => words.iter().filter_map(parse_word).collect(),
+        None => result["segments"]
+            .as_array()?
+            .iter()
+            .filter_map(|segment| segment["words"].as_array())
+            .flatten()
+            .filter_map(parse_word)
+            .collect(),
+    };
+    (!words.is_empty()).then_some(words)
+}
+
+/// Gzip-compress a request body
+fn gzip(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    encoder.write_all(data)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| format!("Failed to compress request: {}", e))
+}
+
+/// Update from a streaming transcription session
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamingTranscript {
+    /// Full transcript of the utterance so far
+    pub text: String,
+    /// The server will not revise this text any further
+    pub stable: bool,
+    /// End of utterance; `text` is the final transcription
+    pub is_final: bool,
+}
+
+/// Sending half of a streaming transcription session
+pub struct AsrStreamSender {
+    sink: SplitSink<WsStream, Message>,
+}
+
+impl AsrStreamSender {
+    /// Send a chunk of 16-bit mono PCM
+    pub async fn send_samples(&mut self, samples: &[i16]) -> Result<(), String> {
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.sink
+            .send(Message::Binary(bytes))
+            .await
+            .map_err(|e| format!("Failed to send audio to ASR stream: {}", e))
+    }
+
+    /// Tell the server no more audio is coming
+    pub async fn finish(&mut self) -> Result<(), String> {
+        self.sink
+            .send(Message::Text(serde_json::json!({ "type": "end" }).to_string()))
+            .await
+            .map_err(|e| format!("Failed to end ASR stream: {}", e))
+    }
+}
+
+/// Receiving half of a streaming transcription session
+pub struct AsrStreamReceiver {
+    stream: SplitStream<WsStream>,
+}
+
+impl AsrStreamReceiver {
+    /// Next transcript update, or None once the server closes the stream
+    pub async fn next(&mut self) -> Option<Result<StreamingTranscript, String>> {
+        while let Some(message) = self.stream.next().await {
+            let text = match message {
+                Ok(Message::Text(text)) => text,
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => continue,
+                Err(e) => return Some(Err(format!("ASR stream error: {}", e))),
+            };
+            let Ok(json) = serde_json::from_str::<serde_json::Value>(&text) else {
+                log::warn!("Ignoring malformed ASR stream message: {}", text);
+                continue;
+            };
+            if let Some(error) = json["error"].as_str() {
+                return Some(Err(format!("ASR stream error: {}", error)));
+            }
+            let is_final = json["is_final"].as_bool().unwrap_or(false);
+            return Some(Ok(StreamingTranscript {
+                text: json["text"].as_str().unwrap_or("").trim().to_string(),
+                stable: is_final || json["stable"].as_bool().unwrap_or(false),
+                is_final,
+            }));
+        }
+        None
+    }
 }
 
 /// WhisperLiveKit ASR service client
+///
+/// Clones share the language cache, compression state, rate limiter and
+/// retry budget, so requests from a clone count against the same limits.
+#[derive(Clone)]
 pub struct WhisperLiveKit {
     config: WhisperConfig,
     client: Client,
+    /// Language cached by `sticky_language`
+    session_language: Arc<Mutex<Option<String>>>,
+    /// Set when the server rejected a compressed body
+    compression_rejected: Arc<AtomicBool>,
+    rate_limiter: Arc<RateLimiter>,
+    /// Charged by retried requests; shared with the pipeline
+    retry_budget: Arc<RetryBudget>,
 }
 
 impl WhisperLiveKit {
     pub fn new(config: WhisperConfig) -> Self {
+        let client = build_client(config.user_agent.as_deref(), config.timeout_secs);
+        let rate_limiter = RateLimiter::new("asr", config.requests_per_minute, config.rate_limit_fail_fast);
         Self {
             config,
-            client: Client::new(),
+            client,
+            session_language: Arc::new(Mutex::new(None)),
+            compression_rejected: Arc::new(AtomicBool::new(false)),
+            rate_limiter: Arc::new(rate_limiter),
+            retry_budget: Arc::new(RetryBudget::default()),
         }
     }
 
     /// Transcribe WAV audio data to text
+    ///
+    /// Audio that is not 16 kHz mono 16-bit PCM is converted first.
     pub async fn transcribe_wav(&self, wav_data: &[u8]) -> Result<TranscriptionResult, String> {
+        if self.config.mock {
+            return Ok(TranscriptionResult {
+                text: MOCK_TRANSCRIPT.to_string(),
+                language: Some("en".to_string()),
+                duration: crate::audio::parse_wav(wav_data).ok().map(|wav| wav.duration()),
+                is_final: true,
+                segments: Vec::new(),
+                words: None,
+            });
+        }
+        let wav_data = crate::audio::prepare_asr_wav(wav_data)
+            .map_err(|e| format!("Invalid audio for transcription: {}", e))?;
+        let Some((wav_data, offset)) = self.trim_silence(wav_data)? else {
+            log::info!("No speech detected, skipping transcription");
+            return Ok(TranscriptionResult {
+                text: String::new(),
+                language: None,
+                duration: None,
+                is_final: true,
+                segments: Vec::new(),
+                words: None,
+            });
+        };
+        // After trimming, so the gain cannot lift background noise into speech
+        let wav_data = self.normalize_input(wav_data)?;
+        let language = self.resolve_language(&wav_data).await;
+        let result = self.request_transcription(&wav_data, &language).await?;
+
+        let mut text = result["text"].as_str().unwrap_or("").to_string();
+        let language = result["language"].as_str().map(|s| s.to_string())
+            .or_else(|| Some(language).filter(|l| l != "auto"));
+
+        if self.config.restore_punctuation && !text.trim().is_empty() && !has_sentence_punctuation(&text) {
+            let lang = language.as_deref().unwrap_or(&self.config.language);
+            text = self.restore_punctuation(&text, lang).await;
+        }
+
+        let segments = result["segments"]
+            .as_array()
+            .map(|segments| {
+                segments
+                    .iter()
+                    .filter_map(|segment| {
+                        Some(TranscriptSegment {
+                            start: segment["start"].as_f64()? + offset,
+                            end: segment["end"].as_f64()? + offset,
+                            text: segment["text"].as_str()?.trim().to_string(),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(TranscriptionResult {
+            text,
+            language,
+            duration: result["duration"].as_f64(),
+            is_final: true,
+            segments,
+            words: parse_words(&result).map(|words| {
+                words
+                    .into_iter()
+                    .map(|word| WordTiming { start: word.start + offset, end: word.end + offset, ..word })
+                    .collect()
+            }),
+        })
+    }
+
+    /// Apply gain to 16 kHz mono WAV so its peak reaches `ASR_PEAK_TARGET_DB`, when enabled
+    fn normalize_input<'a>(&self, wav_data: Cow<'a, [u8]>) -> Result<Cow<'a, [u8]>, String> {
+        if !self.config.normalize {
+            return Ok(wav_data);
+        }
+        let wav = crate::audio::parse_wav(&wav_data)?;
+        let normalized = crate::audio::normalize_peak(&wav.samples, crate::audio::ASR_PEAK_TARGET_DB);
+        Ok(Cow::Owned(crate::audio::encode_wav(&normalized, wav.info.sample_rate, 1)))
+    }
+
+    /// Cut leading and trailing silence from 16 kHz mono WAV when VAD is enabled
+    ///
+    /// Returns the audio to send and the seconds cut from its start (so
+    /// timestamps can be shifted back), or None when the clip has no speech
+    /// and `skip_silent` is set.
+    fn trim_silence<'a>(&self, wav_data: Cow<'a, [u8]>) -> Result<Option<TrimmedAudio<'a>>, String> {
+        let vad = &self.config.vad;
+        if !vad.enabled {
+            return Ok(Some((wav_data, 0.0)));
+        }
+        let wav = crate::audio::parse_wav(&wav_data)?;
+        let sample_rate = wav.info.sample_rate.max(1);
+        match super::vad::speech_range(&wav.samples, sample_rate, vad) {
+            None if vad.skip_silent => Ok(None),
+            Some(range) if range.len() < wav.samples.len() => {
+                let offset = range.start as f64 / sample_rate as f64;
+                log::info!(
+                    "VAD trimmed {:.2}s of silence",
+                    (wav.samples.len() - range.len()) as f64 / sample_rate as f64
+                );
+                Ok(Some((Cow::Owned(crate::audio::encode_wav(&wav.samples[range], sample_rate, 1)), offset)))
+            }
+            _ => Ok(Some((wav_data, 0.0))),
+        }
+    }
+
+    /// Language to request for `wav_data`
+    ///
+    /// An explicit language is used as-is. For "auto" with `detect_once`, the
+    /// language is detected from a short leading slice (or taken from the
+    /// session cache when `sticky_language` is set) so Whisper cannot switch
+    /// languages between chunks of the full recording.
+    async fn resolve_language(&self, wav_data: &[u8]) -> String {
+        if self.config.language != "auto" || !self.config.detect_once {
+            return self.config.language.clone();
+        }
+
+        if self.config.sticky_language {
+            if let Some(language) = self.session_language.lock().ok().and_then(|l| l.clone()) {
+                return language;
+            }
+        }
+
+        match self.detect_language(wav_data).await {
+            Ok(Some(language)) => {
+                log::info!("Detected language from leading slice: {}", language);
+                if self.config.sticky_language {
+                    if let Ok(mut cached) = self.session_language.lock() {
+                        *cached = Some(language.clone());
+                    }
+                }
+                language
+            }
+            Ok(None) => "auto".to_string(),
+            Err(e) => {
+                log::warn!("Language detection failed, falling back to auto: {}", e);
+                "auto".to_string()
+            }
+        }
+    }
+
+    /// Transcribe the first few seconds of `wav_data` and return the reported language
+    async fn detect_language(&self, wav_data: &[u8]) -> Result<Option<String>, String> {
+        let wav = crate::audio::parse_wav(wav_data)?;
+        let channels = wav.info.channels.max(1) as usize;
+        let frames = (DETECTION_SLICE_SECS * wav.info.sample_rate as f64) as usize;
+        let slice = &wav.samples[..wav.samples.len().min(frames * channels)];
+        let slice_wav = crate::audio::encode_wav(slice, wav.info.sample_rate, wav.info.channels);
+
+        let result = self.request_transcription(&slice_wav, "auto").await?;
+        Ok(result["language"]
+            .as_str()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && *l != "auto")
+            .map(str::to_string))
+    }
+
+    /// Send WAV data to the server and return the raw JSON response
+    async fn request_transcription(&self, wav_data: &[u8], language: &str) -> Result<serde_json::Value, String> {
         // Encode as base64
         let audio_base64 = STANDARD.encode(wav_data);
         
         // Create the request payload
         let payload = serde_json::json!({
             "audio": audio_base64,
-            "language": self.config.language,
+            "language": language,
             "model": self.config.model,
             "format": "wav"
         });
 
-        // Send request to WhisperLiveKit server
+        let started = Instant::now();
+        let result = async {
+            let body = serde_json::to_vec(&payload)
+                .map_err(|e| format!("Failed to serialize transcription request: {}", e))?;
+
+            if self.config.compress_request && !self.compression_rejected.load(Ordering::SeqCst) {
+                let response = self.send_transcription_body(gzip(&body)?, true).await?;
+                // 415 Unsupported Media Type or 400 means the server cannot read gzip bodies
+                let status = response.status().as_u16();
+                if status == 415 || status == 400 {
+                    log::warn!("ASR server rejected compressed request ({}), sending uncompressed", status);
+                    self.compression_rejected.store(true, Ordering::SeqCst);
+                } else {
+                    if !response.status().is_success() {
+                        return Err(status_error("Transcription", response).await);
+                    }
+                    return read_json("transcription", response).await;
+                }
+            }
+
+            // Send request to WhisperLiveKit server
+            let response = self.send_transcription_body(body, false).await?;
+
+            if !response.status().is_success() {
+                return Err(status_error("Transcription", response).await);
+            }
+
+            read_json("transcription", response).await
+        }.await;
+
+        let url = format!("{}/transcribe", self.config.server_url);
+        trace::record_exchange("asr", &url, &payload, started, || result.clone());
+        result
+    }
+
+    /// POST a serialized JSON body to the transcription endpoint
+    async fn send_transcription_body(&self, body: Vec<u8>, gzipped: bool) -> Result<reqwest::Response, String> {
+        self.rate_limiter.acquire().await?;
+        let mut request = self.authorize(self.client.post(format!("{}/transcribe", self.config.server_url)))
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        if gzipped {
+            request = request.header(reqwest::header::CONTENT_ENCODING, "gzip");
+        }
+        send_with_retry("ASR", self.config.max_retries, &self.retry_budget, request.body(body))
+            .await
+            .map_err(|e| request_error("ASR", self.config.timeout_secs, "Failed to send transcription request", e))
+    }
+
+    /// WebSocket endpoint for streaming: `ws_url`, or `server_url` with a ws scheme
+    fn stream_url(&self) -> String {
+        match self.config.ws_url.as_deref().map(str::trim).filter(|url| !url.is_empty()) {
+            Some(url) => url.to_string(),
+            None => format!(
+                "{}/asr",
+                self.config.server_url
+                    .replacen("https://", "wss://", 1)
+                    .replacen("http://", "ws://", 1)
+            ),
+        }
+    }
+
+    /// Stream 16-bit mono PCM chunks to the server and report partial results
+    ///
+    /// `on_result` is called for every update, the last one with `is_final`
+    /// set. Returns the final transcription. If the server closes the stream
+    /// without a final update, the last partial result is treated as final.
+    pub async fn transcribe_stream<S, F>(
+        &self,
+        mut audio: S,
+        sample_rate: u32,
+        mut on_result: F,
+    ) -> Result<TranscriptionResult, String>
+    where
+        S: futures::Stream<Item = Vec<i16>> + Unpin,
+        F: FnMut(TranscriptionResult),
+    {
+        let (mut sender, mut receiver) = self.open_stream(sample_rate).await?;
+        let language = self.session_language()
+            .or_else(|| Some(self.config.language.clone()).filter(|l| l != "auto"));
+
+        let send = async {
+            while let Some(chunk) = audio.next().await {
+                sender.send_samples(&chunk).await?;
+            }
+            sender.finish().await
+        };
+
+        let receive = async {
+            let mut last_text = String::new();
+            while let Some(update) = receiver.next().await {
+                let update = update?;
+                let result = TranscriptionResult {
+                    text: update.text,
+                    language: language.clone(),
+                    duration: None,
+                    is_final: update.is_final,
+                    segments: Vec::new(),
+                    words: None,
+                };
+                on_result(result.clone());
+                if result.is_final {
+                    return Ok(result);
+                }
+                last_text = result.text;
+            }
+
+            let result = TranscriptionResult {
+                text: last_text,
+                language: language.clone(),
+                duration: None,
+                is_final: true,
+                segments: Vec::new(),
+                words: None,
+            };
+            on_result(result.clone());
+            Ok(result)
+        };
+
+        tokio::pin!(send, receive);
+        let mut sending = true;
+        loop {
+            tokio::select! {
+                sent = &mut send, if sending => {
+                    sending = false;
+                    sent?;
+                }
+                result = &mut receive => return result,
+            }
+        }
+    }
+
+    /// Open a WebSocket transcription session for 16-bit mono PCM at `sample_rate`
+    ///
+    /// The server sends interim transcripts as audio arrives and a final one
+    /// when it detects the end of the utterance.
+    pub async fn open_stream(&self, sample_rate: u32) -> Result<(AsrStreamSender, AsrStreamReceiver), String> {
+        let mut request = self.stream_url()
+            .into_client_request()
+            .map_err(|e| format!("Invalid ASR stream URL: {}", e))?;
+        let user_agent = self.config.user_agent.as_deref().unwrap_or(super::DEFAULT_USER_AGENT);
+        if let Ok(value) = HeaderValue::from_str(user_agent) {
+            request.headers_mut().insert(USER_AGENT, value);
+        }
+        for (name, value) in &self.config.extra_headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| format!("Invalid ASR header name '{}': {}", name, e))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| format!("Invalid value for ASR header '{}': {}", name, e))?;
+            request.headers_mut().insert(name, value);
+        }
+        if let Some(key) = self.api_key() {
+            let mut value = HeaderValue::from_str(&format!("Bearer {}", key))
+                .map_err(|_| "ASR API key contains characters not allowed in a header".to_string())?;
+            value.set_sensitive(true);
+            request.headers_mut().insert(AUTHORIZATION, value);
+        }
+
+        self.rate_limiter.acquire().await?;
+        let connect = tokio_tungstenite::connect_async(request);
+        let connected = if self.config.timeout_secs > 0 {
+            tokio::time::timeout(std::time::Duration::from_secs(self.config.timeout_secs), connect)
+                .await
+                .map_err(|_| format!("ASR request timed out after {}s", self.config.timeout_secs))?
+        } else {
+            connect.await
+        };
+        let (socket, _) = connected
+            .map_err(|e| format!("Failed to connect to ASR stream: {}", e))?;
+        let (mut sink, stream) = socket.split();
+
+        let language = self.session_language().unwrap_or_else(|| self.config.language.clone());
+        let config = serde_json::json!({
+            "type": "config",
+            "language": language,
+            "model": self.config.model,
+            "sample_rate": sample_rate,
+            "format": "pcm_s16le"
+        });
+        sink.send(Message::Text(config.to_string()))
+            .await
+            .map_err(|e| format!("Failed to configure ASR stream: {}", e))?;
+
+        Ok((AsrStreamSender { sink }, AsrStreamReceiver { stream }))
+    }
+
+    /// Restore punctuation using the configured method, falling back to the
+    /// heuristic if the LLM call fails
+    async fn restore_punctuation(&self, text: &str, language: &str) -> String {
+        if self.config.punctuation_method == PunctuationMethod::Llm {
+            match self.punctuate_with_llm(text).await {
+                Ok(punctuated) if !punctuated.trim().is_empty() => return punctuated,
+                Ok(_) => log::warn!("LLM punctuation returned empty text, using heuristic"),
+                Err(e) => log::warn!("LLM punctuation failed, using heuristic: {}", e),
+            }
+        }
+        crate::text::restore_punctuation(text, language)
+    }
+
+    /// Ask the LLM server to add punctuation without changing the words
+    async fn punctuate_with_llm(&self, text: &str) -> Result<String, String> {
+        let payload = serde_json::json!({
+            "model": self.config.punctuation_llm_model,
+            "messages": [
+                {
+                    "role": "system",
+                    "content": "Restore punctuation and capitalization to the user's transcript. Do not add, remove or change any words. Reply with the corrected transcript only."
+                },
+                { "role": "user", "content": text }
+            ],
+            "temperature": 0.0,
+            "max_tokens": (text.len() / 2).max(64),
+            "stream": false
+        });
+
         let response = self.client
-            .post(format!("{}/transcribe", self.config.server_url))
+            .post(format!("{}/v1/chat/completions", self.config.punctuation_llm_url))
             .json(&payload)
             .send()
             .await
-            .map_err(|e| format!("Failed to send transcription request: {}", e))?;
+            .map_err(|e| request_error("Punctuation", self.config.timeout_secs, "Failed to send punctuation request", e))?;
 
         if !response.status().is_success() {
-            return Err(format!("Transcription failed with status: {}", response.status()));
+            return Err(status_error("Punctuation request", response).await);
         }
 
-        let result: serde_json::Value = response
-            .json()
-            .await
-            .map_err(|e| format!("Failed to parse transcription response: {}", e))?;
+        let result = read_json("punctuation", response).await?;
 
-        Ok(TranscriptionResult {
-            text: result["text"].as_str().unwrap_or("").to_string(),
-            language: result["language"].as_str().map(|s| s.to_string()),
-            duration: result["duration"].as_f64(),
-            is_final: true,
-        })
+        Ok(result["choices"][0]["message"]["content"]
+            .as_str()
+            .unwrap_or("")
+            .trim()
+            .to_string())
     }
 
-    /// Transcribe audio samples to text
+    /// Transcribe mono audio samples to text
     pub async fn transcribe(&self, samples: &[i16], sample_rate: u32) -> Result<TranscriptionResult, String> {
-        // Convert samples to WAV format
-        let wav_data = self.samples_to_wav(samples, sample_rate)?;
+        self.transcribe_channels(samples, sample_rate, 1).await
+    }
+
+    /// Transcribe interleaved samples with `channels` channels (e.g. stereo
+    /// from a USB interface)
+    ///
+    /// Multi-channel audio is downmixed to mono by averaging before upload.
+    pub async fn transcribe_channels(&self, samples: &[i16], sample_rate: u32, channels: u16) -> Result<TranscriptionResult, String> {
+        if channels == 0 {
+            return Err("Channel count must be at least 1".to_string());
+        }
+        let mono = crate::audio::convert_channels(samples, channels, 1);
+        let wav_data = self.samples_to_wav(&mono, sample_rate, 1)?;
         self.transcribe_wav(&wav_data).await
     }
 
-    /// Convert i16 samples to WAV format bytes
-    fn samples_to_wav(&self, samples: &[i16], sample_rate: u32) -> Result<Vec<u8>, String> {
-        let mut buffer = Vec::new();
-        
-        // WAV header
-        let data_size = (samples.len() * 2) as u32;
-        let file_size = data_size + 36;
-        
-        // RIFF header
-        buffer.extend_from_slice(b"RIFF");
-        buffer.extend_from_slice(&file_size.to_le_bytes());
-        buffer.extend_from_slice(b"WAVE");
-        
-        // fmt subchunk
-        buffer.extend_from_slice(b"fmt ");
-        buffer.extend_from_slice(&16u32.to_le_bytes()); // Subchunk1Size for PCM
-        buffer.extend_from_slice(&1u16.to_le_bytes());   // AudioFormat (1 = PCM)
-        buffer.extend_from_slice(&1u16.to_le_bytes());   // NumChannels
-        buffer.extend_from_slice(&sample_rate.to_le_bytes()); // SampleRate
-        buffer.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // ByteRate
-        buffer.extend_from_slice(&2u16.to_le_bytes());   // BlockAlign
-        buffer.extend_from_slice(&16u16.to_le_bytes());  // BitsPerSample
-        
-        // data subchunk
-        buffer.extend_from_slice(b"data");
-        buffer.extend_from_slice(&data_size.to_le_bytes());
-        
-        // Audio data
-        for sample in samples {
-            buffer.extend_from_slice(&sample.to_le_bytes());
+    /// Convert interleaved i16 samples to WAV format bytes
+    fn samples_to_wav(&self, samples: &[i16], sample_rate: u32, channels: u16) -> Result<Vec<u8>, String> {
+        if samples.len() % channels.max(1) as usize != 0 {
+            return Err(format!("{} samples do not divide into {} channels", samples.len(), channels));
         }
-        
-        Ok(buffer)
+        Ok(crate::audio::encode_wav(samples, sample_rate, channels))
     }
 
     /// Get current configuration
@@ -128,8 +741,192 @@ impl WhisperLiveKit {
         &self.config
     }
 
+    /// Restore the default configuration, clearing any cached session language
+    pub fn reset_config(&mut self) {
+        *self = Self::new(WhisperConfig::default());
+    }
+
+    /// Check that the server is reachable via `/health`
+    ///
+    /// The request is built up front, so the check can run without the client.
+    pub fn ping(&self) -> impl std::future::Future<Output = Result<bool, String>> {
+        let request = (!self.config.mock)
+            .then(|| self.authorize(self.client.get(format!("{}/health", self.config.server_url))));
+        ping_url(request, "ASR")
+    }
+
     /// Update server URL
     pub fn set_server_url(&mut self, url: String) {
         self.config.server_url = url;
     }
+
+    /// Update the streaming endpoint (None derives it from the server URL)
+    pub fn set_ws_url(&mut self, url: Option<String>) {
+        self.config.ws_url = url;
+    }
+
+    /// Configure one-shot language detection for `language: "auto"`.
+    /// Always clears the cached session language.
+    pub fn set_language_detection(&mut self, detect_once: bool, sticky_language: bool) {
+        self.config.detect_once = detect_once;
+        self.config.sticky_language = sticky_language;
+        if let Ok(mut cached) = self.session_language.lock() {
+            *cached = None;
+        }
+    }
+
+    /// Force the transcription language ("auto" restores detection).
+    /// Always clears the cached session language.
+    pub fn set_language(&mut self, language: String) {
+        self.config.language = language;
+        if let Ok(mut cached) = self.session_language.lock() {
+            *cached = None;
+        }
+    }
+
+    /// Language cached for this session by `sticky_language`, if any
+    pub fn session_language(&self) -> Option<String> {
+        self.session_language.lock().ok().and_then(|l| l.clone())
+    }
+
+    /// Share the pipeline's retry budget with this client's requests
+    pub fn set_retry_budget(&mut self, budget: Arc<RetryBudget>) {
+        self.retry_budget = budget;
+    }
+
+    /// Enable or disable gzip request bodies, forgetting any earlier rejection
+    pub fn set_compress_request(&mut self, enabled: bool) {
+        self.config.compress_request = enabled;
+        self.compression_rejected.store(false, Ordering::SeqCst);
+    }
+
+    /// Limit requests to the ASR server per minute (0 = unlimited)
+    pub fn set_rate_limit(&mut self, requests_per_minute: u32, fail_fast: bool) {
+        self.config.requests_per_minute = requests_per_minute;
+        self.config.rate_limit_fail_fast = fail_fast;
+        self.rate_limiter.configure(requests_per_minute, fail_fast);
+    }
+
+    /// Set how many times a request is retried on 5xx and connection errors
+    pub fn set_max_retries(&mut self, max_retries: u32) {
+        self.config.max_retries = max_retries;
+    }
+
+    /// Configure punctuation restoration
+    pub fn set_punctuation(&mut self, enabled: bool, method: PunctuationMethod) {
+        self.config.restore_punctuation = enabled;
+        self.config.punctuation_method = method;
+    }
+
+    /// Set the LLM server used for LLM-assisted punctuation
+    pub fn set_punctuation_llm(&mut self, url: String, model: String) {
+        self.config.punctuation_llm_url = url;
+        self.config.punctuation_llm_model = model;
+    }
+
+    /// Update the request timeout in seconds (0 disables it)
+    pub fn set_timeout(&mut self, timeout_secs: u64) {
+        self.config.timeout_secs = timeout_secs;
+        self.client = build_client(self.config.user_agent.as_deref(), timeout_secs);
+    }
+
+    /// Enable or disable mock mode
+    pub fn set_mock(&mut self, enabled: bool) {
+        self.config.mock = enabled;
+    }
+
+    /// Enable or disable input peak normalization before transcription
+    pub fn set_normalize(&mut self, enabled: bool) {
+        self.config.normalize = enabled;
+    }
+
+    /// Configure silence trimming before transcription
+    pub fn set_vad(&mut self, vad: VadConfig) {
+        self.config.vad = vad;
+    }
+
+    /// Set the API key sent as a bearer token (None or empty disables it)
+    pub fn set_api_key(&mut self, api_key: Option<String>) {
+        self.config.api_key = api_key;
+    }
+
+    /// Replace the additional headers sent with every request
+    pub fn set_extra_headers(&mut self, headers: HashMap<String, String>) {
+        self.config.extra_headers = headers;
+    }
+
+    /// Configured API key, if any
+    fn api_key(&self) -> Option<&str> {
+        self.config.api_key.as_deref().map(str::trim).filter(|key| !key.is_empty())
+    }
+
+    /// Attach the configured credentials to a request to the ASR server
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        with_auth(request, self.api_key(), &self.config.extra_headers)
+    }
+
+    /// Update the User-Agent header (None restores the default)
+    pub fn set_user_agent(&mut self, user_agent: Option<String>) {
+        self.client = build_client(user_agent.as_deref(), self.config.timeout_secs);
+        self.config.user_agent = user_agent;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn gzip_round_trips_request_bodies() {
+        let body: Vec<u8> = (0..10_000u32).flat_map(|i| ((i % 300) as i16).to_le_bytes()).collect();
+        let compressed = gzip(&body).unwrap();
+        assert!(compressed.len() < body.len());
+        assert_eq!(&compressed[..2], &[0x1f, 0x8b]);
+
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, body);
+    }
+
+    fn timings(result: serde_json::Value) -> Option<Vec<(String, f64, f64)>> {
+        parse_words(&result).map(|words| words.into_iter().map(|w| (w.word, w.start, w.end)).collect())
+    }
+
+    #[test]
+    fn parses_top_level_words() {
+        let result = serde_json::json!({"words": [
+            {"word": " Hello", "start": 0.0, "end": 0.4},
+            {"text": "world", "start": 0.5, "end": 0.9},
+        ]});
+        assert_eq!(timings(result), Some(vec![("Hello".to_string(), 0.0, 0.4), ("world".to_string(), 0.5, 0.9)]));
+    }
+
+    #[test]
+    fn parses_words_nested_in_segments() {
+        let result = serde_json::json!({"segments": [
+            {"words": [{"word": "one", "start": 0.0, "end": 0.3}]},
+            {"text": "no timings"},
+            {"words": [{"word": "two", "start": 1.0, "end": 1.2}]},
+        ]});
+        assert_eq!(timings(result), Some(vec![("one".to_string(), 0.0, 0.3), ("two".to_string(), 1.0, 1.2)]));
+    }
+
+    #[test]
+    fn skips_words_without_text_or_timings() {
+        let result = serde_json::json!({"words": [
+            {"word": " ", "start": 0.0, "end": 0.1},
+            {"word": "late", "start": 0.2},
+            {"word": "kept", "start": 0.3, "end": 0.5},
+        ]});
+        assert_eq!(timings(result), Some(vec![("kept".to_string(), 0.3, 0.5)]));
+    }
+
+    #[test]
+    fn no_words_is_none() {
+        assert_eq!(timings(serde_json::json!({"text": "hi"})), None);
+        assert_eq!(timings(serde_json::json!({"words": []})), None);
+    }
 }
@@ -5,7 +5,10 @@
 
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::sync::Arc;
+use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
 use super::{MODEL_DIR, WHISPER_MODEL_FILE};
+use crate::services::asr::{TranscriptSegment, WordTiming};
 
 /// Embedded ASR configuration
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -30,23 +33,92 @@ pub struct TranscriptionResult {
     pub language: Option<String>,
     pub duration: Option<f64>,
     pub is_final: bool,
+    /// Timed segments in decode order
+    #[serde(default)]
+    pub segments: Vec<TranscriptSegment>,
+    /// Per-word timings (not produced on device)
+    #[serde(default)]
+    pub words: Option<Vec<WordTiming>>,
 }
 
-/// Embedded ASR service for on-device speech recognition
-/// 
-/// Note: Full whisper inference requires native bindings (whisper-rs).
-/// This is a placeholder that will be implemented when native bindings are added.
-/// For now, it provides the interface and model management.
+impl From<TranscriptionResult> for crate::services::asr::TranscriptionResult {
+    fn from(result: TranscriptionResult) -> Self {
+        Self {
+            text: result.text,
+            language: result.language,
+            duration: result.duration,
+            is_final: result.is_final,
+            segments: result.segments,
+            words: result.words,
+        }
+    }
+}
+
+/// Payload of the `transcription-progress` event
+#[derive(Debug, Clone, Serialize)]
+pub struct TranscriptionProgress {
+    /// Text of the segment just decoded
+    pub segment: String,
+    /// Share of the audio processed so far (0-100)
+    pub percent: f32,
+}
+
+/// Collects segments as the decoder produces them and reports progress
+struct SegmentCollector {
+    /// Length of the audio being transcribed, in seconds
+    duration: f64,
+    segments: Vec<TranscriptSegment>,
+}
+
+impl SegmentCollector {
+    fn new(duration: f64) -> Self {
+        Self { duration, segments: Vec::new() }
+    }
+
+    /// Record a decoded segment and return the progress it represents
+    fn push(&mut self, segment: TranscriptSegment) -> TranscriptionProgress {
+        let percent = if self.duration > 0.0 {
+            (segment.end / self.duration * 100.0).clamp(0.0, 100.0) as f32
+        } else {
+            0.0
+        };
+        let progress = TranscriptionProgress { segment: segment.text.clone(), percent };
+        self.segments.push(segment);
+        progress
+    }
+
+    fn finish(self, language: Option<String>) -> TranscriptionResult {
+        let text = self.segments
+            .iter()
+            .map(|s| s.text.trim())
+            .filter(|t| !t.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        TranscriptionResult {
+            text,
+            language,
+            duration: Some(self.duration),
+            is_final: true,
+            segments: self.segments,
+            words: None,
+        }
+    }
+}
+
+/// Whisper timestamps are in units of 10 ms
+const WHISPER_TICKS_PER_SEC: f64 = 100.0;
+
+/// Embedded ASR service for on-device speech recognition using whisper-rs
 pub struct EmbeddedASR {
     config: EmbeddedASRConfig,
-    is_initialized: bool,
+    context: Option<Arc<WhisperContext>>,
 }
 
 impl EmbeddedASR {
     pub fn new(config: EmbeddedASRConfig) -> Self {
         Self {
             config,
-            is_initialized: false,
+            context: None,
         }
     }
 
@@ -59,31 +131,94 @@ impl EmbeddedASR {
                 self.config.model_path
             ));
         }
-        
-        // In a full implementation, this would load the whisper model
-        // using whisper-rs or similar native bindings
+
+        let path = self.config.model_path
+            .to_str()
+            .ok_or_else(|| format!("Invalid Whisper model path: {:?}", self.config.model_path))?
+            .to_string();
+        let context = tokio::task::spawn_blocking(move || {
+            WhisperContext::new_with_params(&path, WhisperContextParameters::default())
+        })
+        .await
+        .map_err(|e| format!("Failed to load Whisper model: {}", e))?
+        .map_err(|e| format!("Failed to load Whisper model: {}", e))?;
+
         log::info!("Embedded ASR initialized with model: {:?}", self.config.model_path);
-        self.is_initialized = true;
+        self.context = Some(Arc::new(context));
         Ok(())
     }
 
     /// Check if the ASR engine is ready
     pub fn is_ready(&self) -> bool {
-        self.is_initialized && self.config.model_path.exists()
+        self.context.is_some()
     }
 
     /// Transcribe WAV audio data to text
-    /// 
-    /// Note: This is a placeholder implementation. Full implementation requires
-    /// native Whisper bindings (whisper-rs) which need to be compiled for Android.
-    pub async fn transcribe_wav(&self, _wav_data: &[u8]) -> Result<TranscriptionResult, String> {
-        if !self.is_initialized {
+    pub async fn transcribe_wav(&self, wav_data: &[u8]) -> Result<TranscriptionResult, String> {
+        self.transcribe_wav_with_progress(wav_data, |_| {}).await
+    }
+
+    /// Transcribe WAV audio, calling `on_progress` as each segment is decoded
+    ///
+    /// Callers forward the updates as `transcription-progress` events so long
+    /// recordings show partial text while they are processed.
+    pub async fn transcribe_wav_with_progress<F>(&self, wav_data: &[u8], mut on_progress: F) -> Result<TranscriptionResult, String>
+    where
+        F: FnMut(TranscriptionProgress),
+    {
+        let Some(context) = self.context.clone() else {
             return Err("ASR not initialized. Call initialize() first.".to_string());
+        };
+
+        let wav_data = crate::audio::prepare_asr_wav(wav_data)
+            .map_err(|e| format!("Invalid audio for transcription: {}", e))?;
+        let wav = crate::audio::parse_wav(&wav_data)?;
+        let mut collector = SegmentCollector::new(wav.duration());
+        let samples: Vec<f32> = wav.samples.iter().map(|&s| s as f32 / 32768.0).collect();
+        let language = self.config.language.clone();
+
+        // Decoded segments are sent back from the blocking task as they arrive
+        let (segment_tx, mut segment_rx) = tokio::sync::mpsc::unbounded_channel();
+        let decode = tokio::task::spawn_blocking(move || -> Result<Option<String>, String> {
+            let mut state = context.create_state()
+                .map_err(|e| format!("Failed to create Whisper state: {}", e))?;
+
+            let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+            params.set_language(Some(language.as_str()).filter(|l| *l != "auto"));
+            params.set_print_progress(false);
+            params.set_print_realtime(false);
+            params.set_print_special(false);
+            params.set_print_timestamps(false);
+            params.set_segment_callback_safe(move |data: whisper_rs::SegmentCallbackData| {
+                let _ = segment_tx.send(TranscriptSegment {
+                    start: data.start_timestamp as f64 / WHISPER_TICKS_PER_SEC,
+                    end: data.end_timestamp as f64 / WHISPER_TICKS_PER_SEC,
+                    text: data.text.trim().to_string(),
+                });
+            });
+
+            state.full(params, &samples)
+                .map_err(|e| format!("Whisper inference failed: {}", e))?;
+
+            let detected = state.full_lang_id_from_state()
+                .ok()
+                .and_then(whisper_rs::get_lang_str)
+                .map(str::to_string);
+            Ok(detected)
+        });
+
+        // The channel closes once the decode finishes and drops the callback
+        while let Some(segment) = segment_rx.recv().await {
+            on_progress(collector.push(segment));
         }
+        let detected = decode
+            .await
+            .map_err(|e| format!("Whisper inference failed: {}", e))??;
 
-        // Placeholder: In production, this would use whisper-rs to transcribe
-        // For now, return an error indicating embedded inference is not yet available
-        Err("Embedded ASR inference not yet implemented. Please use remote services or implement whisper-rs bindings.".to_string())
+        let language = detected.or_else(|| {
+            (self.config.language != "auto").then(|| self.config.language.clone())
+        });
+        Ok(collector.finish(language))
     }
 
     /// Get model path
@@ -91,6 +226,17 @@ impl EmbeddedASR {
         &self.config.model_path
     }
 
+    /// Load the model from `path` on next use, unloading the current one
+    pub fn set_model_path(&mut self, path: PathBuf) {
+        self.config.model_path = path;
+        self.context = None;
+    }
+
+    /// Force the transcription language ("auto" restores detection)
+    pub fn set_language(&mut self, language: String) {
+        self.config.language = language;
+    }
+
     /// Check if model is downloaded
     pub fn is_model_available(&self) -> bool {
         self.config.model_path.exists()
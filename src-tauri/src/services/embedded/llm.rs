@@ -4,8 +4,21 @@
 //! quantized model that runs directly on the device without requiring external servers.
 
 use serde::{Deserialize, Serialize};
+use std::num::NonZeroU32;
 use std::path::PathBuf;
-use super::{MODEL_DIR, LLM_MODEL_FILE};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use llama_cpp_2::context::params::LlamaContextParams;
+use llama_cpp_2::llama_backend::LlamaBackend;
+use llama_cpp_2::llama_batch::LlamaBatch;
+use llama_cpp_2::model::params::LlamaModelParams;
+use llama_cpp_2::model::{AddBos, LlamaModel, Special};
+use llama_cpp_2::sampling::LlamaSampler;
+use super::{APP_DATA_DIR, MODEL_DIR, LLM_MODEL_FILE};
+use crate::services::llm::{ResponseLength, CANCEL_POLL_INTERVAL, FINISH_CANCELLED};
+
+/// File the embedded LLM settings are persisted to
+const CONFIG_FILE: &str = "embedded_llm.json";
 
 /// Embedded LLM configuration
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -18,6 +31,9 @@ pub struct EmbeddedLLMConfig {
     pub n_threads: u32,
     /// Context size in tokens
     pub context_size: u32,
+    /// Active length preset (None when max_tokens was set directly)
+    #[serde(default)]
+    pub response_length: Option<ResponseLength>,
 }
 
 impl Default for EmbeddedLLMConfig {
@@ -29,10 +45,32 @@ impl Default for EmbeddedLLMConfig {
             system_prompt: "You are a helpful AI assistant. Respond concisely.".to_string(),
             n_threads: 4, // Reasonable for mobile
             context_size: 1024, // Smaller context for mobile
+            response_length: None,
         }
     }
 }
 
+impl EmbeddedLLMConfig {
+    /// Load persisted settings, falling back to defaults
+    pub fn load_or_default() -> Self {
+        let path = APP_DATA_DIR.join(CONFIG_FILE);
+        std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Persist settings so they survive restarts
+    pub fn save(&self) -> Result<(), String> {
+        std::fs::create_dir_all(&*APP_DATA_DIR)
+            .map_err(|e| format!("Failed to create data directory: {}", e))?;
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize LLM settings: {}", e))?;
+        std::fs::write(APP_DATA_DIR.join(CONFIG_FILE), json)
+            .map_err(|e| format!("Failed to save LLM settings: {}", e))
+    }
+}
+
 /// Chat message structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
@@ -47,14 +85,171 @@ pub struct LLMResponse {
     pub finish_reason: Option<String>,
 }
 
-/// Embedded LLM service for on-device text generation
-/// 
-/// Note: Full LLM inference requires native bindings (llama-cpp-rs or similar).
-/// This is a placeholder that will be implemented when native bindings are added.
+impl From<LLMResponse> for crate::services::llm::LLMResponse {
+    fn from(response: LLMResponse) -> Self {
+        Self {
+            text: response.text,
+            finish_reason: response.finish_reason,
+            tool_calls: Vec::new(),
+            awaiting_input: false,
+            usage: None,
+        }
+    }
+}
+
+/// Occupancy of the model's KV cache
+#[derive(Debug, Clone, Serialize)]
+pub struct KvCacheUsage {
+    pub used_tokens: u32,
+    /// Context size in tokens
+    pub capacity: u32,
+}
+
+/// llama.cpp can only be initialized once per process
+fn backend() -> Result<&'static LlamaBackend, String> {
+    static BACKEND: OnceLock<Result<LlamaBackend, String>> = OnceLock::new();
+    BACKEND
+        .get_or_init(|| LlamaBackend::init().map_err(|e| format!("Failed to initialize llama.cpp: {}", e)))
+        .as_ref()
+        .map_err(|e| e.clone())
+}
+
+/// Prompt in the Qwen2 (ChatML) chat template, ending with an open assistant turn
+fn chatml_prompt(system_prompt: &str, history: &[ChatMessage]) -> String {
+    let mut prompt = format!("<|im_start|>system\n{}<|im_end|>\n", system_prompt);
+    for message in history {
+        prompt.push_str(&format!("<|im_start|>{}\n{}<|im_end|>\n", message.role, message.content));
+    }
+    prompt.push_str("<|im_start|>assistant\n");
+    prompt
+}
+
+/// Settings and inputs for one generation, moved onto the blocking thread
+struct Generation {
+    model: Arc<LlamaModel>,
+    system_prompt: String,
+    history: Vec<ChatMessage>,
+    temperature: f32,
+    max_tokens: u32,
+    n_threads: u32,
+    context_size: u32,
+}
+
+/// Outcome of a generation
+struct Generated {
+    finish_reason: &'static str,
+    /// Prompt plus generated tokens evaluated in the context
+    evaluated_tokens: u32,
+}
+
+impl Generation {
+    /// Run inference, sending each piece of text to `on_text` until done or `stop` is set
+    fn run(self, stop: &AtomicBool, mut on_text: impl FnMut(String)) -> Result<Generated, String> {
+        let backend = backend()?;
+        let n_threads = match self.n_threads {
+            0 => std::thread::available_parallelism().map(|c| c.get() as i32).unwrap_or(1),
+            n => n as i32,
+        };
+        let context_params = LlamaContextParams::default()
+            .with_n_ctx(NonZeroU32::new(self.context_size))
+            .with_n_batch(self.context_size)
+            .with_n_threads(n_threads)
+            .with_n_threads_batch(n_threads);
+        let mut context = self.model
+            .new_context(backend, context_params)
+            .map_err(|e| format!("Failed to create LLM context: {}", e))?;
+
+        // Drop the oldest messages until the prompt leaves room for the reply
+        let mut first = 0;
+        let tokens = loop {
+            let prompt = chatml_prompt(&self.system_prompt, &self.history[first..]);
+            let tokens = self.model
+                .str_to_token(&prompt, AddBos::Always)
+                .map_err(|e| format!("Failed to tokenize prompt: {}", e))?;
+            let fits = tokens.len() as u32 + self.max_tokens.min(self.context_size / 2) <= self.context_size;
+            if fits || first + 1 >= self.history.len() {
+                break tokens;
+            }
+            first += 1;
+        };
+        if tokens.len() as u32 >= self.context_size {
+            return Err(format!(
+                "Prompt of {} tokens does not fit the {}-token context",
+                tokens.len(), self.context_size
+            ));
+        }
+
+        let mut batch = LlamaBatch::new(tokens.len().max(1), 1);
+        let last = tokens.len() as i32 - 1;
+        for (position, token) in (0_i32..).zip(tokens.iter().copied()) {
+            batch.add(token, position, &[0], position == last)
+                .map_err(|e| format!("Failed to build prompt batch: {}", e))?;
+        }
+        context.decode(&mut batch)
+            .map_err(|e| format!("Failed to evaluate prompt: {}", e))?;
+
+        let mut sampler = if self.temperature <= 0.0 {
+            LlamaSampler::greedy()
+        } else {
+            let seed = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.subsec_nanos())
+                .unwrap_or(0);
+            LlamaSampler::chain_simple([LlamaSampler::temp(self.temperature), LlamaSampler::dist(seed)])
+        };
+
+        let limit = (self.context_size - tokens.len() as u32).min(self.max_tokens);
+        let mut position = tokens.len() as i32;
+        // Token pieces can split a multi-byte character, so bytes wait here until complete
+        let mut pending: Vec<u8> = Vec::new();
+        let mut generated = 0;
+        let finish_reason = loop {
+            if stop.load(Ordering::SeqCst) {
+                break FINISH_CANCELLED;
+            }
+            if generated >= limit {
+                break "length";
+            }
+
+            let token = sampler.sample(&context, batch.n_tokens() - 1);
+            sampler.accept(token);
+            if self.model.is_eog_token(token) {
+                break "stop";
+            }
+            generated += 1;
+
+            let bytes = self.model
+                .token_to_bytes(token, Special::Tokenize)
+                .map_err(|e| format!("Failed to decode token: {}", e))?;
+            pending.extend_from_slice(&bytes);
+            let valid = match std::str::from_utf8(&pending) {
+                Ok(text) => text.len(),
+                Err(e) => e.valid_up_to(),
+            };
+            if valid > 0 {
+                let text: Vec<u8> = pending.drain(..valid).collect();
+                on_text(String::from_utf8_lossy(&text).into_owned());
+            }
+
+            batch.clear();
+            batch.add(token, position, &[0], true)
+                .map_err(|e| format!("Failed to build batch: {}", e))?;
+            position += 1;
+            context.decode(&mut batch)
+                .map_err(|e| format!("Failed to generate: {}", e))?;
+        };
+
+        Ok(Generated { finish_reason, evaluated_tokens: position as u32 })
+    }
+}
+
+/// Embedded LLM service for on-device text generation using llama.cpp
 pub struct EmbeddedLLM {
     config: EmbeddedLLMConfig,
     conversation_history: Vec<ChatMessage>,
-    is_initialized: bool,
+    model: Option<Arc<LlamaModel>>,
+    /// Tokens evaluated in the model's context by the last turn
+    kv_cache_tokens: u32,
 }
 
 impl EmbeddedLLM {
@@ -62,7 +257,8 @@ impl EmbeddedLLM {
         Self {
             config,
             conversation_history: Vec::new(),
-            is_initialized: false,
+            model: None,
+            kv_cache_tokens: 0,
         }
     }
 
@@ -75,27 +271,42 @@ impl EmbeddedLLM {
                 self.config.model_path
             ));
         }
-        
-        // In a full implementation, this would load the GGUF model
-        // using llama-cpp-rs or similar native bindings
+
+        let path = self.config.model_path.clone();
+        let model = tokio::task::spawn_blocking(move || {
+            LlamaModel::load_from_file(backend()?, &path, &LlamaModelParams::default())
+                .map_err(|e| format!("Failed to load LLM model: {}", e))
+        })
+        .await
+        .map_err(|e| format!("Failed to load LLM model: {}", e))??;
+
         log::info!("Embedded LLM initialized with model: {:?}", self.config.model_path);
-        self.is_initialized = true;
+        self.model = Some(Arc::new(model));
+        self.kv_cache_tokens = 0;
         Ok(())
     }
 
     /// Check if the LLM engine is ready
     pub fn is_ready(&self) -> bool {
-        self.is_initialized && self.config.model_path.exists()
+        self.model.is_some()
     }
 
     /// Send a message and get a response
-    /// 
-    /// Note: This is a placeholder implementation. Full implementation requires
-    /// native llama.cpp bindings which need to be compiled for Android.
     pub async fn chat(&mut self, user_message: &str) -> Result<LLMResponse, String> {
-        if !self.is_initialized {
+        self.chat_stream(user_message, &AtomicBool::new(false), |_| {}).await
+    }
+
+    /// Send a message, calling `on_chunk` with each piece of text as it is generated
+    ///
+    /// Mirrors the remote `QwenLLM::chat_stream`: when `cancel` is set the
+    /// partial text is kept and returned with `finish_reason` `FINISH_CANCELLED`.
+    pub async fn chat_stream<F>(&mut self, user_message: &str, cancel: &AtomicBool, mut on_chunk: F) -> Result<LLMResponse, String>
+    where
+        F: FnMut(&str),
+    {
+        let Some(model) = self.model.clone() else {
             return Err("LLM not initialized. Call initialize() first.".to_string());
-        }
+        };
 
         // Add user message to history
         self.conversation_history.push(ChatMessage {
@@ -103,14 +314,85 @@ impl EmbeddedLLM {
             content: user_message.to_string(),
         });
 
-        // Placeholder: In production, this would use llama-cpp-rs to generate
-        // For now, return an error indicating embedded inference is not yet available
-        Err("Embedded LLM inference not yet implemented. Please use remote services or implement llama-cpp-rs bindings.".to_string())
+        let generation = Generation {
+            model,
+            system_prompt: self.config.system_prompt.clone(),
+            history: self.conversation_history.clone(),
+            temperature: self.config.temperature,
+            max_tokens: self.config.max_tokens,
+            n_threads: self.config.n_threads,
+            context_size: self.config.context_size,
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (text_tx, mut text_rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+        let worker_stop = stop.clone();
+        let worker = tokio::task::spawn_blocking(move || {
+            generation.run(&worker_stop, |text| {
+                let _ = text_tx.send(text);
+            })
+        });
+
+        // Forward text until the worker finishes and closes the channel
+        let mut text = String::new();
+        loop {
+            match tokio::time::timeout(CANCEL_POLL_INTERVAL, text_rx.recv()).await {
+                Ok(Some(piece)) => {
+                    on_chunk(&piece);
+                    text.push_str(&piece);
+                }
+                Ok(None) => break,
+                Err(_) => {}
+            }
+            if cancel.load(Ordering::SeqCst) {
+                stop.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let generated = worker
+            .await
+            .map_err(|e| format!("LLM inference failed: {}", e))
+            .and_then(|result| result);
+        let generated = match generated {
+            Ok(generated) => generated,
+            Err(e) => {
+                self.conversation_history.pop();
+                return Err(e);
+            }
+        };
+
+        self.kv_cache_tokens = generated.evaluated_tokens;
+        self.conversation_history.push(ChatMessage {
+            role: "assistant".to_string(),
+            content: text.clone(),
+        });
+        Ok(LLMResponse {
+            text,
+            finish_reason: Some(generated.finish_reason.to_string()),
+        })
     }
 
     /// Clear conversation history
     pub fn clear_history(&mut self) {
         self.conversation_history.clear();
+        self.reset_kv_cache();
+    }
+
+    /// Current KV cache occupancy
+    pub fn kv_cache_usage(&self) -> KvCacheUsage {
+        KvCacheUsage {
+            used_tokens: self.kv_cache_tokens,
+            capacity: self.config.context_size,
+        }
+    }
+
+    /// Drop the model's KV cache, keeping conversation history
+    ///
+    /// The next turn re-evaluates the history from scratch.
+    pub fn reset_kv_cache(&mut self) {
+        // Each turn evaluates the history in a fresh context, so only the
+        // usage figure needs clearing
+        self.kv_cache_tokens = 0;
     }
 
     /// Get model path
@@ -118,6 +400,16 @@ impl EmbeddedLLM {
         &self.config.model_path
     }
 
+    /// Approximate memory held by the loaded model (its file size), 0 if not loaded
+    pub fn loaded_bytes(&self) -> u64 {
+        if self.model.is_none() {
+            return 0;
+        }
+        std::fs::metadata(&self.config.model_path)
+            .map(|m| m.len())
+            .unwrap_or(0)
+    }
+
     /// Check if model is downloaded
     pub fn is_model_available(&self) -> bool {
         self.config.model_path.exists()
@@ -127,4 +419,48 @@ impl EmbeddedLLM {
     pub fn set_system_prompt(&mut self, prompt: String) {
         self.config.system_prompt = prompt;
     }
+
+    /// Apply a response length preset
+    pub fn set_response_length(&mut self, preset: ResponseLength) {
+        self.config.response_length = Some(preset);
+        self.config.max_tokens = preset.embedded_max_tokens();
+    }
+
+    /// Set the raw token cap, clearing any length preset
+    pub fn set_max_tokens(&mut self, max_tokens: u32) {
+        self.config.response_length = None;
+        self.config.max_tokens = max_tokens;
+    }
+
+    /// Get current configuration
+    pub fn config(&self) -> &EmbeddedLLMConfig {
+        &self.config
+    }
+
+    /// Restore the default configuration, reloading the model if it is loaded
+    pub async fn reset_config(&mut self) -> Result<(), String> {
+        self.config = EmbeddedLLMConfig::default();
+        if self.model.take().is_some() {
+            self.initialize().await?;
+        }
+        Ok(())
+    }
+
+    /// Load the model from `path`, unloading the current one
+    ///
+    /// The new model is loaded on next use, as it may not be downloaded yet.
+    pub fn set_model_path(&mut self, path: PathBuf) {
+        self.config.model_path = path;
+        self.model = None;
+        self.kv_cache_tokens = 0;
+    }
+
+    /// Update the inference thread count, reloading the model if it is loaded
+    pub async fn set_n_threads(&mut self, n_threads: u32) -> Result<(), String> {
+        self.config.n_threads = n_threads;
+        if self.model.take().is_some() {
+            self.initialize().await?;
+        }
+        Ok(())
+    }
 }
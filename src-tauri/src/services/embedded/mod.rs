@@ -20,23 +20,51 @@ pub mod asr;
 pub mod llm;
 pub mod tts;
 pub mod model_manager;
+pub mod whisper_variant;
 
-pub use asr::EmbeddedASR;
-pub use llm::EmbeddedLLM;
-pub use tts::EmbeddedTTS;
-pub use model_manager::ModelManager;
+pub use asr::{EmbeddedASR, EmbeddedASRConfig};
+pub use llm::{EmbeddedLLM, EmbeddedLLMConfig, KvCacheUsage};
+pub use tts::{EmbeddedTTS, EmbeddedTTSConfig};
+pub use model_manager::{DownloadSummary, ModelManager, RepairReport};
+pub use whisper_variant::WhisperVariant;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use once_cell::sync::Lazy;
 
-/// Default model directory path
+pub use crate::paths::APP_DATA_DIR;
+
+/// Environment variable overriding the model directory
+pub const MODEL_DIR_ENV: &str = "ASSIDENTER_MODEL_DIR";
+
+/// File in the app data directory holding the directory saved with `save_model_dir`
+const MODEL_DIR_FILE: &str = "model_dir.txt";
+
+/// Model directory used at startup: `ASSIDENTER_MODEL_DIR` if set, else the
+/// directory saved with `save_model_dir`, else the "models" folder of the
+/// app data directory
 pub static MODEL_DIR: Lazy<PathBuf> = Lazy::new(|| {
-    dirs::data_local_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join("assidenter")
-        .join("models")
+    std::env::var_os(MODEL_DIR_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(saved_model_dir)
+        .unwrap_or_else(|| APP_DATA_DIR.join("models"))
 });
 
+fn saved_model_dir() -> Option<PathBuf> {
+    let dir = std::fs::read_to_string(APP_DATA_DIR.join(MODEL_DIR_FILE)).ok()?;
+    let dir = dir.trim();
+    (!dir.is_empty()).then(|| PathBuf::from(dir))
+}
+
+/// Persist `dir` as the model directory from the next start on
+pub fn save_model_dir(dir: &Path) -> Result<(), String> {
+    let dir = dir.to_str().ok_or_else(|| "Model directory path must be valid UTF-8".to_string())?;
+    std::fs::create_dir_all(&*APP_DATA_DIR)
+        .map_err(|e| format!("Failed to create data directory: {}", e))?;
+    std::fs::write(APP_DATA_DIR.join(MODEL_DIR_FILE), dir)
+        .map_err(|e| format!("Failed to save model directory: {}", e))
+}
+
 /// Model file names
 pub const WHISPER_MODEL_FILE: &str = "whisper-tiny.bin";
 pub const LLM_MODEL_FILE: &str = "qwen2-0.5b-q4.gguf";
@@ -44,3 +72,15 @@ pub const LLM_MODEL_FILE: &str = "qwen2-0.5b-q4.gguf";
 /// Model download URLs (from Hugging Face)
 pub const WHISPER_MODEL_URL: &str = "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-tiny.bin";
 pub const LLM_MODEL_URL: &str = "https://huggingface.co/Qwen/Qwen2-0.5B-Instruct-GGUF/resolve/main/qwen2-0_5b-instruct-q4_k_m.gguf";
+
+/// Published SHA-256 of each model, when pinned
+///
+/// The URLs above track `main`, so a pinned hash would reject every file
+/// after an upstream update; set these together with a revision-pinned URL.
+/// Without one, a download is checked against the hash Hugging Face
+/// publishes for the file, and the verified hash is recorded.
+///
+/// Release builds pin them through the `ASSIDENTER_WHISPER_MODEL_SHA256` and
+/// `ASSIDENTER_LLM_MODEL_SHA256` build-time environment variables.
+pub const WHISPER_MODEL_SHA256: Option<&str> = option_env!("ASSIDENTER_WHISPER_MODEL_SHA256");
+pub const LLM_MODEL_SHA256: Option<&str> = option_env!("ASSIDENTER_LLM_MODEL_SHA256");
@@ -4,8 +4,48 @@
 //! required for embedded inference.
 
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use super::{MODEL_DIR, WHISPER_MODEL_FILE, LLM_MODEL_FILE, WHISPER_MODEL_URL, LLM_MODEL_URL};
+use super::{WHISPER_MODEL_SHA256, LLM_MODEL_SHA256};
+use super::WhisperVariant;
+use crate::services::llm::CANCEL_POLL_INTERVAL;
+
+/// Suffix of in-progress downloads
+pub const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Suffixes of partial downloads cleaned up by `repair` (".part" was used
+/// by downloads made from the frontend)
+const STALE_PARTIAL_SUFFIXES: &[&str] = &[PARTIAL_SUFFIX, ".part"];
+
+/// Minimum interval between download progress reports
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Model files smaller than this fraction of the expected size are treated as truncated
+const MIN_SIZE_RATIO: f64 = 0.5;
+
+/// Header of ggml (Whisper) model files: the magic 0x67676d6c, little-endian
+const GGML_MAGIC: &[u8] = b"lmgg";
+
+/// Header of GGUF (LLM) model files
+const GGUF_MAGIC: &[u8] = b"GGUF";
+
+/// File in the model directory recording the SHA-256 of each downloaded model
+const CHECKSUM_FILE: &str = "checksums.json";
+
+/// Free space a download needs, as a multiple of the model size
+const DOWNLOAD_SPACE_MARGIN: f64 = 1.1;
+
+/// Header in which Hugging Face publishes the SHA-256 of an LFS file
+const LINKED_ETAG_HEADER: &str = "x-linked-etag";
+
+/// Time allowed for looking up a published checksum
+const CHECKSUM_LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Model information
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,6 +55,12 @@ pub struct ModelInfo {
     pub download_url: String,
     pub size_bytes: u64,
     pub is_downloaded: bool,
+    /// Hex SHA-256 the file must match (pinned, or recorded at download)
+    #[serde(default)]
+    pub expected_sha256: Option<String>,
+    /// There is enough free disk space to download the model
+    #[serde(default)]
+    pub can_download: bool,
 }
 
 /// Download progress information
@@ -26,20 +72,178 @@ pub struct DownloadProgress {
     pub percentage: f32,
 }
 
+/// What `ModelManager::repair` found and did
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RepairReport {
+    /// Leftover partial downloads that were deleted
+    pub removed_partial: Vec<String>,
+    /// Files renamed to the name the app expects ("old -> new")
+    pub renamed: Vec<String>,
+    /// Corrupt model files that were deleted ("name: reason")
+    pub removed_corrupt: Vec<String>,
+    /// Model files that passed verification
+    pub verified: Vec<String>,
+    /// Required models that still need downloading
+    pub missing: Vec<ModelInfo>,
+    /// Files not recognized as models, left in place
+    pub unknown: Vec<String>,
+}
+
+/// What `ModelManager::download_all_models` did
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DownloadSummary {
+    /// Models downloaded by this call
+    pub downloaded: Vec<String>,
+    /// Models that were already present
+    pub already_present: Vec<String>,
+    /// Models whose download failed ("name: reason")
+    pub failed: Vec<String>,
+}
+
+/// Published SHA-256 of a required model, if pinned
+fn pinned_sha256(file_name: &str) -> Option<&'static str> {
+    match file_name {
+        WHISPER_MODEL_FILE => WHISPER_MODEL_SHA256,
+        LLM_MODEL_FILE => LLM_MODEL_SHA256,
+        _ => None,
+    }
+}
+
+/// Free space needed to download a model of `size_bytes`
+fn space_needed(size_bytes: u64) -> u64 {
+    (size_bytes as f64 * DOWNLOAD_SPACE_MARGIN) as u64
+}
+
+/// Byte count in MB for messages
+fn format_size(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1_000_000.0)
+}
+
+/// Last path segment of a URL
+fn url_file_name(url: &str) -> &str {
+    url.rsplit('/').next().unwrap_or(url)
+}
+
+/// SHA-256 the server publishes for `url`
+///
+/// Hugging Face reports the hash of LFS files in the `X-Linked-Etag` header of
+/// the `/resolve/` response, before redirecting to the storage backend.
+/// Returns None for other servers or when the lookup fails.
+async fn published_sha256(url: &str) -> Option<String> {
+    let client = reqwest::Client::builder()
+        .user_agent(crate::services::DEFAULT_USER_AGENT)
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(CHECKSUM_LOOKUP_TIMEOUT)
+        .build()
+        .ok()?;
+    let response = match client.head(url).send().await {
+        Ok(response) => response,
+        Err(e) => {
+            log::warn!("Failed to look up published checksum of {}: {}", url, e);
+            return None;
+        }
+    };
+    let etag = response.headers().get(LINKED_ETAG_HEADER)?.to_str().ok()?;
+    let hash = etag.trim_start_matches("W/").trim_matches('"');
+    (hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())).then(|| hash.to_lowercase())
+}
+
 /// Model manager for handling model downloads and storage
+#[derive(Clone)]
 pub struct ModelManager {
     model_dir: PathBuf,
+    /// Models registered at runtime by file name, overriding built-in entries
+    registered: HashMap<String, ModelInfo>,
+    /// Cancel flags of in-flight downloads, shared between clones
+    downloads: DownloadFlags,
+}
+
+/// Cancel flag of each in-flight download by file name
+type DownloadFlags = Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>;
+
+/// Removes a download's cancel flag when the download ends
+struct DownloadGuard {
+    downloads: DownloadFlags,
+    file_name: String,
+}
+
+impl Drop for DownloadGuard {
+    fn drop(&mut self) {
+        if let Ok(mut downloads) = self.downloads.lock() {
+            downloads.remove(&self.file_name);
+        }
+    }
 }
 
 impl ModelManager {
     pub fn new() -> Self {
-        Self {
-            model_dir: MODEL_DIR.clone(),
-        }
+        Self::with_model_dir(MODEL_DIR.clone())
     }
 
     pub fn with_model_dir(model_dir: PathBuf) -> Self {
-        Self { model_dir }
+        Self { model_dir, registered: HashMap::new(), downloads: Arc::default() }
+    }
+
+    /// A manager for `model_dir` that keeps this one's registered models
+    pub fn moved_to(&self, model_dir: PathBuf) -> Self {
+        Self { registered: self.registered.clone(), ..Self::with_model_dir(model_dir) }
+    }
+
+    /// Check that models can be saved in `dir`, creating it if needed
+    pub fn check_writable(dir: &Path) -> Result<(), String> {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create model directory {}: {}", dir.display(), e))?;
+        let probe = dir.join(".write-test");
+        std::fs::write(&probe, b"")
+            .map_err(|e| format!("Model directory {} is not writable: {}", dir.display(), e))?;
+        let _ = std::fs::remove_file(&probe);
+        Ok(())
+    }
+
+    /// Whether any model is currently downloading
+    pub fn is_downloading(&self) -> bool {
+        self.downloads.lock().is_ok_and(|downloads| !downloads.is_empty())
+    }
+
+    /// Add a model, or replace the source of a known one (e.g. a mirror URL
+    /// or a different quantization saved under the same file name)
+    ///
+    /// The registered `expected_sha256` replaces any pinned hash; `None`
+    /// accepts whatever the new source serves.
+    pub fn register_model(&mut self, info: ModelInfo) {
+        log::info!("Registered model {} from {}", info.file_name, info.download_url);
+        self.registered.insert(info.file_name.clone(), info);
+    }
+
+    /// Download `file_name` from `url` instead of its built-in source
+    ///
+    /// The model's pinned hash still applies, as a mirror serves the same file.
+    pub fn set_model_url(&mut self, file_name: &str, url: String) -> Result<(), String> {
+        let info = self.known_models()
+            .into_iter()
+            .find(|info| info.file_name == file_name)
+            .ok_or_else(|| format!("Unknown model: {}", file_name))?;
+        let expected_sha256 = self.required_sha256(file_name);
+        self.register_model(ModelInfo { download_url: url, expected_sha256, ..info });
+        Ok(())
+    }
+
+    /// A registered model with its download state refreshed
+    fn registered_info(&self, info: &ModelInfo, available: Option<u64>) -> ModelInfo {
+        ModelInfo {
+            is_downloaded: self.is_model_downloaded(&info.file_name),
+            expected_sha256: self.expected_sha256(&info.file_name),
+            can_download: Self::fits(info.size_bytes, available),
+            ..info.clone()
+        }
+    }
+
+    /// Hash a fresh download must match: the registered one, else the pinned one
+    fn required_sha256(&self, file_name: &str) -> Option<String> {
+        match self.registered.get(file_name) {
+            Some(info) => info.expected_sha256.clone(),
+            None => pinned_sha256(file_name).map(str::to_string),
+        }
     }
 
     /// Get the model directory path
@@ -53,15 +257,42 @@ impl ModelManager {
             .map_err(|e| format!("Failed to create model directory: {}", e))
     }
 
+    /// Free bytes on the filesystem holding the model directory
+    pub fn available_space(&self) -> Result<u64, String> {
+        // The directory may not exist yet, so measure its nearest existing ancestor
+        let dir = self.model_dir
+            .ancestors()
+            .find(|path| path.exists())
+            .unwrap_or(&self.model_dir);
+        let dir = dir.canonicalize()
+            .map_err(|e| format!("Failed to resolve {}: {}", dir.display(), e))?;
+        let disks = sysinfo::Disks::new_with_refreshed_list();
+        disks
+            .list()
+            .iter()
+            .filter(|disk| dir.starts_with(disk.mount_point()))
+            .max_by_key(|disk| disk.mount_point().as_os_str().len())
+            .map(|disk| disk.available_space())
+            .ok_or_else(|| format!("No filesystem found for {}", dir.display()))
+    }
+
+    /// Whether a model of `size_bytes` fits in `available` free bytes (unknown space is assumed to fit)
+    fn fits(size_bytes: u64, available: Option<u64>) -> bool {
+        available.map_or(true, |free| free >= space_needed(size_bytes))
+    }
+
     /// Get information about all required models
     pub fn get_model_info(&self) -> Vec<ModelInfo> {
-        vec![
+        let available = self.available_space().ok();
+        let models = vec![
             ModelInfo {
                 name: "Whisper Tiny (ASR)".to_string(),
                 file_name: WHISPER_MODEL_FILE.to_string(),
                 download_url: WHISPER_MODEL_URL.to_string(),
                 size_bytes: 75_000_000, // ~75MB
                 is_downloaded: self.model_dir.join(WHISPER_MODEL_FILE).exists(),
+                expected_sha256: self.expected_sha256(WHISPER_MODEL_FILE),
+                can_download: Self::fits(75_000_000, available),
             },
             ModelInfo {
                 name: "Qwen 0.5B Q4 (LLM)".to_string(),
@@ -69,8 +300,17 @@ impl ModelManager {
                 download_url: LLM_MODEL_URL.to_string(),
                 size_bytes: 400_000_000, // ~400MB
                 is_downloaded: self.model_dir.join(LLM_MODEL_FILE).exists(),
+                expected_sha256: self.expected_sha256(LLM_MODEL_FILE),
+                can_download: Self::fits(400_000_000, available),
             },
-        ]
+        ];
+        models
+            .into_iter()
+            .map(|info| match self.registered.get(&info.file_name) {
+                Some(registered) => self.registered_info(registered, available),
+                None => info,
+            })
+            .collect()
     }
 
     /// Check if all required models are downloaded
@@ -79,6 +319,16 @@ impl ModelManager {
         self.model_dir.join(LLM_MODEL_FILE).exists()
     }
 
+    /// Check that every required model is downloaded and passes `verify_model`
+    pub fn are_models_verified(&self) -> Result<bool, String> {
+        for info in self.get_model_info() {
+            if !info.is_downloaded || !self.verify_model(&info.file_name)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
     /// Check if a specific model is downloaded
     pub fn is_model_downloaded(&self, file_name: &str) -> bool {
         self.model_dir.join(file_name).exists()
@@ -89,11 +339,14 @@ impl ModelManager {
         self.model_dir.join(file_name)
     }
 
-    /// Get download URL for a model
-    pub fn get_download_url(&self, file_name: &str) -> Option<&'static str> {
+    /// Get download URL for a model, preferring a registered source
+    pub fn get_download_url(&self, file_name: &str) -> Option<String> {
+        if let Some(info) = self.registered.get(file_name) {
+            return Some(info.download_url.clone());
+        }
         match file_name {
-            f if f == WHISPER_MODEL_FILE => Some(WHISPER_MODEL_URL),
-            f if f == LLM_MODEL_FILE => Some(LLM_MODEL_URL),
+            f if f == WHISPER_MODEL_FILE => Some(WHISPER_MODEL_URL.to_string()),
+            f if f == LLM_MODEL_FILE => Some(LLM_MODEL_URL.to_string()),
             _ => None,
         }
     }
@@ -108,6 +361,442 @@ impl ModelManager {
         Ok(())
     }
 
+    /// Every model the app knows about: the required models, all Whisper
+    /// variants and any registered models
+    fn known_models(&self) -> Vec<ModelInfo> {
+        let mut models = self.get_model_info();
+        let available = self.available_space().ok();
+        for variant in WhisperVariant::ALL {
+            if !models.iter().any(|info| info.file_name == variant.file_name())
+                && !self.registered.contains_key(variant.file_name())
+            {
+                models.push(ModelInfo {
+                    name: format!("Whisper {:?} (ASR)", variant),
+                    file_name: variant.file_name().to_string(),
+                    download_url: variant.download_url().to_string(),
+                    size_bytes: variant.size_bytes(),
+                    is_downloaded: self.is_model_downloaded(variant.file_name()),
+                    expected_sha256: self.expected_sha256(variant.file_name()),
+                    can_download: Self::fits(variant.size_bytes(), available),
+                });
+            }
+        }
+        for registered in self.registered.values() {
+            if !models.iter().any(|info| info.file_name == registered.file_name) {
+                models.push(self.registered_info(registered, available));
+            }
+        }
+        models
+    }
+
+    /// SHA-256 recorded for each downloaded model
+    fn recorded_checksums(&self) -> HashMap<String, String> {
+        std::fs::read_to_string(self.model_dir.join(CHECKSUM_FILE))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Record (or with None, forget) the SHA-256 of a model file
+    fn record_checksum(&self, file_name: &str, sha256: Option<String>) -> Result<(), String> {
+        let mut checksums = self.recorded_checksums();
+        match sha256 {
+            Some(sha256) => checksums.insert(file_name.to_string(), sha256),
+            None => checksums.remove(file_name),
+        };
+        let json = serde_json::to_string_pretty(&checksums)
+            .map_err(|e| format!("Failed to serialize checksums: {}", e))?;
+        std::fs::write(self.model_dir.join(CHECKSUM_FILE), json)
+            .map_err(|e| format!("Failed to save checksums: {}", e))
+    }
+
+    /// Registered or pinned SHA-256 for a model, else the one recorded at download
+    fn expected_sha256(&self, file_name: &str) -> Option<String> {
+        self.required_sha256(file_name)
+            .or_else(|| self.recorded_checksums().remove(file_name))
+    }
+
+    /// Hex SHA-256 of a file
+    pub fn sha256_file(path: &Path) -> Result<String, String> {
+        let mut file = std::fs::File::open(path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 1 << 20];
+        loop {
+            let read = file.read(&mut buffer)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    /// Hash a model file and compare it with its expected SHA-256
+    ///
+    /// Returns false for a file with a bad header or size, or whose hash does
+    /// not match. Models with no known hash only get the header and size check.
+    pub fn verify_model(&self, file_name: &str) -> Result<bool, String> {
+        let info = self.known_models()
+            .into_iter()
+            .find(|info| info.file_name == file_name)
+            .ok_or_else(|| format!("Unknown model: {}", file_name))?;
+        if !info.is_downloaded {
+            return Err(format!("Model not downloaded: {}", file_name));
+        }
+        Ok(self.model_problem(&info)?.is_none())
+    }
+
+    /// Why a downloaded model file is invalid, if it is
+    fn model_problem(&self, info: &ModelInfo) -> Result<Option<String>, String> {
+        let path = self.get_model_path(&info.file_name);
+        if let Err(reason) = Self::check_model_file(&path, &info.file_name, info.size_bytes) {
+            return Ok(Some(reason));
+        }
+        let Some(expected) = &info.expected_sha256 else {
+            return Ok(None);
+        };
+        let actual = Self::sha256_file(&path)?;
+        if actual.eq_ignore_ascii_case(expected) {
+            Ok(None)
+        } else {
+            Ok(Some(format!("checksum mismatch (expected {}, got {})", expected, actual)))
+        }
+    }
+
+    /// Check a model file's header and size
+    ///
+    /// Catches truncated downloads and files that are not models (e.g. saved
+    /// HTML error pages) without hashing the whole file.
+    fn check_model_file(path: &Path, file_name: &str, expected_bytes: u64) -> Result<(), String> {
+        let size = std::fs::metadata(&path)
+            .map_err(|e| format!("Failed to read model metadata: {}", e))?
+            .len();
+        if (size as f64) < expected_bytes as f64 * MIN_SIZE_RATIO {
+            return Err(format!("truncated ({} of ~{} bytes)", size, expected_bytes));
+        }
+
+        let mut header = [0u8; 4];
+        std::fs::File::open(path)
+            .and_then(|mut file| file.read_exact(&mut header))
+            .map_err(|e| format!("unreadable header: {}", e))?;
+        let expected = if file_name.ends_with(".gguf") { GGUF_MAGIC } else { GGML_MAGIC };
+        if header != expected {
+            return Err("not a model file (bad header)".to_string());
+        }
+        Ok(())
+    }
+
+    /// Clean up the model directory
+    ///
+    /// Deletes leftover partial downloads, renames models saved under their
+    /// upstream or differently-cased names, and deletes model files that fail
+    /// verification. Must not run while a download is in progress.
+    pub fn repair(&self) -> Result<RepairReport, String> {
+        self.ensure_model_dir()?;
+        let known = self.known_models();
+        let mut report = RepairReport::default();
+
+        let entries = std::fs::read_dir(&self.model_dir)
+            .map_err(|e| format!("Failed to read model directory: {}", e))?;
+        for entry in entries.flatten() {
+            if !entry.path().is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            if STALE_PARTIAL_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) {
+                std::fs::remove_file(entry.path())
+                    .map_err(|e| format!("Failed to delete {}: {}", name, e))?;
+                report.removed_partial.push(name);
+                continue;
+            }
+            if name == CHECKSUM_FILE || known.iter().any(|info| info.file_name == name) {
+                continue;
+            }
+
+            let target = known.iter().find(|info| {
+                (name == url_file_name(&info.download_url) || name.eq_ignore_ascii_case(&info.file_name))
+                    && !self.is_model_downloaded(&info.file_name)
+            });
+            match target {
+                Some(info) => {
+                    std::fs::rename(entry.path(), self.model_dir.join(&info.file_name))
+                        .map_err(|e| format!("Failed to rename {}: {}", name, e))?;
+                    report.renamed.push(format!("{} -> {}", name, info.file_name));
+                }
+                None => report.unknown.push(name),
+            }
+        }
+
+        // Re-read so renamed files are picked up
+        for info in self.known_models() {
+            if !info.is_downloaded {
+                continue;
+            }
+            match self.model_problem(&info)? {
+                None => report.verified.push(info.file_name),
+                Some(reason) => {
+                    self.delete_model(&info.file_name)?;
+                    self.record_checksum(&info.file_name, None)?;
+                    report.removed_corrupt.push(format!("{}: {}", info.file_name, reason));
+                }
+            }
+        }
+
+        report.missing = self.get_model_info()
+            .into_iter()
+            .filter(|info| !info.is_downloaded)
+            .collect();
+        Ok(report)
+    }
+
+    /// Download a known model into the model directory
+    ///
+    /// The file is streamed to `<file_name>.partial` and renamed into place
+    /// once complete and verified. If the connection drops, the partial file
+    /// is kept and the next call resumes it with a Range request; it is only
+    /// removed if the finished file fails verification. `on_progress` is
+    /// called at most every 250ms, plus once at the end.
+    pub async fn download_model<F>(&self, file_name: &str, mut on_progress: F) -> Result<(), String>
+    where
+        F: FnMut(DownloadProgress),
+    {
+        let info = self.known_models()
+            .into_iter()
+            .find(|info| info.file_name == file_name)
+            .ok_or_else(|| format!("Unknown model: {}", file_name))?;
+
+        self.ensure_model_dir()?;
+        let partial = self.model_dir.join(format!("{}{}", info.file_name, PARTIAL_SUFFIX));
+        let (cancel, _guard) = self.begin_download(&info.file_name)?;
+
+        // Bytes already in a partial download do not need space again
+        let existing = tokio::fs::metadata(&partial).await.map(|m| m.len()).unwrap_or(0);
+        let needed = space_needed(info.size_bytes).saturating_sub(existing);
+        match self.available_space() {
+            Ok(available) if available < needed => {
+                return Err(format!(
+                    "Not enough disk space: need {}, have {}",
+                    format_size(needed), format_size(available)
+                ));
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("Could not check free space before downloading {}: {}", info.name, e),
+        }
+
+        // Only a required or published hash applies to a fresh download; a
+        // recorded one was for the old file
+        let expected_sha256 = match self.required_sha256(&info.file_name) {
+            Some(expected) => Some(expected),
+            None => published_sha256(&info.download_url).await,
+        };
+        if expected_sha256.is_none() {
+            log::warn!("No published checksum for {}, recording the downloaded file's hash", info.name);
+        }
+
+        self.fetch_to(&info, &partial, &cancel, &mut on_progress).await?;
+
+        let problem = match Self::check_model_file(&partial, &info.file_name, info.size_bytes) {
+            Err(reason) => Some(reason),
+            Ok(()) => {
+                let hash_path = partial.clone();
+                let actual = tokio::task::spawn_blocking(move || Self::sha256_file(&hash_path))
+                    .await
+                    .map_err(|e| format!("Failed to hash {}: {}", info.file_name, e))??;
+                match expected_sha256 {
+                    Some(expected) if !actual.eq_ignore_ascii_case(&expected) => {
+                        Some(format!("checksum mismatch (expected {}, got {})", expected, actual))
+                    }
+                    _ => {
+                        self.record_checksum(&info.file_name, Some(actual))?;
+                        None
+                    }
+                }
+            }
+        };
+        if let Some(reason) = problem {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(format!("Downloaded {} is invalid: {}", info.name, reason));
+        }
+
+        tokio::fs::rename(&partial, self.get_model_path(&info.file_name))
+            .await
+            .map_err(|e| format!("Failed to move {} into place: {}", info.file_name, e))?;
+        Ok(())
+    }
+
+    /// Download every required model not yet present, all at once
+    ///
+    /// Each download keeps its own partial file and resume logic, and reports
+    /// progress through `on_progress` tagged with its model name. A failed
+    /// download does not stop the others.
+    pub async fn download_all_models<F>(&self, on_progress: F) -> DownloadSummary
+    where
+        F: Fn(DownloadProgress),
+    {
+        use futures::stream::{FuturesUnordered, StreamExt};
+
+        let mut summary = DownloadSummary::default();
+        let on_progress = &on_progress;
+        let mut downloads = FuturesUnordered::new();
+        for info in self.get_model_info() {
+            if info.is_downloaded {
+                summary.already_present.push(info.name);
+                continue;
+            }
+            log::info!("Downloading {} from {}", info.name, info.download_url);
+            downloads.push(async move {
+                let result = self.download_model(&info.file_name, on_progress).await;
+                (info.name, result)
+            });
+        }
+
+        while let Some((name, result)) = downloads.next().await {
+            match result {
+                Ok(()) => summary.downloaded.push(name),
+                Err(e) => {
+                    log::warn!("Failed to download {}: {}", name, e);
+                    summary.failed.push(format!("{}: {}", name, e));
+                }
+            }
+        }
+        summary
+    }
+
+    /// Register an in-flight download, failing if the model is already downloading
+    fn begin_download(&self, file_name: &str) -> Result<(Arc<AtomicBool>, DownloadGuard), String> {
+        let mut downloads = self.downloads.lock().map_err(|e| e.to_string())?;
+        if downloads.contains_key(file_name) {
+            return Err(format!("{} is already downloading", file_name));
+        }
+        let cancel = Arc::new(AtomicBool::new(false));
+        downloads.insert(file_name.to_string(), cancel.clone());
+        let guard = DownloadGuard { downloads: self.downloads.clone(), file_name: file_name.to_string() };
+        Ok((cancel, guard))
+    }
+
+    /// Stop an in-flight download; its partial file is deleted
+    ///
+    /// Returns false if the model is not downloading.
+    pub fn cancel_download(&self, file_name: &str) -> bool {
+        match self.downloads.lock().ok().and_then(|downloads| downloads.get(file_name).cloned()) {
+            Some(cancel) => {
+                cancel.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stream a model's download URL into `path`, resuming any bytes already there
+    ///
+    /// Setting `cancel` stops between chunks and deletes `path`.
+    async fn fetch_to<F>(&self, info: &ModelInfo, path: &Path, cancel: &AtomicBool, on_progress: &mut F) -> Result<(), String>
+    where
+        F: FnMut(DownloadProgress),
+    {
+        use futures::StreamExt;
+        use reqwest::{header, StatusCode};
+        use tokio::io::AsyncWriteExt;
+
+        let existing = tokio::fs::metadata(path).await.map(|m| m.len()).unwrap_or(0);
+        let mut request = crate::services::build_client(None, 0).get(&info.download_url);
+        if existing > 0 {
+            request = request.header(header::RANGE, format!("bytes={}-", existing));
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Failed to download {}: {}", info.name, e))?;
+
+        // Nothing left past the end of the partial file: it is already complete
+        if existing > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            log::info!("{} partial download is already complete", info.name);
+            on_progress(DownloadProgress {
+                model_name: info.name.clone(),
+                downloaded_bytes: existing,
+                total_bytes: existing,
+                percentage: 100.0,
+            });
+            return Ok(());
+        }
+        if !response.status().is_success() {
+            return Err(crate::services::status_error(&format!("Download of {}", info.name), response).await);
+        }
+
+        // 206 continues from where the partial file ends; 200 means the server
+        // ignored the range, so the download restarts from zero
+        let resume_from = if response.status() == StatusCode::PARTIAL_CONTENT {
+            let content_range = response.headers()
+                .get(header::CONTENT_RANGE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("");
+            if !content_range.starts_with(&format!("bytes {}-", existing)) {
+                let _ = tokio::fs::remove_file(path).await;
+                return Err(format!(
+                    "Download of {} resumed at the wrong offset ({}), restart it",
+                    info.name, content_range
+                ));
+            }
+            log::info!("Resuming {} download at {} bytes", info.name, existing);
+            existing
+        } else {
+            0
+        };
+
+        let total_bytes = response.content_length()
+            .map(|remaining| resume_from + remaining)
+            .unwrap_or(info.size_bytes);
+        let progress = |downloaded_bytes: u64| DownloadProgress {
+            model_name: info.name.clone(),
+            downloaded_bytes,
+            total_bytes,
+            percentage: downloaded_bytes as f32 / total_bytes.max(1) as f32 * 100.0,
+        };
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resume_from > 0)
+            .truncate(resume_from == 0)
+            .open(path)
+            .await
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let mut downloaded_bytes = resume_from;
+        let mut last_report = Instant::now();
+        let mut stream = response.bytes_stream();
+        loop {
+            if cancel.load(Ordering::SeqCst) {
+                drop(file);
+                let _ = tokio::fs::remove_file(path).await;
+                log::info!("Download of {} cancelled at {} bytes", info.name, downloaded_bytes);
+                return Err(format!("Download of {} was cancelled", info.name));
+            }
+            // Poll with a timeout so a stalled connection still notices cancellation
+            let chunk = match tokio::time::timeout(CANCEL_POLL_INTERVAL, stream.next()).await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(_) => continue,
+            };
+            let chunk = chunk.map_err(|e| format!("Failed to download {}: {}", info.name, e))?;
+            file.write_all(&chunk)
+                .await
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+            downloaded_bytes += chunk.len() as u64;
+            if last_report.elapsed() >= PROGRESS_INTERVAL {
+                last_report = Instant::now();
+                on_progress(progress(downloaded_bytes));
+            }
+        }
+        file.flush()
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        on_progress(progress(downloaded_bytes));
+        Ok(())
+    }
+
     /// Get total size of downloaded models
     pub fn get_downloaded_size(&self) -> u64 {
         let mut total = 0;
@@ -34,6 +34,17 @@ pub struct TTSResult {
     pub duration: f64,
 }
 
+impl From<TTSResult> for crate::services::tts::TTSResult {
+    fn from(result: TTSResult) -> Self {
+        Self {
+            audio_data: result.audio_data,
+            sample_rate: result.sample_rate,
+            duration: result.duration,
+            alignment: None,
+        }
+    }
+}
+
 /// Embedded TTS service for on-device speech synthesis
 /// 
 /// On Android, this integrates with the Android TextToSpeech API through Tauri plugins.
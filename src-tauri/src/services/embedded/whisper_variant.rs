@@ -0,0 +1,89 @@
+//! Whisper model variants and device-based selection
+//!
+//! Larger Whisper models are more accurate but slower. The real-time factor
+//! (processing time / audio time) of each variant is estimated from the
+//! device's core count so the largest model that keeps up can be chosen.
+
+use serde::{Deserialize, Serialize};
+use super::APP_DATA_DIR;
+
+/// File the active variant is persisted to
+const ACTIVE_VARIANT_FILE: &str = "whisper_variant.json";
+
+/// Real-time factor of whisper-tiny on a single core
+const TINY_SINGLE_CORE_RTF: f32 = 0.6;
+
+/// Most cores whisper.cpp benefits from
+const MAX_USEFUL_CORES: u32 = 8;
+
+/// Whisper model sizes supported on device
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WhisperVariant {
+    Tiny,
+    Base,
+    Small,
+}
+
+impl WhisperVariant {
+    /// All variants, smallest first
+    pub const ALL: [WhisperVariant; 3] = [WhisperVariant::Tiny, WhisperVariant::Base, WhisperVariant::Small];
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            WhisperVariant::Tiny => "whisper-tiny.bin",
+            WhisperVariant::Base => "whisper-base.bin",
+            WhisperVariant::Small => "whisper-small.bin",
+        }
+    }
+
+    pub fn download_url(self) -> &'static str {
+        match self {
+            WhisperVariant::Tiny => "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-tiny.bin",
+            WhisperVariant::Base => "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-base.bin",
+            WhisperVariant::Small => "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-small.bin",
+        }
+    }
+
+    /// Approximate download size
+    pub fn size_bytes(self) -> u64 {
+        match self {
+            WhisperVariant::Tiny => 75_000_000,
+            WhisperVariant::Base => 142_000_000,
+            WhisperVariant::Small => 466_000_000,
+        }
+    }
+
+    /// Compute cost relative to tiny
+    fn relative_cost(self) -> f32 {
+        match self {
+            WhisperVariant::Tiny => 1.0,
+            WhisperVariant::Base => 2.0,
+            WhisperVariant::Small => 6.0,
+        }
+    }
+
+    /// Estimated real-time factor on a device with `cores` CPU cores
+    pub fn estimate_rtf(self, cores: u32) -> f32 {
+        let cores = cores.clamp(1, MAX_USEFUL_CORES) as f32;
+        TINY_SINGLE_CORE_RTF * self.relative_cost() / cores
+    }
+
+    /// Persisted active variant, defaulting to tiny
+    pub fn load_active() -> Self {
+        std::fs::read_to_string(APP_DATA_DIR.join(ACTIVE_VARIANT_FILE))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or(WhisperVariant::Tiny)
+    }
+
+    /// Persist this variant as the active one
+    pub fn save_active(self) -> Result<(), String> {
+        std::fs::create_dir_all(&*APP_DATA_DIR)
+            .map_err(|e| format!("Failed to create data directory: {}", e))?;
+        let json = serde_json::to_string(&self)
+            .map_err(|e| format!("Failed to serialize Whisper variant: {}", e))?;
+        std::fs::write(APP_DATA_DIR.join(ACTIVE_VARIANT_FILE), json)
+            .map_err(|e| format!("Failed to save Whisper variant: {}", e))
+    }
+}
@@ -0,0 +1,148 @@
+use std::future::Future;
+
+use serde::Serialize;
+
+use super::ServiceMode;
+
+/// A backend failed and the next one in the chain was tried
+#[derive(Debug, Clone, Serialize)]
+pub struct FallbackEvent {
+    /// "asr", "llm" or "tts"
+    pub service: String,
+    pub from: &'static str,
+    pub to: &'static str,
+    /// Error returned by the failed backend
+    pub error: String,
+}
+
+/// Parse a backend order such as `["embedded", "remote"]`
+pub fn parse_chain(modes: &[String]) -> Result<Vec<ServiceMode>, String> {
+    let mut chain = Vec::with_capacity(modes.len());
+    for mode in modes {
+        let mode = ServiceMode::parse(mode)?;
+        if chain.contains(&mode) {
+            return Err(format!("Backend {} appears more than once in the fallback chain", mode.as_str()));
+        }
+        chain.push(mode);
+    }
+    if chain.is_empty() {
+        return Err("Fallback chain cannot be empty".to_string());
+    }
+    Ok(chain)
+}
+
+/// Run `attempt` on each backend of `chain` in order until one succeeds
+///
+/// `on_fallback` is called with the event before moving on to the next
+/// backend. Returns the last backend's error when all of them fail.
+pub async fn with_fallback<T, F, Fut, E>(
+    service: &str,
+    chain: &[ServiceMode],
+    mut attempt: F,
+    mut on_fallback: E,
+) -> Result<T, String>
+where
+    F: FnMut(ServiceMode) -> Fut,
+    Fut: Future<Output = Result<T, String>>,
+    E: FnMut(&FallbackEvent),
+{
+    let mut modes = chain.iter().copied().peekable();
+    while let Some(mode) = modes.next() {
+        let error = match attempt(mode).await {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        let Some(&next) = modes.peek() else {
+            return Err(error);
+        };
+        log::warn!("{} {} backend failed, falling back to {}: {}",
+            service.to_uppercase(), mode.as_str(), next.as_str(), error);
+        on_fallback(&FallbackEvent {
+            service: service.to_string(),
+            from: mode.as_str(),
+            to: next.as_str(),
+            error,
+        });
+    }
+    Err(format!("No {} backend configured", service.to_uppercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAIN: [ServiceMode; 2] = [ServiceMode::Embedded, ServiceMode::Remote];
+
+    fn modes(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_chain() {
+        assert_eq!(parse_chain(&modes(&["remote"])), Ok(vec![ServiceMode::Remote]));
+        assert_eq!(parse_chain(&modes(&[" Remote "])), Ok(vec![ServiceMode::Remote]));
+    }
+
+    #[test]
+    fn rejects_duplicate_backends() {
+        assert_eq!(
+            parse_chain(&modes(&["remote", "remote"])),
+            Err("Backend remote appears more than once in the fallback chain".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_empty_chain() {
+        assert_eq!(parse_chain(&[]), Err("Fallback chain cannot be empty".to_string()));
+    }
+
+    #[tokio::test]
+    async fn first_success_skips_fallback() {
+        let mut tried = Vec::new();
+        let mut events = Vec::new();
+        let result = with_fallback("asr", &CHAIN, |mode| {
+            tried.push(mode);
+            async move { Ok::<_, String>(mode.as_str()) }
+        }, |event| events.push(event.clone())).await;
+        assert_eq!(result, Ok("embedded"));
+        assert_eq!(tried, [ServiceMode::Embedded]);
+        assert!(events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn falls_back_in_chain_order() {
+        let mut tried = Vec::new();
+        let mut events = Vec::new();
+        let result = with_fallback("llm", &CHAIN, |mode| {
+            tried.push(mode);
+            async move {
+                match mode {
+                    ServiceMode::Embedded => Err("model not loaded".to_string()),
+                    ServiceMode::Remote => Ok("reply"),
+                }
+            }
+        }, |event| events.push(event.clone())).await;
+        assert_eq!(result, Ok("reply"));
+        assert_eq!(tried, CHAIN);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].service, "llm");
+        assert_eq!((events[0].from, events[0].to), ("embedded", "remote"));
+        assert_eq!(events[0].error, "model not loaded");
+    }
+
+    #[tokio::test]
+    async fn returns_last_error() {
+        let mut events = 0;
+        let result: Result<(), String> = with_fallback("tts", &CHAIN, |mode| async move {
+            Err(format!("{} failed", mode.as_str()))
+        }, |_| events += 1).await;
+        assert_eq!(result, Err("remote failed".to_string()));
+        assert_eq!(events, 1);
+    }
+
+    #[tokio::test]
+    async fn empty_chain_is_an_error() {
+        let result: Result<(), String> = with_fallback("asr", &[], |_| async { Ok(()) }, |_| {}).await;
+        assert_eq!(result, Err("No ASR backend configured".to_string()));
+    }
+}
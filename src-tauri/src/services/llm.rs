@@ -1,6 +1,67 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use serde::{Deserialize, Serialize};
 use reqwest::Client;
+use super::{build_client, ping_url, with_auth, read_json, request_error, status_error, RateLimiter};
+use super::retry::{send_with_retry, RetryBudget};
 use futures::StreamExt;
+use crate::trace;
+
+/// Maximum tool-call rounds per streamed turn before giving up on tools
+const MAX_TOOL_ROUNDS: usize = 4;
+
+/// Result recorded for a tool call that was not run
+const TOOL_NOT_RUN: &str = "Error: tool call was not run";
+
+/// How often a stalled stream checks for cancellation
+pub const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `finish_reason` of a stream stopped by its cancel flag
+pub const FINISH_CANCELLED: &str = "cancelled";
+
+/// `finish_reason` of a reply cut off by `max_tokens`
+pub const FINISH_LENGTH: &str = "length";
+
+/// Follow-up requests `auto_continue` makes for one reply
+const MAX_CONTINUATIONS: usize = 2;
+
+/// Prefix of replies in mock mode, followed by the last user message
+const MOCK_REPLY_PREFIX: &str = "Mock reply to: ";
+
+/// Message asking the model to finish a reply cut off by `max_tokens`
+const CONTINUE_PROMPT: &str = "Continue exactly where you stopped, without repeating anything.";
+
+/// Reminder sent when a JSON reply does not parse
+const JSON_RETRY_REMINDER: &str =
+    "That was not valid JSON. Return valid JSON only, with no explanation or code fences.";
+
+/// Reply of `chat_json`: the raw text and its parsed value
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonResponse {
+    pub text: String,
+    pub json: serde_json::Value,
+}
+
+/// Parse a JSON reply, allowing for a surrounding Markdown code fence
+fn parse_json_reply(text: &str) -> Option<serde_json::Value> {
+    let trimmed = text.trim();
+    let unfenced = trimmed
+        .strip_prefix("```json")
+        .or_else(|| trimmed.strip_prefix("```"))
+        .and_then(|rest| rest.trim_end().strip_suffix("```"))
+        .unwrap_or(trimmed);
+    serde_json::from_str(unfenced.trim()).ok()
+}
+
+/// Marker the model appends when it needs an answer from the user
+pub const FOLLOW_UP_MARKER: &str = "[ASK]";
+
+/// System prompt addition used when follow-up questions are enabled
+const FOLLOW_UP_INSTRUCTION: &str =
+    "If you need more information from the user before you can answer, ask one short question and end your reply with [ASK].";
 
 /// Qwen LLM configuration
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -9,7 +70,153 @@ pub struct QwenConfig {
     pub model: String,
     pub temperature: f32,
     pub max_tokens: u32,
+    /// Nucleus sampling cutoff (1.0 = disabled)
+    #[serde(default = "default_top_p")]
+    pub top_p: f32,
+    /// Penalty for tokens that already appeared (-2.0 to 2.0)
+    #[serde(default)]
+    pub presence_penalty: f32,
+    /// Penalty scaled by how often a token already appeared (-2.0 to 2.0)
+    #[serde(default)]
+    pub frequency_penalty: f32,
+    /// Sequences that end the reply
+    #[serde(default)]
+    pub stop: Vec<String>,
+    /// OpenAI `response_format` sent with every request (e.g. `{"type": "json_object"}`)
+    #[serde(default)]
+    pub response_format: Option<serde_json::Value>,
     pub system_prompt: String,
+    /// OpenAI-format tool definitions sent with each request
+    #[serde(default)]
+    pub tools: Vec<serde_json::Value>,
+    /// OpenAI `tool_choice` ("auto", "none" or a specific function)
+    #[serde(default)]
+    pub tool_choice: Option<serde_json::Value>,
+    /// Describe the configured tools and built-in capabilities in the system prompt
+    #[serde(default)]
+    pub inject_tool_descriptions: bool,
+    /// User-Agent header (defaults to assidenter/<version>)
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// Sent as `Authorization: Bearer <key>`; never logged or sent to the webview
+    #[serde(default, skip_serializing)]
+    pub api_key: Option<String>,
+    /// Additional headers sent with every request
+    #[serde(default)]
+    pub extra_headers: HashMap<String, String>,
+    /// Request timeout in seconds (0 = none)
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Active length preset (None when max_tokens was set directly)
+    #[serde(default)]
+    pub response_length: Option<ResponseLength>,
+    /// Let the model ask clarifying questions marked with `FOLLOW_UP_MARKER`
+    #[serde(default)]
+    pub follow_up_questions: bool,
+    /// Context window of the model in tokens
+    #[serde(default = "default_context_tokens")]
+    pub context_tokens: u32,
+    /// Most history messages kept, oldest exchanges dropped first (0 = no limit)
+    #[serde(default)]
+    pub max_history_messages: usize,
+    /// Whether the model accepts image input (None = guess from the model name)
+    #[serde(default)]
+    pub vision: Option<bool>,
+    /// Client-side request limit per minute (0 = unlimited)
+    #[serde(default)]
+    pub requests_per_minute: u32,
+    /// Fail requests over the limit instead of waiting for budget
+    #[serde(default)]
+    pub rate_limit_fail_fast: bool,
+    /// Retries of a request on 5xx and connection errors
+    #[serde(default = "super::retry::default_http_retries")]
+    pub max_retries: u32,
+    /// Echo the user message in a canned reply instead of contacting the server (for offline development)
+    #[serde(default)]
+    pub mock: bool,
+    /// Ask the model to continue replies cut off by `max_tokens`
+    #[serde(default)]
+    pub auto_continue: bool,
+}
+
+/// Longer than the other services, since streamed replies count against it too
+fn default_timeout_secs() -> u64 {
+    60
+}
+
+fn default_context_tokens() -> u32 {
+    4096
+}
+
+fn default_top_p() -> f32 {
+    1.0
+}
+
+/// Model name parts that indicate a vision-capable model
+const VISION_MODEL_HINTS: &[&str] = &["vl", "vision", "llava", "pixtral", "gpt-4o", "minicpm-v", "gemma3", "omni"];
+
+/// Guess from its name whether a model accepts images
+fn is_vision_model(model: &str) -> bool {
+    let model = model.to_lowercase();
+    VISION_MODEL_HINTS.iter().any(|hint| {
+        if hint.contains('-') {
+            model.contains(hint)
+        } else {
+            model.split(|c: char| !c.is_ascii_alphanumeric()).any(|part| part.starts_with(hint))
+        }
+    })
+}
+
+/// Per-message overhead of the chat template, in tokens
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Estimated context window usage
+#[derive(Debug, Clone, Serialize)]
+pub struct ContextUsage {
+    pub used_tokens: u32,
+    /// Context window size
+    pub max_tokens: u32,
+    /// Messages in the conversation history
+    pub messages: usize,
+    /// The next reply (at `max_tokens` of the config) would overflow the window
+    pub will_truncate_next: bool,
+}
+
+/// Friendly response length presets
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ResponseLength {
+    Short,
+    Medium,
+    Long,
+}
+
+impl ResponseLength {
+    /// Token cap for the remote LLM
+    pub fn max_tokens(self) -> u32 {
+        match self {
+            ResponseLength::Short => 128,
+            ResponseLength::Medium => 512,
+            ResponseLength::Long => 1024,
+        }
+    }
+
+    /// Token cap for the smaller on-device LLM
+    pub fn embedded_max_tokens(self) -> u32 {
+        match self {
+            ResponseLength::Short => 96,
+            ResponseLength::Medium => 256,
+            ResponseLength::Long => 512,
+        }
+    }
+
+    /// Instruction appended to the system prompt, if any
+    pub fn prompt_hint(self) -> Option<&'static str> {
+        match self {
+            ResponseLength::Short => Some("Keep your answers brief: one or two sentences."),
+            ResponseLength::Medium => None,
+            ResponseLength::Long => Some("Give thorough, detailed answers."),
+        }
+    }
 }
 
 impl Default for QwenConfig {
@@ -19,9 +226,306 @@ impl Default for QwenConfig {
             model: "qwen-0.5b".to_string(),
             temperature: 0.7,
             max_tokens: 512,
+            top_p: default_top_p(),
+            presence_penalty: 0.0,
+            frequency_penalty: 0.0,
+            stop: Vec::new(),
+            response_format: None,
             system_prompt: "You are a helpful AI assistant. Respond concisely and helpfully.".to_string(),
+            tools: Vec::new(),
+            tool_choice: None,
+            inject_tool_descriptions: false,
+            user_agent: None,
+            api_key: None,
+            extra_headers: HashMap::new(),
+            timeout_secs: default_timeout_secs(),
+            response_length: None,
+            follow_up_questions: false,
+            context_tokens: default_context_tokens(),
+            max_history_messages: 0,
+            vision: None,
+            requests_per_minute: 0,
+            rate_limit_fail_fast: false,
+            max_retries: super::retry::DEFAULT_HTTP_RETRIES,
+            mock: false,
+            auto_continue: false,
+        }
+    }
+}
+
+/// Capabilities the app provides regardless of server-side tool support
+const BUILTIN_CAPABILITIES: &[(&str, &str)] = &[
+    ("screenshot", "The app can capture the user's screen and share it with you when asked about what is on screen."),
+];
+
+/// Build a plain-text description of tools for models without native tool calling
+pub fn describe_tools(tools: &[serde_json::Value]) -> String {
+    let mut out = String::new();
+
+    if !tools.is_empty() {
+        out.push_str("You have access to the following tools:\n");
+        for tool in tools {
+            let function = &tool["function"];
+            let name = function["name"].as_str().unwrap_or("unnamed");
+            let description = function["description"].as_str().unwrap_or("");
+            out.push_str(&format!("- {}: {}", name, description));
+
+            if let Some(properties) = function["parameters"]["properties"].as_object() {
+                let required: Vec<&str> = function["parameters"]["required"]
+                    .as_array()
+                    .map(|r| r.iter().filter_map(|v| v.as_str()).collect())
+                    .unwrap_or_default();
+                let params: Vec<String> = properties.iter().map(|(param, schema)| {
+                    let kind = schema["type"].as_str().unwrap_or("any");
+                    if required.contains(&param.as_str()) {
+                        format!("{} ({}, required)", param, kind)
+                    } else {
+                        format!("{} ({})", param, kind)
+                    }
+                }).collect();
+                if !params.is_empty() {
+                    out.push_str(&format!(" Parameters: {}", params.join(", ")));
+                }
+            }
+            out.push('\n');
         }
     }
+
+    out.push_str("Built-in capabilities:\n");
+    for (name, description) in BUILTIN_CAPABILITIES {
+        out.push_str(&format!("- {}: {}\n", name, description));
+    }
+
+    out
+}
+
+/// A tool call requested by the model
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    /// JSON-encoded arguments
+    pub arguments: String,
+}
+
+impl ToolCall {
+    /// Parse an OpenAI-format `tool_calls` entry
+    fn from_json(value: &serde_json::Value) -> Self {
+        Self {
+            id: value["id"].as_str().unwrap_or("").to_string(),
+            name: value["function"]["name"].as_str().unwrap_or("").to_string(),
+            arguments: value["function"]["arguments"].as_str().unwrap_or("").to_string(),
+        }
+    }
+
+    /// OpenAI wire format, for echoing the call back in history
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "type": "function",
+            "function": { "name": self.name, "arguments": self.arguments }
+        })
+    }
+}
+
+/// Executes a tool call and returns its result as text for the model
+pub type ToolDispatcher = Arc<dyn Fn(&ToolCall) -> Result<String, String> + Send + Sync>;
+
+/// Reassembles tool calls streamed as `delta.tool_calls` fragments
+///
+/// The first fragment for each `index` carries the call id and function name;
+/// the arguments then arrive as partial JSON strings that must be concatenated.
+#[derive(Debug, Default)]
+pub struct ToolCallAccumulator {
+    calls: Vec<ToolCall>,
+}
+
+impl ToolCallAccumulator {
+    /// Merge one `delta.tool_calls` array into the calls seen so far
+    pub fn push_delta(&mut self, fragments: &serde_json::Value) {
+        let Some(fragments) = fragments.as_array() else {
+            return;
+        };
+        for fragment in fragments {
+            let index = fragment["index"].as_u64().unwrap_or(0) as usize;
+            if self.calls.len() <= index {
+                self.calls.resize_with(index + 1, ToolCall::default);
+            }
+            let call = &mut self.calls[index];
+            if let Some(id) = fragment["id"].as_str().filter(|id| !id.is_empty()) {
+                call.id = id.to_string();
+            }
+            if let Some(name) = fragment["function"]["name"].as_str() {
+                call.name.push_str(name);
+            }
+            if let Some(arguments) = fragment["function"]["arguments"].as_str() {
+                call.arguments.push_str(arguments);
+            }
+        }
+    }
+
+    /// Completed calls, in index order
+    pub fn finish(self) -> Vec<ToolCall> {
+        self.calls.into_iter().filter(|c| !c.name.is_empty()).collect()
+    }
+}
+
+/// Token counts reported by the server for one or more requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+impl TokenUsage {
+    /// Parse an OpenAI `usage` object, if present
+    fn from_json(value: &serde_json::Value) -> Option<Self> {
+        if !value.is_object() {
+            return None;
+        }
+        let count = |key: &str| value[key].as_u64().unwrap_or(0) as u32;
+        let prompt_tokens = count("prompt_tokens");
+        let completion_tokens = count("completion_tokens");
+        let total_tokens = value["total_tokens"]
+            .as_u64()
+            .map_or(prompt_tokens + completion_tokens, |t| t as u32);
+        Some(Self { prompt_tokens, completion_tokens, total_tokens })
+    }
+
+    /// Sum usage over several requests (e.g. tool call rounds)
+    fn combine(a: Option<Self>, b: Option<Self>) -> Option<Self> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(Self {
+                prompt_tokens: a.prompt_tokens + b.prompt_tokens,
+                completion_tokens: a.completion_tokens + b.completion_tokens,
+                total_tokens: a.total_tokens + b.total_tokens,
+            }),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// A finished completion, streamed or not
+struct Completion {
+    text: String,
+    finish_reason: Option<String>,
+    tool_calls: Vec<ToolCall>,
+    usage: Option<TokenUsage>,
+}
+
+/// Accumulated state of a streaming completion
+#[derive(Default)]
+struct StreamState {
+    text: String,
+    finish_reason: Option<String>,
+    tool_calls: ToolCallAccumulator,
+    usage: Option<TokenUsage>,
+}
+
+/// Canned completion echoing the last user message in `messages`
+fn mock_completion(messages: &serde_json::Value) -> Completion {
+    let last_user = messages
+        .as_array()
+        .and_then(|messages| messages.iter().rev().find(|m| m["role"] == "user"))
+        .map(|m| match &m["content"] {
+            // Vision messages carry a list of parts
+            serde_json::Value::Array(parts) => parts
+                .iter()
+                .find_map(|part| part["text"].as_str())
+                .unwrap_or("")
+                .to_string(),
+            content => content.as_str().unwrap_or("").to_string(),
+        })
+        .unwrap_or_default();
+    Completion {
+        text: format!("{}{}", MOCK_REPLY_PREFIX, last_user),
+        finish_reason: Some("stop".to_string()),
+        tool_calls: Vec::new(),
+        usage: None,
+    }
+}
+
+/// Splits a byte stream into lines, holding back a trailing partial line
+/// (and any UTF-8 character split with it) until the rest arrives
+#[derive(Default)]
+struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    /// Add a network chunk and return the lines it completed, without line endings
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(bytes);
+        let Some(end) = self.pending.iter().rposition(|&b| b == b'\n') else {
+            return Vec::new();
+        };
+        let complete: Vec<u8> = self.pending.drain(..=end).collect();
+        String::from_utf8_lossy(&complete[..end])
+            .split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line).to_string())
+            .collect()
+    }
+
+    /// Take a final line not terminated by a newline
+    fn finish(&mut self) -> Option<String> {
+        let rest = std::mem::take(&mut self.pending);
+        let line = String::from_utf8_lossy(&rest);
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        (!line.is_empty()).then(|| line.to_string())
+    }
+}
+
+/// Handle one SSE line, collecting `data:` fields into `event_data` and
+/// dispatching the event at the blank line that ends it. Returns true when the
+/// stream is finished (`[DONE]`).
+fn handle_sse_line<F>(line: &str, event_data: &mut Vec<String>, state: &mut StreamState, on_chunk: &mut F) -> bool
+where
+    F: FnMut(&str),
+{
+    if line.is_empty() {
+        let data = event_data.join("\n");
+        event_data.clear();
+        return handle_sse_event(&data, state, on_chunk);
+    }
+    if let Some(value) = line.strip_prefix("data:") {
+        event_data.push(value.strip_prefix(' ').unwrap_or(value).to_string());
+    }
+    // Comments (": keep-alive") and other fields (event:, id:, retry:) are ignored
+    false
+}
+
+/// Handle one complete SSE event payload from a streaming completion.
+/// Returns true when the stream is finished (`[DONE]`).
+fn handle_sse_event<F>(data: &str, state: &mut StreamState, on_chunk: &mut F) -> bool
+where
+    F: FnMut(&str),
+{
+    if data.is_empty() {
+        return false;
+    }
+    if data == "[DONE]" {
+        return true;
+    }
+
+    if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
+        let choice = &json["choices"][0];
+        if let Some(content) = choice["delta"]["content"].as_str() {
+            state.text.push_str(content);
+            on_chunk(content);
+        }
+        if !choice["delta"]["tool_calls"].is_null() {
+            state.tool_calls.push_delta(&choice["delta"]["tool_calls"]);
+        }
+        if let Some(reason) = choice["finish_reason"].as_str() {
+            state.finish_reason = Some(reason.to_string());
+        }
+        // Servers that report usage send it on the final chunk (often with no choices)
+        if let Some(usage) = TokenUsage::from_json(&json["usage"]) {
+            state.usage = Some(usage);
+        }
+    }
+    false
 }
 
 /// Chat message structure
@@ -29,6 +533,81 @@ impl Default for QwenConfig {
 pub struct ChatMessage {
     pub role: String,
     pub content: String,
+    /// Tool calls requested by an assistant message (OpenAI format)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<serde_json::Value>>,
+    /// Id of the call a `tool` message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// Pinned messages are never dropped when history is trimmed
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+}
+
+impl ChatMessage {
+    pub fn new(role: &str, content: impl Into<String>) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.into(),
+            tool_calls: None,
+            tool_call_id: None,
+            pinned: false,
+        }
+    }
+
+    /// Assistant message, recording any tool calls it made
+    fn assistant(content: impl Into<String>, tool_calls: &[ToolCall]) -> Self {
+        let mut message = Self::new("assistant", content);
+        if !tool_calls.is_empty() {
+            message.tool_calls = Some(tool_calls.iter().map(ToolCall::to_json).collect());
+        }
+        message
+    }
+
+    /// Result of a tool call
+    fn tool_result(call_id: &str, content: impl Into<String>) -> Self {
+        let mut message = Self::new("tool", content);
+        message.tool_call_id = Some(call_id.to_string());
+        message
+    }
+}
+
+/// Sampling overrides for a single chat call; unset fields use the config
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ChatOptions {
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    /// Nucleus sampling cutoff
+    pub top_p: Option<f32>,
+}
+
+impl ChatOptions {
+    /// Check that each override is in range
+    pub fn validate(&self) -> Result<(), String> {
+        if self.temperature.is_some_and(|t| !(0.0..=2.0).contains(&t)) {
+            return Err("temperature must be between 0 and 2".to_string());
+        }
+        if self.max_tokens == Some(0) {
+            return Err("max_tokens must be greater than 0".to_string());
+        }
+        if self.top_p.is_some_and(|p| !(p > 0.0 && p <= 1.0)) {
+            return Err("top_p must be greater than 0 and at most 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// Replace the configured values in a request payload
+    fn apply(&self, payload: &mut serde_json::Value) {
+        if let Some(temperature) = self.temperature {
+            payload["temperature"] = serde_json::json!(temperature);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            payload["max_tokens"] = serde_json::json!(max_tokens);
+        }
+        if let Some(top_p) = self.top_p {
+            payload["top_p"] = serde_json::json!(top_p);
+        }
+    }
 }
 
 /// LLM response
@@ -36,179 +615,1163 @@ pub struct ChatMessage {
 pub struct LLMResponse {
     pub text: String,
     pub finish_reason: Option<String>,
+    /// Tool calls requested by the model (empty for plain replies)
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCall>,
+    /// The model asked a follow-up question and expects the user to answer
+    #[serde(default)]
+    pub awaiting_input: bool,
+    /// Token usage reported by the server, if it sent any
+    #[serde(default)]
+    pub usage: Option<TokenUsage>,
+}
+
+impl LLMResponse {
+    /// Whether the reply was cut off by `max_tokens`
+    pub fn is_truncated(&self) -> bool {
+        self.finish_reason.as_deref() == Some(FINISH_LENGTH)
+    }
+}
+
+impl From<Completion> for LLMResponse {
+    fn from(completion: Completion) -> Self {
+        Self {
+            text: completion.text,
+            finish_reason: completion.finish_reason,
+            tool_calls: completion.tool_calls,
+            awaiting_input: false,
+            usage: completion.usage,
+        }
+    }
 }
 
 /// Qwen 0.5 LLM service client
+#[derive(Clone)]
 pub struct QwenLLM {
     config: QwenConfig,
     client: Client,
     conversation_history: Vec<ChatMessage>,
+    /// Cached tool description, regenerated whenever tools change
+    tool_prompt: String,
+    /// Runs tool calls made during streamed turns
+    tool_dispatcher: Option<ToolDispatcher>,
+    /// Shared with speculative clones so they count against the same budget
+    rate_limiter: Arc<RateLimiter>,
+    /// Charged by retried requests; shared with the pipeline
+    retry_budget: Arc<RetryBudget>,
+    /// Named configurations that can be switched to at runtime
+    profiles: HashMap<String, QwenConfig>,
+    active_profile: Option<String>,
 }
 
 impl QwenLLM {
     pub fn new(config: QwenConfig) -> Self {
+        let tool_prompt = describe_tools(&config.tools);
+        let client = build_client(config.user_agent.as_deref(), config.timeout_secs);
+        let rate_limiter = Arc::new(RateLimiter::new("llm", config.requests_per_minute, config.rate_limit_fail_fast));
         Self {
             config,
-            client: Client::new(),
+            client,
             conversation_history: Vec::new(),
+            tool_prompt,
+            tool_dispatcher: None,
+            rate_limiter,
+            retry_budget: Arc::new(RetryBudget::default()),
+            profiles: HashMap::new(),
+            active_profile: None,
+        }
+    }
+
+    /// System prompt with the length hint and tool description appended
+    fn effective_system_prompt(&self) -> String {
+        let mut prompt = self.config.system_prompt.clone();
+        if let Some(hint) = self.config.response_length.and_then(ResponseLength::prompt_hint) {
+            prompt.push(' ');
+            prompt.push_str(hint);
+        }
+        if self.config.inject_tool_descriptions {
+            prompt.push_str("\n\n");
+            prompt.push_str(&self.tool_prompt);
+        }
+        if self.config.follow_up_questions {
+            prompt.push(' ');
+            prompt.push_str(FOLLOW_UP_INSTRUCTION);
+        }
+        prompt
+    }
+
+    /// Convert a completion into a response, stripping the follow-up marker
+    fn finish(&self, completion: Completion) -> LLMResponse {
+        let mut response = LLMResponse::from(completion);
+        if self.config.follow_up_questions {
+            if let Some(text) = response.text.trim_end().strip_suffix(FOLLOW_UP_MARKER) {
+                response.text = text.trim_end().to_string();
+                response.awaiting_input = true;
+            }
+        }
+        response
+    }
+
+    /// Add an assistant reply to history
+    ///
+    /// Tool calls that were not run get an error result each, since servers
+    /// reject a history with calls left unanswered.
+    fn record_reply(&mut self, text: &str, tool_calls: &[ToolCall]) {
+        self.conversation_history.push(ChatMessage::assistant(text, tool_calls));
+        for call in tool_calls {
+            self.conversation_history.push(ChatMessage::tool_result(&call.id, TOOL_NOT_RUN));
         }
     }
 
     /// Send a message to the LLM and get a response
+    ///
+    /// The user message is only kept in history if the request succeeds, so a
+    /// failed call can be retried without duplicating it.
     pub async fn chat(&mut self, user_message: &str) -> Result<LLMResponse, String> {
+        self.chat_with_options(user_message, &ChatOptions::default()).await
+    }
+
+    /// Like `chat`, with `options` overriding the configured sampling for
+    /// this call only
+    pub async fn chat_with_options(&mut self, user_message: &str, options: &ChatOptions) -> Result<LLMResponse, String> {
         // Add user message to history
-        self.conversation_history.push(ChatMessage {
-            role: "user".to_string(),
-            content: user_message.to_string(),
-        });
+        self.conversation_history.push(ChatMessage::new("user", user_message));
+        self.trim_history();
+
+        let messages = self.build_messages(&self.conversation_history);
+        let completion = match self.request_completion_with(&messages, None, options).await {
+            Ok(completion) => self.continue_truncated(&messages, completion, options).await,
+            Err(e) => {
+                self.conversation_history.pop();
+                return Err(e);
+            }
+        };
+
+        // Add assistant response to history
+        self.record_reply(&completion.text, &completion.tool_calls);
+
+        Ok(self.finish(completion))
+    }
+
+    /// Send a message using `system` as the system prompt for this request only
+    ///
+    /// The configured system prompt is left untouched, and the turn is kept
+    /// in history like a normal `chat` turn.
+    pub async fn chat_with_system(&mut self, system: &str, user_message: &str) -> Result<LLMResponse, String> {
+        self.conversation_history.push(ChatMessage::new("user", user_message));
+        self.trim_history();
 
-        // Build messages array with system prompt
-        let mut messages = vec![ChatMessage {
-            role: "system".to_string(),
-            content: self.config.system_prompt.clone(),
-        }];
-        messages.extend(self.conversation_history.clone());
+        let messages = self.build_messages_with(system.to_string(), &self.conversation_history);
+        let completion = match self.request_completion(&messages).await {
+            Ok(completion) => completion,
+            Err(e) => {
+                self.conversation_history.pop();
+                return Err(e);
+            }
+        };
+
+        self.record_reply(&completion.text, &completion.tool_calls);
+        Ok(self.finish(completion))
+    }
 
+    /// Send a message and require a JSON reply
+    ///
+    /// Requests `response_format: {"type": "json_object"}` (with `schema`, if
+    /// given, also described in the message). A reply that does not parse is
+    /// retried once with a reminder. Only a valid reply is kept in history.
+    pub async fn chat_json(&mut self, user_message: &str, schema: Option<&serde_json::Value>) -> Result<JsonResponse, String> {
+        let mut response_format = serde_json::json!({ "type": "json_object" });
+        let content = match schema {
+            Some(schema) => {
+                response_format["schema"] = schema.clone();
+                format!("{}\n\nRespond with JSON matching this schema:\n{}", user_message, schema)
+            }
+            None => user_message.to_string(),
+        };
+
+        self.trim_history();
+        let mut turns = self.conversation_history.clone();
+        turns.push(ChatMessage::new("user", &content));
+
+        let mut completion = self.request_completion_with(&self.build_messages(&turns), Some(&response_format), &ChatOptions::default()).await?;
+        let json = match parse_json_reply(&completion.text) {
+            Some(json) => json,
+            None => {
+                log::warn!("LLM reply is not valid JSON, retrying once");
+                turns.push(ChatMessage::new("assistant", &completion.text));
+                turns.push(ChatMessage::new("user", JSON_RETRY_REMINDER));
+                completion = self.request_completion_with(&self.build_messages(&turns), Some(&response_format), &ChatOptions::default()).await?;
+                parse_json_reply(&completion.text)
+                    .ok_or_else(|| format!("LLM did not return valid JSON: {}", completion.text.trim()))?
+            }
+        };
+
+        self.conversation_history.push(ChatMessage::new("user", &content));
+        self.conversation_history.push(ChatMessage::new("assistant", &completion.text));
+        Ok(JsonResponse { text: completion.text, json })
+    }
+
+    /// Send a single message without prior context, leaving history untouched
+    pub async fn chat_stateless(&self, user_message: &str) -> Result<LLMResponse, String> {
+        let messages = self.build_messages(&[ChatMessage::new("user", user_message)]);
+        let completion = self.request_completion(&messages).await?;
+        Ok(self.finish(self.continue_truncated(&messages, completion, &ChatOptions::default()).await))
+    }
+
+    /// With `auto_continue`, ask the model to carry on after a reply cut off
+    /// by `max_tokens`, appending each continuation to the text
+    ///
+    /// A failed follow-up keeps the text so far, still marked as truncated.
+    async fn continue_truncated(&self, messages: &[ChatMessage], mut completion: Completion, options: &ChatOptions) -> Completion {
+        let mut continuations = 0;
+        while self.config.auto_continue
+            && continuations < MAX_CONTINUATIONS
+            && completion.finish_reason.as_deref() == Some(FINISH_LENGTH)
+            && completion.tool_calls.is_empty()
+        {
+            continuations += 1;
+            let mut follow_up = messages.to_vec();
+            follow_up.push(ChatMessage::new("assistant", &completion.text));
+            follow_up.push(ChatMessage::new("user", CONTINUE_PROMPT));
+            match self.request_completion_with(&follow_up, None, options).await {
+                Ok(next) => {
+                    log::info!("Continued truncated LLM reply ({} more chars)", next.text.len());
+                    completion.text.push_str(&next.text);
+                    completion.finish_reason = next.finish_reason;
+                    completion.usage = TokenUsage::combine(completion.usage, next.usage);
+                }
+                Err(e) => {
+                    log::warn!("Failed to continue truncated LLM reply: {}", e);
+                    break;
+                }
+            }
+        }
+        completion
+    }
+
+    /// Streaming counterpart of `continue_truncated`: each continuation keeps
+    /// streaming through `on_chunk`
+    async fn continue_truncated_stream<F>(
+        &self,
+        messages: &[ChatMessage],
+        mut completion: Completion,
+        options: &ChatOptions,
+        cancel: &AtomicBool,
+        on_chunk: &mut F,
+    ) -> Completion
+    where
+        F: FnMut(&str),
+    {
+        let mut continuations = 0;
+        while self.config.auto_continue
+            && continuations < MAX_CONTINUATIONS
+            && completion.finish_reason.as_deref() == Some(FINISH_LENGTH)
+            && completion.tool_calls.is_empty()
+        {
+            continuations += 1;
+            let mut follow_up = messages.to_vec();
+            follow_up.push(ChatMessage::new("assistant", &completion.text));
+            follow_up.push(ChatMessage::new("user", CONTINUE_PROMPT));
+            match self.stream_completion(&follow_up, options, cancel, on_chunk).await {
+                Ok(next) => {
+                    log::info!("Continued truncated LLM reply ({} more chars)", next.text.len());
+                    completion.text.push_str(&next.text);
+                    completion.finish_reason = next.finish_reason;
+                    completion.usage = TokenUsage::combine(completion.usage, next.usage);
+                }
+                Err(e) => {
+                    log::warn!("Failed to continue truncated LLM reply: {}", e);
+                    break;
+                }
+            }
+        }
+        completion
+    }
+
+    /// Reply to `user_message` in the context of the history without recording
+    /// the turn. Pair with `commit_turn` once the message is confirmed.
+    pub async fn chat_speculative(&self, user_message: &str) -> Result<LLMResponse, String> {
+        let mut turns = self.conversation_history.clone();
+        turns.push(ChatMessage::new("user", user_message));
+        let messages = self.build_messages(&turns);
+        Ok(self.finish(self.request_completion(&messages).await?))
+    }
+
+    /// Record a turn produced by `chat_speculative`
+    pub fn commit_turn(&mut self, user_message: &str, response: &LLMResponse) {
+        self.conversation_history.push(ChatMessage::new("user", user_message));
+        self.record_reply(&response.text, &response.tool_calls);
+    }
+
+    /// Send a message with an attached PNG image (OpenAI vision format)
+    ///
+    /// Only the text of the message is kept in history; the image is sent once.
+    pub async fn chat_with_image(&mut self, user_message: &str, image_png_base64: &str) -> Result<LLMResponse, String> {
+        self.trim_history();
+        let mut messages: Vec<serde_json::Value> = self.build_messages(&self.conversation_history)
+            .iter()
+            .map(|m| serde_json::json!(m))
+            .collect();
+        messages.push(serde_json::json!({
+            "role": "user",
+            "content": [
+                { "type": "text", "text": user_message },
+                {
+                    "type": "image_url",
+                    "image_url": { "url": format!("data:image/png;base64,{}", image_png_base64) }
+                }
+            ]
+        }));
+
+        let completion = self.request_completion(&messages).await?;
+
+        self.conversation_history.push(ChatMessage::new("user", user_message));
+        self.record_reply(&completion.text, &completion.tool_calls);
+
+        Ok(self.finish(completion))
+    }
+
+    /// Prepend the system prompt to `turns`
+    fn build_messages(&self, turns: &[ChatMessage]) -> Vec<ChatMessage> {
+        self.build_messages_with(self.effective_system_prompt(), turns)
+    }
+
+    /// Messages for a request using `system_prompt` in place of the configured one
+    fn build_messages_with(&self, system_prompt: String, turns: &[ChatMessage]) -> Vec<ChatMessage> {
+        let mut messages = vec![ChatMessage::new("system", system_prompt)];
+        // Pin state is local bookkeeping and not sent to the server
+        messages.extend(turns.iter().map(|m| ChatMessage { pinned: false, ..m.clone() }));
+        messages
+    }
+
+    /// Drop the oldest exchanges until the history is within `max_history_messages`
+    /// and the next reply fits the context window
+    ///
+    /// The system prompt, pinned messages and the latest message are always
+    /// kept. Whole exchanges are removed so no reply is left without its question.
+    fn trim_history(&mut self) {
+        loop {
+            let max = self.config.max_history_messages;
+            let too_many = max > 0 && self.conversation_history.len() > max;
+            if !too_many && !self.context_usage().will_truncate_next {
+                break;
+            }
+            if !self.drop_oldest_exchange() {
+                break;
+            }
+        }
+    }
+
+    /// Remove the oldest unpinned message and the replies and tool results
+    /// that follow it, up to the next user message. Returns false if nothing
+    /// could be removed.
+    fn drop_oldest_exchange(&mut self) -> bool {
+        let last = self.conversation_history.len().saturating_sub(1);
+        let Some(start) = self.conversation_history[..last].iter().position(|m| !m.pinned) else {
+            return false;
+        };
+
+        let mut end = start + 1;
+        while end < last && self.conversation_history[end].role != "user" {
+            end += 1;
+        }
+
+        let mut index = start;
+        while index < end {
+            if self.conversation_history[index].pinned {
+                index += 1;
+            } else {
+                self.conversation_history.remove(index);
+                end -= 1;
+            }
+        }
+        true
+    }
+
+    /// Add sampling options that differ from the server defaults to a request payload
+    fn apply_sampling(&self, payload: &mut serde_json::Value) {
+        if self.config.top_p < 1.0 {
+            payload["top_p"] = serde_json::json!(self.config.top_p);
+        }
+        if self.config.presence_penalty != 0.0 {
+            payload["presence_penalty"] = serde_json::json!(self.config.presence_penalty);
+        }
+        if self.config.frequency_penalty != 0.0 {
+            payload["frequency_penalty"] = serde_json::json!(self.config.frequency_penalty);
+        }
+        if !self.config.stop.is_empty() {
+            payload["stop"] = serde_json::json!(self.config.stop);
+        }
+        if let Some(format) = &self.config.response_format {
+            payload["response_format"] = format.clone();
+        }
+    }
+
+    /// Add the configured tools and tool choice to a request payload
+    ///
+    /// Tools are only offered while a dispatcher can run them: a call left
+    /// without its result makes the server reject the next request.
+    fn apply_tools(&self, payload: &mut serde_json::Value) {
+        if self.config.tools.is_empty() || self.tool_dispatcher.is_none() {
+            return;
+        }
+        payload["tools"] = serde_json::Value::Array(self.config.tools.clone());
+        if let Some(choice) = &self.config.tool_choice {
+            payload["tool_choice"] = choice.clone();
+        }
+    }
+
+    /// Send `messages` to the server and return the completion
+    async fn request_completion<M: Serialize + Sync>(&self, messages: &M) -> Result<Completion, String> {
+        self.request_completion_with(messages, None, &ChatOptions::default()).await
+    }
+
+    /// Like `request_completion`, overriding the configured `response_format`
+    /// and sampling
+    async fn request_completion_with<M: Serialize + Sync>(
+        &self,
+        messages: &M,
+        response_format: Option<&serde_json::Value>,
+        options: &ChatOptions,
+    ) -> Result<Completion, String> {
         // Create the request payload (OpenAI-compatible format)
-        let payload = serde_json::json!({
+        let mut payload = serde_json::json!({
             "model": self.config.model,
             "messages": messages,
             "temperature": self.config.temperature,
             "max_tokens": self.config.max_tokens,
             "stream": false
         });
+        self.apply_sampling(&mut payload);
+        options.apply(&mut payload);
+        if let Some(format) = response_format {
+            payload["response_format"] = format.clone();
+        }
+        self.apply_tools(&mut payload);
+        if self.config.mock {
+            return Ok(mock_completion(&payload["messages"]));
+        }
 
         // Send request to Qwen server
-        let response = self.client
-            .post(format!("{}/v1/chat/completions", self.config.server_url))
-            .json(&payload)
-            .send()
-            .await
-            .map_err(|e| format!("Failed to send LLM request: {}", e))?;
+        let url = format!("{}/v1/chat/completions", self.config.server_url);
+        let started = Instant::now();
+        let result = async {
+            self.rate_limiter.acquire().await?;
+            let response = send_with_retry("LLM", self.config.max_retries, &self.retry_budget, self.authorize(self.client.post(&url)).json(&payload))
+                .await
+                .map_err(|e| request_error("LLM", self.config.timeout_secs, "Failed to send LLM request", e))?;
 
-        if !response.status().is_success() {
-            return Err(format!("LLM request failed with status: {}", response.status()));
-        }
+            if !response.status().is_success() {
+                return Err(status_error("LLM request", response).await);
+            }
 
-        let result: serde_json::Value = response
-            .json()
-            .await
-            .map_err(|e| format!("Failed to parse LLM response: {}", e))?;
+            read_json("LLM", response).await
+        }.await;
+        trace::record_exchange("llm", &url, &payload, started, || result.clone());
+        let result = result?;
 
-        let assistant_message = result["choices"][0]["message"]["content"]
+        let choice = &result["choices"][0];
+        let text = choice["message"]["content"]
             .as_str()
             .unwrap_or("")
             .to_string();
 
-        let finish_reason = result["choices"][0]["finish_reason"]
+        let finish_reason = choice["finish_reason"]
             .as_str()
             .map(|s| s.to_string());
 
-        // Add assistant response to history
-        self.conversation_history.push(ChatMessage {
-            role: "assistant".to_string(),
-            content: assistant_message.clone(),
-        });
+        let tool_calls = choice["message"]["tool_calls"]
+            .as_array()
+            .map(|calls| calls.iter().map(ToolCall::from_json).collect())
+            .unwrap_or_default();
 
-        Ok(LLMResponse {
-            text: assistant_message,
-            finish_reason,
-        })
+        let usage = TokenUsage::from_json(&result["usage"]);
+
+        Ok(Completion { text, finish_reason, tool_calls, usage })
     }
 
     /// Stream a response from the LLM
-    pub async fn chat_stream<F>(&mut self, user_message: &str, mut on_chunk: F) -> Result<LLMResponse, String>
+    ///
+    /// If the model requests tool calls and a dispatcher is set, each call is
+    /// run and its result sent back in a follow-up request whose text keeps
+    /// streaming through `on_chunk`.
+    ///
+    /// Setting `cancel` stops the stream; the partial reply is kept in history
+    /// and returned with `finish_reason` `FINISH_CANCELLED`. With
+    /// `auto_continue`, a reply cut off by `max_tokens` keeps streaming.
+    pub async fn chat_stream<F>(&mut self, user_message: &str, cancel: &AtomicBool, on_chunk: F) -> Result<LLMResponse, String>
+    where
+        F: FnMut(&str),
+    {
+        self.chat_stream_with_options(user_message, &ChatOptions::default(), cancel, on_chunk).await
+    }
+
+    /// Like `chat_stream`, with `options` overriding the configured sampling
+    /// for this call only
+    pub async fn chat_stream_with_options<F>(
+        &mut self,
+        user_message: &str,
+        options: &ChatOptions,
+        cancel: &AtomicBool,
+        mut on_chunk: F,
+    ) -> Result<LLMResponse, String>
     where
         F: FnMut(&str),
     {
         // Add user message to history
-        self.conversation_history.push(ChatMessage {
-            role: "user".to_string(),
-            content: user_message.to_string(),
-        });
+        self.conversation_history.push(ChatMessage::new("user", user_message));
+        self.trim_history();
+        let history_len = self.conversation_history.len() - 1;
 
-        // Build messages array with system prompt
-        let mut messages = vec![ChatMessage {
-            role: "system".to_string(),
-            content: self.config.system_prompt.clone(),
-        }];
-        messages.extend(self.conversation_history.clone());
+        let mut rounds = 0;
+        let mut usage = None;
+        let completion = loop {
+            // Build messages array with system prompt
+            let messages = self.build_messages(&self.conversation_history);
+            let completion = match self.stream_completion(&messages, options, cancel, &mut on_chunk).await {
+                Ok(mut completion) => {
+                    usage = TokenUsage::combine(usage, completion.usage);
+                    completion.usage = usage;
+                    completion
+                }
+                Err(e) => {
+                    self.conversation_history.truncate(history_len);
+                    return Err(e);
+                }
+            };
+
+            let cancelled = completion.finish_reason.as_deref() == Some(FINISH_CANCELLED);
+            let dispatcher = match &self.tool_dispatcher {
+                Some(dispatcher) if !cancelled && !completion.tool_calls.is_empty() && rounds < MAX_TOOL_ROUNDS => dispatcher.clone(),
+                _ => break self.continue_truncated_stream(&messages, completion, options, cancel, &mut on_chunk).await,
+            };
+            rounds += 1;
 
+            // Record the tool request and each result, then continue the turn
+            self.conversation_history.push(ChatMessage::assistant(&completion.text, &completion.tool_calls));
+            for call in &completion.tool_calls {
+                let result = dispatcher(call).unwrap_or_else(|e| format!("Error: {}", e));
+                log::info!("Tool call {} ({}) dispatched", call.name, call.id);
+                self.conversation_history.push(ChatMessage::tool_result(&call.id, result));
+            }
+        };
+
+        // Add assistant response to history
+        self.record_reply(&completion.text, &completion.tool_calls);
+
+        Ok(LLMResponse {
+            finish_reason: completion.finish_reason.clone().or_else(|| Some("stop".to_string())),
+            ..self.finish(completion)
+        })
+    }
+
+    /// Send a streaming request and collect the completion
+    async fn stream_completion<F>(&self, messages: &[ChatMessage], options: &ChatOptions, cancel: &AtomicBool, on_chunk: &mut F) -> Result<Completion, String>
+    where
+        F: FnMut(&str),
+    {
         // Create the request payload
-        let payload = serde_json::json!({
+        let mut payload = serde_json::json!({
             "model": self.config.model,
             "messages": messages,
             "temperature": self.config.temperature,
             "max_tokens": self.config.max_tokens,
             "stream": true
         });
+        self.apply_sampling(&mut payload);
+        options.apply(&mut payload);
+        self.apply_tools(&mut payload);
+        if self.config.mock {
+            let completion = mock_completion(&payload["messages"]);
+            on_chunk(&completion.text);
+            return Ok(completion);
+        }
+
+        let url = format!("{}/v1/chat/completions", self.config.server_url);
+        let started = Instant::now();
+        let result = self.read_stream(&url, &payload, cancel, on_chunk).await;
+        trace::record_exchange("llm", &url, &payload, started, || {
+            result.as_ref()
+                .map(|completion| serde_json::json!({
+                    "content": completion.text,
+                    "finish_reason": completion.finish_reason,
+                    "tool_calls": completion.tool_calls,
+                    "usage": completion.usage,
+                }))
+                .map_err(|e| e.clone())
+        });
+        result
+    }
 
+    /// Send a streaming request and accumulate the SSE events into a completion
+    async fn read_stream<F>(&self, url: &str, payload: &serde_json::Value, cancel: &AtomicBool, on_chunk: &mut F) -> Result<Completion, String>
+    where
+        F: FnMut(&str),
+    {
         // Send streaming request
-        let response = self.client
-            .post(format!("{}/v1/chat/completions", self.config.server_url))
-            .json(&payload)
-            .send()
+        self.rate_limiter.acquire().await?;
+        let response = send_with_retry("LLM", self.config.max_retries, &self.retry_budget, self.authorize(self.client.post(url)).json(payload))
             .await
-            .map_err(|e| format!("Failed to send streaming LLM request: {}", e))?;
+            .map_err(|e| request_error("LLM", self.config.timeout_secs, "Failed to send streaming LLM request", e))?;
 
         if !response.status().is_success() {
-            return Err(format!("Streaming LLM request failed with status: {}", response.status()));
+            return Err(status_error("Streaming LLM request", response).await);
         }
 
-        let mut full_response = String::new();
+        let mut state = StreamState::default();
         let mut stream = response.bytes_stream();
+        // Lines may be split across network chunks, so they are reassembled first
+        let mut lines = LineBuffer::default();
+        // `data:` lines of the current event; an event ends at a blank line
+        let mut event_data: Vec<String> = Vec::new();
+        let mut done = false;
+        let mut cancelled = false;
 
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk.map_err(|e| format!("Stream error: {}", e))?;
-            let text = String::from_utf8_lossy(&chunk);
-            
-            // Parse SSE data
-            for line in text.lines() {
-                if line.starts_with("data: ") {
-                    let data = &line[6..];
-                    if data == "[DONE]" {
-                        break;
-                    }
-                    
-                    if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
-                        if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
-                            full_response.push_str(content);
-                            on_chunk(content);
-                        }
-                    }
+        loop {
+            if cancel.load(Ordering::SeqCst) {
+                cancelled = true;
+                break;
+            }
+            let chunk = match tokio::time::timeout(CANCEL_POLL_INTERVAL, stream.next()).await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(_) => continue,
+            };
+            let chunk = chunk.map_err(|e| request_error("LLM", self.config.timeout_secs, "Stream error", e))?;
+
+            // One chunk may hold several events, or only part of a line
+            for line in lines.push(&chunk) {
+                if handle_sse_line(&line, &mut event_data, &mut state, on_chunk) {
+                    done = true;
+                    break;
                 }
             }
+
+            if done {
+                break;
+            }
         }
 
-        // Add assistant response to history
-        self.conversation_history.push(ChatMessage {
-            role: "assistant".to_string(),
-            content: full_response.clone(),
-        });
+        if cancelled {
+            // Tool call arguments may be cut off mid-JSON, so only the text is kept
+            log::info!("LLM stream cancelled after {} chars", state.text.len());
+            return Ok(Completion {
+                text: state.text,
+                finish_reason: Some(FINISH_CANCELLED.to_string()),
+                tool_calls: Vec::new(),
+                usage: state.usage,
+            });
+        }
 
-        Ok(LLMResponse {
-            text: full_response,
-            finish_reason: Some("stop".to_string()),
+        // Flush a final line and event not terminated by a newline or blank line
+        if !done {
+            if let Some(line) = lines.finish() {
+                handle_sse_line(&line, &mut event_data, &mut state, on_chunk);
+            }
+            if !event_data.is_empty() {
+                handle_sse_event(&event_data.join("\n"), &mut state, on_chunk);
+            }
+        }
+
+        Ok(Completion {
+            text: state.text,
+            finish_reason: state.finish_reason,
+            tool_calls: state.tool_calls.finish(),
+            usage: state.usage,
         })
     }
 
+    /// Estimate how much of the context window the prompt and history use
+    pub fn context_usage(&self) -> ContextUsage {
+        let system = crate::text::estimate_tokens(&self.effective_system_prompt()) + MESSAGE_OVERHEAD_TOKENS;
+        let history: usize = self.conversation_history
+            .iter()
+            .map(|m| crate::text::estimate_tokens(&m.content) + MESSAGE_OVERHEAD_TOKENS)
+            .sum();
+        let used_tokens = (system + history) as u32;
+        ContextUsage {
+            used_tokens,
+            max_tokens: self.config.context_tokens,
+            messages: self.conversation_history.len(),
+            will_truncate_next: used_tokens + self.config.max_tokens > self.config.context_tokens,
+        }
+    }
+
+    /// Restore the default configuration, keeping conversation history and
+    /// the tool dispatcher
+    pub fn reset_config(&mut self) {
+        self.apply_config(QwenConfig::default());
+        self.active_profile = None;
+    }
+
+    /// Replace the whole configuration, rebuilding what depends on it
+    fn apply_config(&mut self, config: QwenConfig) {
+        self.client = build_client(config.user_agent.as_deref(), config.timeout_secs);
+        self.tool_prompt = describe_tools(&config.tools);
+        self.rate_limiter.configure(config.requests_per_minute, config.rate_limit_fail_fast);
+        self.config = config;
+        self.trim_history();
+    }
+
+    /// Store the current configuration as profile `name`, replacing any existing one
+    pub fn save_profile(&mut self, name: &str) {
+        self.profiles.insert(name.to_string(), self.config.clone());
+        self.active_profile = Some(name.to_string());
+    }
+
+    /// Remove a profile. The active configuration is left as it is.
+    pub fn remove_profile(&mut self, name: &str) -> Result<(), String> {
+        self.profiles
+            .remove(name)
+            .ok_or_else(|| format!("Unknown LLM profile: {}", name))?;
+        if self.active_profile.as_deref() == Some(name) {
+            self.active_profile = None;
+        }
+        Ok(())
+    }
+
+    /// Apply profile `name`, keeping conversation history and the tool dispatcher
+    pub fn switch_profile(&mut self, name: &str) -> Result<(), String> {
+        let config = self.profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("Unknown LLM profile: {}", name))?;
+        self.apply_config(config);
+        self.active_profile = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Names of the registered profiles, sorted
+    pub fn profile_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.profiles.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Profile the current configuration was last switched to or saved as
+    pub fn active_profile(&self) -> Option<&str> {
+        self.active_profile.as_deref()
+    }
+
     /// Clear conversation history
     pub fn clear_history(&mut self) {
         self.conversation_history.clear();
     }
 
+    /// Get conversation history (without the system prompt)
+    pub fn history(&self) -> &[ChatMessage] {
+        &self.conversation_history
+    }
+
+    /// Owned copy of the conversation history (without the system prompt)
+    pub fn get_conversation(&self) -> Vec<ChatMessage> {
+        self.history().to_vec()
+    }
+
+    /// Render the conversation history as Markdown for sharing
+    ///
+    /// Messages are headed `**User:**` / `**Assistant:**` and their text is
+    /// kept verbatim, so fenced code blocks survive. Tool calls and results are
+    /// left out. With `exported_at`, the title carries that timestamp. The
+    /// output can be read back by `conversations::parse_markdown`.
+    pub fn export_conversation_markdown(&self, exported_at: Option<&str>) -> String {
+        let mut markdown = match exported_at {
+            Some(timestamp) => format!("# Conversation ({})\n", timestamp),
+            None => "# Conversation\n".to_string(),
+        };
+        for message in &self.conversation_history {
+            let label = match message.role.as_str() {
+                "user" => "User",
+                "assistant" => "Assistant",
+                _ => continue,
+            };
+            let content = message.content.trim();
+            if content.is_empty() {
+                continue;
+            }
+            markdown.push_str(&format!("\n**{}:**\n\n{}\n", label, content));
+        }
+        markdown
+    }
+
+    /// Pin or unpin the history message at `index`
+    pub fn set_pinned(&mut self, index: usize, pinned: bool) -> Result<(), String> {
+        let count = self.conversation_history.len();
+        let message = self.conversation_history
+            .get_mut(index)
+            .ok_or_else(|| format!("Message index {} out of range (history has {})", index, count))?;
+        message.pinned = pinned;
+        Ok(())
+    }
+
+    /// Replace conversation history, e.g. when loading a saved conversation
+    pub fn set_history(&mut self, history: Vec<ChatMessage>) {
+        self.conversation_history = history;
+    }
+
+    /// Write conversation history to `path` as JSON
+    pub fn save_history(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create history directory: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(&self.conversation_history)
+            .map_err(|e| format!("Failed to serialize history: {}", e))?;
+        // Write then rename so an interrupted save never leaves a truncated file
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .map_err(|e| format!("Failed to save history: {}", e))?;
+        std::fs::rename(&tmp, path)
+            .map_err(|e| format!("Failed to save history: {}", e))
+    }
+
+    /// Replace conversation history with the JSON saved at `path`
+    pub fn load_history(&mut self, path: &Path) -> Result<(), String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read history: {}", e))?;
+        let history: Vec<ChatMessage> = serde_json::from_str(&json)
+            .map_err(|e| format!("Failed to parse history: {}", e))?;
+        self.conversation_history = history;
+        self.trim_history();
+        Ok(())
+    }
+
     /// Get current configuration
     pub fn config(&self) -> &QwenConfig {
         &self.config
     }
 
+    /// Check that the server is reachable via `/v1/models`
+    ///
+    /// The request is built up front, so the check can run without the client.
+    pub fn ping(&self) -> impl std::future::Future<Output = Result<bool, String>> {
+        let request = (!self.config.mock)
+            .then(|| self.authorize(self.client.get(format!("{}/v1/models", self.config.server_url))));
+        ping_url(request, "LLM")
+    }
+
     /// Update server URL
     pub fn set_server_url(&mut self, url: String) {
         self.config.server_url = url;
     }
 
+    /// Update the request timeout in seconds (0 disables it)
+    pub fn set_timeout(&mut self, timeout_secs: u64) {
+        self.config.timeout_secs = timeout_secs;
+        self.client = build_client(self.config.user_agent.as_deref(), timeout_secs);
+    }
+
+    /// Update the model name sent with each request, in the active profile too
+    pub fn set_model(&mut self, model: String) {
+        if let Some(profile) = self.active_profile.as_ref().and_then(|name| self.profiles.get_mut(name)) {
+            profile.model = model.clone();
+        }
+        self.config.model = model;
+    }
+
+    /// Whether the configured model accepts images
+    pub fn supports_vision(&self) -> bool {
+        self.config.vision.unwrap_or_else(|| is_vision_model(&self.config.model))
+    }
+
+    /// Declare whether the model accepts images (None = guess from its name)
+    pub fn set_vision(&mut self, vision: Option<bool>) {
+        self.config.vision = vision;
+    }
+
+    /// Update the User-Agent header (None restores the default)
+    pub fn set_user_agent(&mut self, user_agent: Option<String>) {
+        self.client = build_client(user_agent.as_deref(), self.config.timeout_secs);
+        self.config.user_agent = user_agent;
+    }
+
+    /// Set the API key sent as a bearer token (None or empty disables it)
+    pub fn set_api_key(&mut self, api_key: Option<String>) {
+        self.config.api_key = api_key;
+    }
+
+    /// Replace the additional headers sent with every request
+    pub fn set_extra_headers(&mut self, headers: HashMap<String, String>) {
+        self.config.extra_headers = headers;
+    }
+
+    /// Attach the configured credentials to a request to the LLM server
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        with_auth(request, self.config.api_key.as_deref(), &self.config.extra_headers)
+    }
+
     /// Update system prompt
     pub fn set_system_prompt(&mut self, prompt: String) {
         self.config.system_prompt = prompt;
     }
+
+    /// Apply a response length preset
+    pub fn set_response_length(&mut self, preset: ResponseLength) {
+        self.config.response_length = Some(preset);
+        self.config.max_tokens = preset.max_tokens();
+    }
+
+    /// Set the raw token cap, clearing any length preset
+    pub fn set_max_tokens(&mut self, max_tokens: u32) {
+        self.config.response_length = None;
+        self.config.max_tokens = max_tokens;
+    }
+
+    /// Set the nucleus sampling cutoff (1.0 = disabled)
+    pub fn set_top_p(&mut self, top_p: f32) {
+        self.config.top_p = top_p;
+    }
+
+    /// Set the presence and frequency penalties (0.0 = none)
+    pub fn set_penalties(&mut self, presence_penalty: f32, frequency_penalty: f32) {
+        self.config.presence_penalty = presence_penalty;
+        self.config.frequency_penalty = frequency_penalty;
+    }
+
+    /// Set the sequences that end a reply
+    pub fn set_stop(&mut self, stop: Vec<String>) {
+        self.config.stop = stop;
+    }
+
+    /// Set the `response_format` sent with every request (None = server default)
+    pub fn set_response_format(&mut self, response_format: Option<serde_json::Value>) {
+        self.config.response_format = response_format;
+    }
+
+    /// Limit the number of history messages kept (0 = no limit)
+    pub fn set_max_history(&mut self, max_messages: usize) {
+        self.config.max_history_messages = max_messages;
+        self.trim_history();
+    }
+
+    /// Replace the tool definitions and regenerate their description
+    pub fn set_tools(&mut self, tools: Vec<serde_json::Value>) {
+        self.tool_prompt = describe_tools(&tools);
+        self.config.tools = tools;
+    }
+
+    /// Share the pipeline's retry budget with this client's requests
+    pub fn set_retry_budget(&mut self, budget: Arc<RetryBudget>) {
+        self.retry_budget = budget;
+    }
+
+    /// Limit requests to the LLM server per minute (0 = unlimited)
+    pub fn set_rate_limit(&mut self, requests_per_minute: u32, fail_fast: bool) {
+        self.config.requests_per_minute = requests_per_minute;
+        self.config.rate_limit_fail_fast = fail_fast;
+        self.rate_limiter.configure(requests_per_minute, fail_fast);
+    }
+
+    /// Set how many times a request is retried on 5xx and connection errors
+    pub fn set_max_retries(&mut self, max_retries: u32) {
+        self.config.max_retries = max_retries;
+    }
+
+    /// Enable or disable mock mode
+    pub fn set_mock(&mut self, enabled: bool) {
+        self.config.mock = enabled;
+    }
+
+    /// Enable or disable continuing replies cut off by `max_tokens`
+    pub fn set_auto_continue(&mut self, enabled: bool) {
+        self.config.auto_continue = enabled;
+    }
+
+    /// Enable or disable assistant follow-up questions
+    pub fn set_follow_up_questions(&mut self, enabled: bool) {
+        self.config.follow_up_questions = enabled;
+    }
+
+    /// Set the tool choice sent alongside tools (None lets the server decide)
+    pub fn set_tool_choice(&mut self, tool_choice: Option<serde_json::Value>) {
+        self.config.tool_choice = tool_choice;
+    }
+
+    /// Set the dispatcher that executes tool calls during streamed turns
+    pub fn set_tool_dispatcher(&mut self, dispatcher: Option<ToolDispatcher>) {
+        self.tool_dispatcher = dispatcher;
+    }
+
+    /// Enable or disable tool descriptions in the system prompt
+    pub fn set_inject_tool_descriptions(&mut self, enabled: bool) {
+        self.config.inject_tool_descriptions = enabled;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed SSE lines through `handle_sse_line`, returning the streamed chunks
+    /// and whether `[DONE]` was seen
+    fn run_sse(lines: &[&str], state: &mut StreamState) -> (Vec<String>, bool) {
+        let mut chunks = Vec::new();
+        let mut event_data = Vec::new();
+        let mut on_chunk = |chunk: &str| chunks.push(chunk.to_string());
+        let mut done = false;
+        for line in lines {
+            done |= handle_sse_line(line, &mut event_data, state, &mut on_chunk);
+        }
+        (chunks, done)
+    }
+
+    #[test]
+    fn joins_multi_line_sse_data() {
+        let mut state = StreamState::default();
+        let (chunks, done) = run_sse(&[
+            r#"data: {"choices":[{"delta":"#,
+            r#"data: {"content":"Hello"}}]}"#,
+            "",
+            ": keep-alive",
+            r#"data: {"choices":[{"delta":{"content":" world"},"finish_reason":"stop"}]}"#,
+            "",
+            "data: [DONE]",
+            "",
+        ], &mut state);
+        assert_eq!(chunks, ["Hello", " world"]);
+        assert!(done);
+        assert_eq!(state.text, "Hello world");
+        assert_eq!(state.finish_reason.as_deref(), Some("stop"));
+    }
+
+    #[test]
+    fn waits_for_blank_line_before_dispatching() {
+        let mut state = StreamState::default();
+        let (chunks, done) = run_sse(&[r#"data: {"choices":[{"delta":{"content":"Hi"}}]}"#], &mut state);
+        assert!(chunks.is_empty());
+        assert!(!done);
+    }
+
+    #[test]
+    fn line_buffer_rejoins_split_utf8_and_lines() {
+        let mut lines = LineBuffer::default();
+        let text = "data: 你好\r\n\ndata: 世";
+        let bytes = text.as_bytes();
+        // Split inside the three-byte "你" and again inside the CRLF
+        let cut = "data: ".len() + 1;
+        assert!(lines.push(&bytes[..cut]).is_empty());
+        assert!(lines.push(&bytes[cut..cut + 6]).is_empty());
+        assert_eq!(lines.push(&bytes[cut + 6..cut + 8]), ["data: 你好", ""]);
+        assert!(lines.push(&bytes[cut + 8..]).is_empty());
+        assert_eq!(lines.finish().as_deref(), Some("data: 世"));
+        assert_eq!(lines.finish(), None);
+    }
+
+    #[test]
+    fn reassembles_fragmented_tool_calls() {
+        let mut calls = ToolCallAccumulator::default();
+        calls.push_delta(&serde_json::json!([
+            {"index": 0, "id": "call_a", "function": {"name": "get_weather", "arguments": ""}},
+        ]));
+        calls.push_delta(&serde_json::json!([
+            {"index": 1, "id": "call_b", "function": {"name": "get_time", "arguments": "{\"tz\":"}},
+            {"index": 0, "function": {"arguments": "{\"city\":"}},
+        ]));
+        calls.push_delta(&serde_json::json!([
+            {"index": 0, "function": {"arguments": "\"Paris\"}"}},
+            {"index": 1, "id": "", "function": {"arguments": "\"UTC\"}"}},
+        ]));
+
+        let calls = calls.finish();
+        assert_eq!(calls.len(), 2);
+        assert_eq!((calls[0].id.as_str(), calls[0].name.as_str()), ("call_a", "get_weather"));
+        assert_eq!(calls[0].arguments, r#"{"city":"Paris"}"#);
+        assert_eq!((calls[1].id.as_str(), calls[1].name.as_str()), ("call_b", "get_time"));
+        assert_eq!(calls[1].arguments, r#"{"tz":"UTC"}"#);
+    }
+
+    #[test]
+    fn tools_need_a_dispatcher() {
+        let mut llm = QwenLLM::new(QwenConfig::default());
+        llm.set_tools(vec![serde_json::json!({"type": "function", "function": {"name": "get_time"}})]);
+        let mut payload = serde_json::json!({});
+        llm.apply_tools(&mut payload);
+        assert!(payload.get("tools").is_none());
+
+        llm.set_tool_dispatcher(Some(Arc::new(|_: &ToolCall| Ok("12:00".to_string()))));
+        llm.apply_tools(&mut payload);
+        assert_eq!(payload["tools"].as_array().map(Vec::len), Some(1));
+    }
+
+    #[test]
+    fn unanswered_tool_calls_get_error_results() {
+        let mut llm = QwenLLM::new(QwenConfig::default());
+        let call = ToolCall { id: "call_a".to_string(), name: "get_time".to_string(), arguments: "{}".to_string() };
+        llm.record_reply("", &[call]);
+
+        let history = llm.history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].tool_calls.as_ref().map(Vec::len), Some(1));
+        assert_eq!(history[1].role, "tool");
+        assert_eq!(history[1].tool_call_id.as_deref(), Some("call_a"));
+        assert_eq!(history[1].content, TOOL_NOT_RUN);
+    }
+
+    #[test]
+    fn parses_plain_json_replies() {
+        assert_eq!(parse_json_reply(" {\"ok\": true}\n"), Some(serde_json::json!({"ok": true})));
+    }
+
+    #[test]
+    fn parses_fenced_json_replies() {
+        let expected = Some(serde_json::json!({"items": [1, 2]}));
+        assert_eq!(parse_json_reply("```json\n{\"items\": [1, 2]}\n```"), expected);
+        assert_eq!(parse_json_reply("```\n{\"items\": [1, 2]}\n```  "), expected);
+    }
+
+    #[test]
+    fn rejects_replies_that_are_not_json() {
+        assert_eq!(parse_json_reply("Sure! {\"ok\": true}"), None);
+        assert_eq!(parse_json_reply("```json\n{\"ok\": \n```"), None);
+    }
+
+    #[test]
+    fn chat_options_validate_ranges() {
+        assert!(ChatOptions::default().validate().is_ok());
+        let valid = ChatOptions { temperature: Some(2.0), max_tokens: Some(1), top_p: Some(1.0) };
+        assert!(valid.validate().is_ok());
+        for invalid in [
+            ChatOptions { temperature: Some(2.5), ..ChatOptions::default() },
+            ChatOptions { temperature: Some(-0.1), ..ChatOptions::default() },
+            ChatOptions { max_tokens: Some(0), ..ChatOptions::default() },
+            ChatOptions { top_p: Some(0.0), ..ChatOptions::default() },
+            ChatOptions { top_p: Some(1.5), ..ChatOptions::default() },
+        ] {
+            assert!(invalid.validate().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn chat_options_override_only_set_fields() {
+        let mut payload = serde_json::json!({"temperature": 0.7, "max_tokens": 512, "top_p": 0.9});
+        ChatOptions { max_tokens: Some(64), ..ChatOptions::default() }.apply(&mut payload);
+        assert_eq!(payload, serde_json::json!({"temperature": 0.7, "max_tokens": 64, "top_p": 0.9}));
+
+        ChatOptions { temperature: Some(0.0), max_tokens: None, top_p: Some(0.5) }.apply(&mut payload);
+        assert_eq!(payload, serde_json::json!({"temperature": 0.0, "max_tokens": 64, "top_p": 0.5}));
+    }
+
+    #[test]
+    fn set_model_updates_active_profile() {
+        let mut llm = QwenLLM::new(QwenConfig::default());
+        llm.save_profile("fast");
+        llm.set_model("qwen-coder".to_string());
+        llm.save_profile("coding");
+        llm.switch_profile("fast").unwrap();
+        llm.set_model("qwen-small".to_string());
+
+        llm.switch_profile("coding").unwrap();
+        assert_eq!(llm.config().model, "qwen-coder");
+        llm.switch_profile("fast").unwrap();
+        assert_eq!(llm.config().model, "qwen-small");
+    }
+
+    #[test]
+    fn drops_tool_calls_without_a_name() {
+        let mut calls = ToolCallAccumulator::default();
+        calls.push_delta(&serde_json::json!([{"index": 1, "id": "call_b", "function": {"name": "lookup"}}]));
+        let calls = calls.finish();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].name, "lookup");
+    }
 }
@@ -1,6 +1,10 @@
 pub mod asr;
 pub mod llm;
 pub mod tts;
+pub mod retry;
+pub mod rate_limit;
+pub mod vad;
+pub mod fallback;
 
 #[cfg(feature = "embedded-services")]
 pub mod embedded;
@@ -8,6 +12,158 @@ pub mod embedded;
 pub use asr::WhisperLiveKit;
 pub use llm::QwenLLM;
 pub use tts::VoxCPMTTS;
+pub use retry::RetryBudget;
+pub use rate_limit::RateLimiter;
+pub use vad::VadConfig;
+
+/// User-Agent sent with service requests unless overridden
+pub const DEFAULT_USER_AGENT: &str = concat!("assidenter/", env!("CARGO_PKG_VERSION"));
+
+/// Default request timeout for the ASR and TTS services, in seconds
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// Timeout of a health check request
+pub const PING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// Send a GET to a lightweight endpoint and report whether it answered with success
+///
+/// Connection failures and timeouts are errors; an error status is `Ok(false)`.
+/// No request (a mock server) always passes.
+pub async fn ping_url(request: Option<reqwest::RequestBuilder>, service: &str) -> Result<bool, String> {
+    let Some(request) = request else {
+        return Ok(true);
+    };
+    let response = request
+        .timeout(PING_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| request_error(service, PING_TIMEOUT.as_secs(), &format!("{} is unreachable", service), e))?;
+    Ok(response.status().is_success())
+}
+
+/// Build the HTTP client shared by a service's requests
+///
+/// `timeout_secs` bounds each whole request, including reading the body;
+/// 0 disables the timeout.
+pub fn build_client(user_agent: Option<&str>, timeout_secs: u64) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(user_agent.unwrap_or(DEFAULT_USER_AGENT));
+    if timeout_secs > 0 {
+        builder = builder.timeout(std::time::Duration::from_secs(timeout_secs));
+    }
+    builder
+        .build()
+        .unwrap_or_else(|e| {
+            log::warn!("Failed to build HTTP client, using defaults: {}", e);
+            reqwest::Client::new()
+        })
+}
+
+/// Attach a service's credentials to a request
+///
+/// `api_key` is sent as `Authorization: Bearer <key>` (marked sensitive so it
+/// never shows up in debug output); `extra_headers` are added as given.
+pub fn with_auth(
+    mut request: reqwest::RequestBuilder,
+    api_key: Option<&str>,
+    extra_headers: &std::collections::HashMap<String, String>,
+) -> reqwest::RequestBuilder {
+    for (name, value) in extra_headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    if let Some(key) = api_key.map(str::trim).filter(|key| !key.is_empty()) {
+        match reqwest::header::HeaderValue::from_str(&format!("Bearer {}", key)) {
+            Ok(mut value) => {
+                value.set_sensitive(true);
+                request = request.header(reqwest::header::AUTHORIZATION, value);
+            }
+            Err(_) => log::warn!("API key contains characters not allowed in a header, not sending it"),
+        }
+    }
+    request
+}
+
+/// Error message for a failed request, naming the timeout when that was the cause
+///
+/// `service` names the service in the timeout message ("ASR request timed out
+/// after 30s"); `context` prefixes any other error.
+pub fn request_error(service: &str, timeout_secs: u64, context: &str, e: reqwest::Error) -> String {
+    if e.is_timeout() {
+        format!("{} request timed out after {}s", service, timeout_secs)
+    } else {
+        format!("{}: {}", context, e)
+    }
+}
+
+/// Longest body excerpt quoted in error messages, in characters
+const ERROR_BODY_LIMIT: usize = 300;
+
+/// Readable excerpt of a response body for error messages
+///
+/// Decodes lossily, strips tags from HTML error pages, collapses whitespace
+/// and truncates. Bodies that are mostly binary are summarized by size.
+pub fn body_snippet(body: &[u8]) -> String {
+    let text = String::from_utf8_lossy(body);
+    let unreadable = text.chars()
+        .filter(|&c| c == char::REPLACEMENT_CHARACTER || (c.is_control() && !c.is_whitespace()))
+        .count();
+    if unreadable * 10 > text.chars().count() {
+        return format!("<{} bytes of binary data>", body.len());
+    }
+
+    let lower = text.to_lowercase();
+    let text = if lower.contains("<html") || lower.contains("<!doctype") {
+        let mut stripped = String::with_capacity(text.len());
+        let mut in_tag = false;
+        for c in text.chars() {
+            match c {
+                '<' => in_tag = true,
+                '>' if in_tag => {
+                    in_tag = false;
+                    stripped.push(' ');
+                }
+                _ if !in_tag => stripped.push(c),
+                _ => {}
+            }
+        }
+        stripped
+    } else {
+        text.into_owned()
+    };
+
+    let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.chars().count() > ERROR_BODY_LIMIT {
+        let truncated: String = collapsed.chars().take(ERROR_BODY_LIMIT).collect();
+        format!("{}...", truncated)
+    } else {
+        collapsed
+    }
+}
+
+/// Error message for a non-success response, quoting the body
+pub async fn status_error(context: &str, response: reqwest::Response) -> String {
+    let status = response.status();
+    let body = response.bytes().await.unwrap_or_default();
+    let snippet = body_snippet(&body);
+    if snippet.is_empty() {
+        format!("{} failed with status: {}", context, status)
+    } else {
+        format!("{} failed with status: {}: {}", context, status, snippet)
+    }
+}
+
+/// Parse a JSON response body, quoting the body if it is not valid JSON
+pub async fn read_json(context: &str, response: reqwest::Response) -> Result<serde_json::Value, String> {
+    let body = response.bytes()
+        .await
+        .map_err(|e| if e.is_timeout() {
+            format!("{} response timed out", context)
+        } else {
+            format!("Failed to read {} response: {}", context, e)
+        })?;
+    serde_json::from_slice(&body)
+        .map_err(|e| format!("Failed to parse {} response: {} (body: {})", context, e, body_snippet(&body)))
+}
 
 // Service mode configuration
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -18,6 +174,26 @@ pub enum ServiceMode {
     Embedded,
 }
 
+impl ServiceMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ServiceMode::Remote => "remote",
+            ServiceMode::Embedded => "embedded",
+        }
+    }
+
+    /// Parse "remote" or "embedded", rejecting embedded mode in builds without
+    /// the `embedded-services` feature
+    pub fn parse(mode: &str) -> Result<Self, String> {
+        match mode.trim().to_lowercase().as_str() {
+            "remote" => Ok(ServiceMode::Remote),
+            "embedded" if cfg!(feature = "embedded-services") => Ok(ServiceMode::Embedded),
+            "embedded" => Err("Embedded mode requires a build with the embedded-services feature".to_string()),
+            other => Err(format!("Unknown service mode: {}", other)),
+        }
+    }
+}
+
 impl Default for ServiceMode {
     fn default() -> Self {
         #[cfg(feature = "embedded-services")]
@@ -27,3 +203,50 @@ impl Default for ServiceMode {
         return ServiceMode::Remote;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::tungstenite::http;
+
+    const HTML_ERROR: &str = "<!DOCTYPE html>\n<html><head><title>500 Internal Server Error</title></head>\n\
+        <body>\n  <h1>Internal   Server Error</h1>\n  <p>The model failed to load.</p>\n</body></html>";
+
+    #[test]
+    fn strips_html_error_pages() {
+        assert_eq!(
+            body_snippet(HTML_ERROR.as_bytes()),
+            "500 Internal Server Error Internal Server Error The model failed to load."
+        );
+    }
+
+    #[test]
+    fn truncates_long_bodies() {
+        let snippet = body_snippet("x".repeat(ERROR_BODY_LIMIT + 50).as_bytes());
+        assert_eq!(snippet.chars().count(), ERROR_BODY_LIMIT + 3);
+        assert!(snippet.ends_with("..."));
+    }
+
+    #[test]
+    fn summarizes_binary_bodies() {
+        assert_eq!(body_snippet(&[0xff, 0x00, 0xfe, 0x01, 0x02]), "<5 bytes of binary data>");
+    }
+
+    #[tokio::test]
+    async fn status_error_quotes_html_body() {
+        let response = http::Response::builder().status(500).body(HTML_ERROR).unwrap();
+        let message = status_error("LLM request", reqwest::Response::from(response)).await;
+        assert_eq!(
+            message,
+            "LLM request failed with status: 500 Internal Server Error: \
+             500 Internal Server Error Internal Server Error The model failed to load."
+        );
+    }
+
+    #[tokio::test]
+    async fn status_error_without_body() {
+        let response = http::Response::builder().status(503).body("").unwrap();
+        let message = status_error("TTS request", reqwest::Response::from(response)).await;
+        assert_eq!(message, "TTS request failed with status: 503 Service Unavailable");
+    }
+}
@@ -0,0 +1,188 @@
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// Payload of the `rate-limited-locally` event
+#[derive(Debug, Clone, Serialize)]
+pub struct RateLimitEvent {
+    /// "asr", "llm" or "tts"
+    pub service: &'static str,
+    /// How long the request waits for budget (0 when it failed fast)
+    pub wait_ms: u64,
+    /// The request was rejected instead of waiting
+    pub rejected: bool,
+}
+
+type Listener = Box<dyn Fn(&RateLimitEvent) + Send + Sync>;
+
+/// Called whenever a limiter throttles a request
+static LISTENER: OnceLock<Listener> = OnceLock::new();
+
+/// Register the throttling listener (only the first registration takes effect)
+pub fn set_listener<F>(listener: F)
+where
+    F: Fn(&RateLimitEvent) + Send + Sync + 'static,
+{
+    let _ = LISTENER.set(Box::new(listener));
+}
+
+fn notify(event: RateLimitEvent) {
+    log::warn!(
+        "{} rate limit reached, {}",
+        event.service,
+        if event.rejected { "request rejected".to_string() } else { format!("waiting {}ms", event.wait_ms) }
+    );
+    if let Some(listener) = LISTENER.get() {
+        listener(&event);
+    }
+}
+
+struct Bucket {
+    requests_per_minute: u32,
+    fail_fast: bool,
+    /// Available requests; negative while callers are queued for budget
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        let capacity = self.requests_per_minute as f64;
+        self.tokens = (self.tokens + elapsed * capacity / 60.0).min(capacity);
+        self.last_refill = now;
+    }
+}
+
+/// Client-side token bucket limiting requests per minute to one service
+///
+/// The bucket holds up to a minute's worth of requests, so short bursts go
+/// through immediately and sustained traffic is paced to the configured rate.
+/// A limit of 0 disables limiting.
+pub struct RateLimiter {
+    service: &'static str,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(service: &'static str, requests_per_minute: u32, fail_fast: bool) -> Self {
+        Self {
+            service,
+            bucket: Mutex::new(Bucket {
+                requests_per_minute,
+                fail_fast,
+                tokens: requests_per_minute as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Change the limit and refill the bucket
+    pub fn configure(&self, requests_per_minute: u32, fail_fast: bool) {
+        if let Ok(mut bucket) = self.bucket.lock() {
+            bucket.requests_per_minute = requests_per_minute;
+            bucket.fail_fast = fail_fast;
+            bucket.tokens = requests_per_minute as f64;
+            bucket.last_refill = Instant::now();
+        }
+    }
+
+    /// Reserve budget for one request. Returns how long to wait before sending.
+    fn reserve(&self) -> Result<Duration, String> {
+        let mut bucket = self.bucket.lock().map_err(|e| e.to_string())?;
+        if bucket.requests_per_minute == 0 {
+            return Ok(Duration::ZERO);
+        }
+
+        bucket.refill();
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(Duration::ZERO);
+        }
+
+        if bucket.fail_fast {
+            return Err(format!(
+                "Local {} rate limit of {} requests per minute reached",
+                self.service, bucket.requests_per_minute
+            ));
+        }
+
+        // Take the token now so queued callers are spaced one interval apart
+        let per_second = bucket.requests_per_minute as f64 / 60.0;
+        let wait = Duration::from_secs_f64((1.0 - bucket.tokens) / per_second);
+        bucket.tokens -= 1.0;
+        Ok(wait)
+    }
+
+    /// Wait until the budget allows another request, or fail if configured to
+    pub async fn acquire(&self) -> Result<(), String> {
+        match self.reserve() {
+            Ok(wait) if wait.is_zero() => Ok(()),
+            Ok(wait) => {
+                notify(RateLimitEvent {
+                    service: self.service,
+                    wait_ms: wait.as_millis() as u64,
+                    rejected: false,
+                });
+                tokio::time::sleep(wait).await;
+                Ok(())
+            }
+            Err(e) => {
+                notify(RateLimitEvent { service: self.service, wait_ms: 0, rejected: true });
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(wait: Duration, expected_secs: f64) {
+        let secs = wait.as_secs_f64();
+        assert!((secs - expected_secs).abs() < 0.05, "waited {}s, expected {}s", secs, expected_secs);
+    }
+
+    #[test]
+    fn bursts_up_to_capacity_then_paces() {
+        let limiter = RateLimiter::new("llm", 60, false);
+        for _ in 0..60 {
+            assert_eq!(limiter.reserve().unwrap(), Duration::ZERO);
+        }
+        // Queued callers are spaced one interval (1s at 60/min) apart
+        assert_near(limiter.reserve().unwrap(), 1.0);
+        assert_near(limiter.reserve().unwrap(), 2.0);
+        assert_near(limiter.reserve().unwrap(), 3.0);
+    }
+
+    #[test]
+    fn fail_fast_rejects_over_the_limit() {
+        let limiter = RateLimiter::new("tts", 2, true);
+        assert!(limiter.reserve().is_ok());
+        assert!(limiter.reserve().is_ok());
+        assert_eq!(
+            limiter.reserve().unwrap_err(),
+            "Local tts rate limit of 2 requests per minute reached"
+        );
+    }
+
+    #[test]
+    fn zero_disables_limiting() {
+        let limiter = RateLimiter::new("asr", 0, true);
+        for _ in 0..1000 {
+            assert_eq!(limiter.reserve().unwrap(), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn configure_refills_the_bucket() {
+        let limiter = RateLimiter::new("asr", 1, false);
+        assert_eq!(limiter.reserve().unwrap(), Duration::ZERO);
+        assert_near(limiter.reserve().unwrap(), 60.0);
+        limiter.configure(120, false);
+        assert_eq!(limiter.reserve().unwrap(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,139 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Default time the whole pipeline may spend retrying before giving up
+pub const DEFAULT_RETRY_BUDGET_MS: u64 = 30_000;
+
+/// Initial backoff delay, doubled after each failed attempt
+const INITIAL_BACKOFF_MS: u64 = 500;
+
+/// Default retries of a single HTTP request on 5xx and connection errors
+pub const DEFAULT_HTTP_RETRIES: u32 = 2;
+
+pub fn default_http_retries() -> u32 {
+    DEFAULT_HTTP_RETRIES
+}
+
+/// Retry time budget shared by every request of a pipeline run
+///
+/// Each retried request charges the failed attempt's duration plus the
+/// backoff delay against the budget. Once exhausted, no request retries again
+/// until `reset`.
+pub struct RetryBudget {
+    total_ms: AtomicU64,
+    remaining_ms: AtomicU64,
+}
+
+impl RetryBudget {
+    pub fn new(total_ms: u64) -> Self {
+        Self {
+            total_ms: AtomicU64::new(total_ms),
+            remaining_ms: AtomicU64::new(total_ms),
+        }
+    }
+
+    /// Refill the budget (called at the start of each pipeline run)
+    pub fn reset(&self) {
+        self.remaining_ms.store(self.total_ms.load(Ordering::SeqCst), Ordering::SeqCst);
+    }
+
+    /// Total budget per pipeline run
+    pub fn total_ms(&self) -> u64 {
+        self.total_ms.load(Ordering::SeqCst)
+    }
+
+    /// Update the total budget and refill
+    pub fn set_total_ms(&self, total_ms: u64) {
+        self.total_ms.store(total_ms, Ordering::SeqCst);
+        self.reset();
+    }
+
+    /// Budget left in the current run
+    pub fn remaining_ms(&self) -> u64 {
+        self.remaining_ms.load(Ordering::SeqCst)
+    }
+
+    /// Whether the budget has run out
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining_ms() == 0
+    }
+
+    /// Charge `ms` against the budget. Returns false if there was not enough left,
+    /// in which case the budget is drained to zero.
+    pub fn try_consume(&self, ms: u64) -> bool {
+        let mut current = self.remaining_ms.load(Ordering::SeqCst);
+        loop {
+            let (next, ok) = if current >= ms { (current - ms, true) } else { (0, false) };
+            match self.remaining_ms.compare_exchange(current, next, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return ok,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+impl Default for RetryBudget {
+    fn default() -> Self {
+        Self::new(DEFAULT_RETRY_BUDGET_MS)
+    }
+}
+
+/// Randomize a backoff delay to between half and all of `ms`, so clients that
+/// failed together do not retry in lockstep
+fn jittered(ms: u64) -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u64(ms);
+    ms / 2 + hasher.finish() % (ms / 2 + 1)
+}
+
+/// Whether a failed send is worth repeating (connection problems and timeouts)
+fn is_transient(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout() || e.is_request()
+}
+
+/// Send `request`, retrying up to `max_retries` times with jittered
+/// exponential backoff on 5xx responses and connection errors
+///
+/// 4xx responses are returned as-is. Each retry is charged against `budget`;
+/// once it runs out, or after the last attempt, the final response or error
+/// is returned for the caller to report. Requests whose body cannot be cloned
+/// (streams) are sent once.
+pub async fn send_with_retry(
+    service: &str,
+    max_retries: u32,
+    budget: &RetryBudget,
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response, reqwest::Error> {
+    let mut backoff_ms = INITIAL_BACKOFF_MS;
+    let mut attempt = 0;
+
+    loop {
+        let retry = if attempt < max_retries { request.try_clone() } else { None };
+        let Some(next) = retry else {
+            return request.send().await;
+        };
+
+        let started = Instant::now();
+        let outcome = next.send().await;
+        let reason = match &outcome {
+            Ok(response) if response.status().is_server_error() => response.status().to_string(),
+            Err(e) if is_transient(e) => e.to_string(),
+            _ => return outcome,
+        };
+
+        let delay = jittered(backoff_ms);
+        if !budget.try_consume(started.elapsed().as_millis() as u64 + delay) {
+            log::warn!("{} request failed and retry budget is exhausted: {}", service, reason);
+            return outcome;
+        }
+
+        attempt += 1;
+        log::warn!(
+            "{} request failed (attempt {}/{}), retrying in {}ms: {}",
+            service, attempt, max_retries + 1, delay, reason
+        );
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+        backoff_ms *= 2;
+    }
+}
@@ -1,6 +1,13 @@
 use serde::{Deserialize, Serialize};
 use reqwest::Client;
+use super::{build_client, ping_url, with_auth, read_json, request_error, status_error, RateLimiter, DEFAULT_TIMEOUT_SECS};
+use super::retry::{send_with_retry, RetryBudget};
 use base64::{Engine as _, engine::general_purpose::STANDARD};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use crate::text::{speech_chunks, DEFAULT_MAX_SENTENCE_CHARS};
+use crate::trace;
 
 /// VoxCPM TTS configuration
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -9,6 +16,63 @@ pub struct VoxCPMConfig {
     pub voice: String,
     pub speed: f32,
     pub sample_rate: u32,
+    /// User-Agent header (defaults to assidenter/<version>)
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// Sent as `Authorization: Bearer <key>`; never logged or sent to the webview
+    #[serde(default, skip_serializing)]
+    pub api_key: Option<String>,
+    /// Additional headers sent with every request
+    #[serde(default)]
+    pub extra_headers: HashMap<String, String>,
+    /// Request timeout in seconds (0 = none)
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Normalize synthesized audio to `target_rms` so voices play at a consistent level
+    #[serde(default)]
+    pub normalize_output: bool,
+    #[serde(default = "default_target_rms")]
+    pub target_rms: f32,
+    /// Request phoneme/word timings for lip-sync
+    #[serde(default)]
+    pub return_alignment: bool,
+    /// Output channels (1 = mono, 2 = stereo); audio is converted if the server differs
+    #[serde(default = "default_channels")]
+    pub channels: u16,
+    /// Client-side request limit per minute (0 = unlimited)
+    #[serde(default)]
+    pub requests_per_minute: u32,
+    /// Fail requests over the limit instead of waiting for budget
+    #[serde(default)]
+    pub rate_limit_fail_fast: bool,
+    /// Retries of a request on 5xx and connection errors
+    #[serde(default = "super::retry::default_http_retries")]
+    pub max_retries: u32,
+    /// Return short silent audio instead of contacting the server (for offline development)
+    #[serde(default)]
+    pub mock: bool,
+    /// Emit response audio as `tts-audio-chunk` events with chunk metadata
+    #[serde(default)]
+    pub stream_sentences: bool,
+    /// Longest piece of text sent in one synthesis request, in characters
+    #[serde(default = "default_max_chunk_chars")]
+    pub max_chunk_chars: usize,
+}
+
+fn default_timeout_secs() -> u64 {
+    DEFAULT_TIMEOUT_SECS
+}
+
+fn default_max_chunk_chars() -> usize {
+    DEFAULT_MAX_SENTENCE_CHARS
+}
+
+fn default_channels() -> u16 {
+    1
+}
+
+fn default_target_rms() -> f32 {
+    crate::audio::DEFAULT_TARGET_RMS
 }
 
 impl Default for VoxCPMConfig {
@@ -18,53 +82,187 @@ impl Default for VoxCPMConfig {
             voice: "default".to_string(),
             speed: 1.0,
             sample_rate: 22050,
+            user_agent: None,
+            api_key: None,
+            extra_headers: HashMap::new(),
+            timeout_secs: default_timeout_secs(),
+            normalize_output: false,
+            target_rms: default_target_rms(),
+            return_alignment: false,
+            channels: default_channels(),
+            requests_per_minute: 0,
+            rate_limit_fail_fast: false,
+            max_retries: super::retry::DEFAULT_HTTP_RETRIES,
+            mock: false,
+            stream_sentences: false,
+            max_chunk_chars: default_max_chunk_chars(),
         }
     }
 }
 
+/// Timing of one phoneme or word in synthesized audio
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlignmentToken {
+    pub token: String,
+    /// Start time in seconds
+    pub start: f64,
+    /// End time in seconds
+    pub end: f64,
+}
+
 /// TTS synthesis result
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TTSResult {
     pub audio_data: Vec<u8>,
     pub sample_rate: u32,
     pub duration: f64,
+    /// Phoneme or word timings (only when `return_alignment` is set)
+    #[serde(default)]
+    pub alignment: Option<Vec<AlignmentToken>>,
+}
+
+/// Length of the silent audio returned in mock mode, in seconds
+const MOCK_AUDIO_SECS: f64 = 0.5;
+
+/// A voice offered by the TTS server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoiceInfo {
+    /// Value to use as `VoxCPMConfig.voice`
+    pub id: String,
+    pub name: String,
+    pub language: Option<String>,
+    pub gender: Option<String>,
+}
+
+impl VoiceInfo {
+    /// Parse a voice entry, which may be a bare id string or an object
+    fn from_json(value: &serde_json::Value) -> Option<Self> {
+        if let Some(id) = value.as_str() {
+            return Some(Self { id: id.to_string(), name: id.to_string(), language: None, gender: None });
+        }
+        let id = value["id"].as_str().or_else(|| value["name"].as_str())?.to_string();
+        Some(Self {
+            name: value["name"].as_str().unwrap_or(&id).to_string(),
+            language: value["language"].as_str().map(str::to_string),
+            gender: value["gender"].as_str().map(str::to_string),
+            id,
+        })
+    }
+}
+
+/// Parse an alignment array returned by the server
+fn parse_alignment(value: &serde_json::Value) -> Option<Vec<AlignmentToken>> {
+    let tokens: Vec<AlignmentToken> = value
+        .as_array()?
+        .iter()
+        .filter_map(|entry| {
+            let token = entry["token"].as_str()
+                .or_else(|| entry["phoneme"].as_str())
+                .or_else(|| entry["word"].as_str())?;
+            Some(AlignmentToken {
+                token: token.to_string(),
+                start: entry["start"].as_f64()?,
+                end: entry["end"].as_f64()?,
+            })
+        })
+        .collect();
+    (!tokens.is_empty()).then_some(tokens)
+}
+
+/// Rough word timings spread over `duration` in proportion to word length
+fn estimate_word_alignment(text: &str, duration: f64) -> Option<Vec<AlignmentToken>> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let total_chars: usize = words.iter().map(|w| w.chars().count()).sum();
+    if total_chars == 0 || duration <= 0.0 {
+        return None;
+    }
+
+    let mut start = 0.0;
+    Some(
+        words
+            .iter()
+            .map(|word| {
+                let end = start + duration * word.chars().count() as f64 / total_chars as f64;
+                let token = AlignmentToken { token: word.to_string(), start, end };
+                start = end;
+                token
+            })
+            .collect(),
+    )
 }
 
 /// VoxCPM TTS service client
 pub struct VoxCPMTTS {
     config: VoxCPMConfig,
     client: Client,
+    rate_limiter: RateLimiter,
+    /// Charged by retried requests; shared with the pipeline
+    retry_budget: Arc<RetryBudget>,
 }
 
 impl VoxCPMTTS {
     pub fn new(config: VoxCPMConfig) -> Self {
-        Self {
-            config,
-            client: Client::new(),
-        }
+        let client = build_client(config.user_agent.as_deref(), config.timeout_secs);
+        let rate_limiter = RateLimiter::new("tts", config.requests_per_minute, config.rate_limit_fail_fast);
+        Self { config, client, rate_limiter, retry_budget: Arc::new(RetryBudget::default()) }
     }
 
     /// Synthesize text to speech
     pub async fn synthesize(&self, text: &str) -> Result<TTSResult, String> {
+        if self.config.mock {
+            let channels = self.config.channels.max(1);
+            let frames = (self.config.sample_rate as f64 * MOCK_AUDIO_SECS) as usize;
+            let silence = vec![0i16; frames * channels as usize];
+            return Ok(TTSResult {
+                audio_data: crate::audio::encode_wav(&silence, self.config.sample_rate, channels),
+                sample_rate: self.config.sample_rate,
+                duration: MOCK_AUDIO_SECS,
+                alignment: None,
+            });
+        }
+
         // Create the request payload
         let payload = serde_json::json!({
             "text": text,
             "voice": self.config.voice,
             "speed": self.config.speed,
             "sample_rate": self.config.sample_rate,
-            "format": "wav"
+            "channels": self.config.channels,
+            "format": "wav",
+            "return_alignment": self.config.return_alignment
         });
 
+        let url = format!("{}/tts", self.config.server_url);
+        let started = Instant::now();
+        let result = self.request_synthesis(&url, &payload, text).await;
+        trace::record_exchange("tts", &url, &payload, started, || {
+            result.as_ref()
+                .map(|tts| serde_json::json!({
+                    "audio_bytes": tts.audio_data.len(),
+                    "sample_rate": tts.sample_rate,
+                    "duration": tts.duration,
+                    "alignment": tts.alignment,
+                }))
+                .map_err(|e| e.clone())
+        });
+        result
+    }
+
+    /// Split text into the pieces synthesized one request at a time
+    pub fn speech_chunks(&self, text: &str, language: &str) -> Vec<String> {
+        speech_chunks(text, language, self.config.max_chunk_chars)
+    }
+
+    /// Send a synthesis request and decode the returned audio
+    async fn request_synthesis(&self, url: &str, payload: &serde_json::Value, text: &str) -> Result<TTSResult, String> {
         // Send request to VoxCPM server
-        let response = self.client
-            .post(format!("{}/tts", self.config.server_url))
-            .json(&payload)
-            .send()
+        self.rate_limiter.acquire().await?;
+        let response = send_with_retry("TTS", self.config.max_retries, &self.retry_budget, self.authorize(self.client.post(url)).json(payload))
             .await
-            .map_err(|e| format!("Failed to send TTS request: {}", e))?;
+            .map_err(|e| request_error("TTS", self.config.timeout_secs, "Failed to send TTS request", e))?;
 
         if !response.status().is_success() {
-            return Err(format!("TTS request failed with status: {}", response.status()));
+            return Err(status_error("TTS request", response).await);
         }
 
         // Check if response is JSON with base64 audio or raw audio bytes
@@ -74,12 +272,14 @@ impl VoxCPMTTS {
             .and_then(|v| v.to_str().ok())
             .unwrap_or("");
 
+        let mut alignment = None;
         let audio_data = if content_type.contains("application/json") {
             // JSON response with base64 encoded audio
-            let result: serde_json::Value = response
-                .json()
-                .await
-                .map_err(|e| format!("Failed to parse TTS response: {}", e))?;
+            let result = read_json("TTS", response).await?;
+
+            if self.config.return_alignment {
+                alignment = parse_alignment(&result["alignment"]);
+            }
 
             let audio_base64 = result["audio"]
                 .as_str()
@@ -93,33 +293,180 @@ impl VoxCPMTTS {
             response
                 .bytes()
                 .await
-                .map_err(|e| format!("Failed to read audio bytes: {}", e))?
+                .map_err(|e| request_error("TTS", self.config.timeout_secs, "Failed to read audio bytes", e))?
                 .to_vec()
         };
 
-        // Calculate approximate duration assuming 16-bit mono PCM audio
+        let audio_data = self.post_process(audio_data);
+
+        // Calculate approximate duration assuming 16-bit PCM audio
         // Duration = total_bytes / (sample_rate * bytes_per_sample * channels)
-        // For 16-bit mono: bytes_per_sample = 2, channels = 1
         let bytes_per_sample: f64 = 2.0;
-        let duration = audio_data.len() as f64 / (self.config.sample_rate as f64 * bytes_per_sample);
+        let channels = self.config.channels.max(1) as f64;
+        let duration = audio_data.len() as f64 / (self.config.sample_rate as f64 * bytes_per_sample * channels);
+
+        // Fall back to word timings derived from the text when the server has none
+        if self.config.return_alignment && alignment.is_none() {
+            alignment = estimate_word_alignment(text, duration);
+        }
 
         Ok(TTSResult {
             audio_data,
             sample_rate: self.config.sample_rate,
             duration,
+            alignment,
         })
     }
 
+    /// Apply loudness normalization and convert to the configured channel count
+    fn post_process(&self, audio_data: Vec<u8>) -> Vec<u8> {
+        let wav = match crate::audio::parse_wav(&audio_data) {
+            Ok(wav) => wav,
+            Err(e) => {
+                log::warn!("Skipping TTS post-processing: {}", e);
+                return audio_data;
+            }
+        };
+
+        let channels = self.config.channels.max(1);
+        if !self.config.normalize_output && wav.info.channels == channels {
+            return audio_data;
+        }
+
+        let mut samples = wav.samples;
+        if self.config.normalize_output {
+            samples = crate::audio::normalize_rms(&samples, self.config.target_rms);
+        }
+        if wav.info.channels != channels {
+            samples = crate::audio::convert_channels(&samples, wav.info.channels, channels);
+        }
+        crate::audio::encode_wav(&samples, wav.info.sample_rate, channels)
+    }
+
     /// Get current configuration
     pub fn config(&self) -> &VoxCPMConfig {
         &self.config
     }
 
+    /// Restore the default configuration
+    pub fn reset_config(&mut self) {
+        *self = Self::new(VoxCPMConfig::default());
+    }
+
+    /// Share the pipeline's retry budget with this client's requests
+    pub fn set_retry_budget(&mut self, budget: Arc<RetryBudget>) {
+        self.retry_budget = budget;
+    }
+
+    /// Limit requests to the TTS server per minute (0 = unlimited)
+    pub fn set_rate_limit(&mut self, requests_per_minute: u32, fail_fast: bool) {
+        self.config.requests_per_minute = requests_per_minute;
+        self.config.rate_limit_fail_fast = fail_fast;
+        self.rate_limiter.configure(requests_per_minute, fail_fast);
+    }
+
+    /// Set how many times a request is retried on 5xx and connection errors
+    pub fn set_max_retries(&mut self, max_retries: u32) {
+        self.config.max_retries = max_retries;
+    }
+
+    /// Check that the server is reachable via `/health`
+    ///
+    /// The request is built up front, so the check can run without the client.
+    pub fn ping(&self) -> impl std::future::Future<Output = Result<bool, String>> {
+        let request = (!self.config.mock)
+            .then(|| self.authorize(self.client.get(format!("{}/health", self.config.server_url))));
+        ping_url(request, "TTS")
+    }
+
+    /// Voices offered by the server's `/voices` endpoint
+    ///
+    /// Servers without the endpoint yield an empty list rather than an error.
+    pub async fn list_voices(&self) -> Result<Vec<VoiceInfo>, String> {
+        let url = format!("{}/voices", self.config.server_url);
+        let response = send_with_retry("TTS", self.config.max_retries, &self.retry_budget, self.authorize(self.client.get(&url)))
+            .await
+            .map_err(|e| request_error("TTS", self.config.timeout_secs, "Failed to list TTS voices", e))?;
+
+        let status = response.status();
+        if matches!(status.as_u16(), 404 | 405 | 501) {
+            log::warn!("TTS server does not support voice listing ({})", status);
+            return Ok(Vec::new());
+        }
+        if !status.is_success() {
+            return Err(status_error("TTS voice listing", response).await);
+        }
+
+        let result = read_json("TTS voice listing", response).await?;
+        let voices = result.as_array().or_else(|| result["voices"].as_array());
+        Ok(voices
+            .map(|voices| voices.iter().filter_map(VoiceInfo::from_json).collect())
+            .unwrap_or_default())
+    }
+
     /// Update server URL
     pub fn set_server_url(&mut self, url: String) {
         self.config.server_url = url;
     }
 
+    /// Update the request timeout in seconds (0 disables it)
+    pub fn set_timeout(&mut self, timeout_secs: u64) {
+        self.config.timeout_secs = timeout_secs;
+        self.client = build_client(self.config.user_agent.as_deref(), timeout_secs);
+    }
+
+    /// Update the User-Agent header (None restores the default)
+    pub fn set_user_agent(&mut self, user_agent: Option<String>) {
+        self.client = build_client(user_agent.as_deref(), self.config.timeout_secs);
+        self.config.user_agent = user_agent;
+    }
+
+    /// Enable or disable mock mode
+    pub fn set_mock(&mut self, enabled: bool) {
+        self.config.mock = enabled;
+    }
+
+    /// Set the API key sent as a bearer token (None or empty disables it)
+    pub fn set_api_key(&mut self, api_key: Option<String>) {
+        self.config.api_key = api_key;
+    }
+
+    /// Replace the additional headers sent with every request
+    pub fn set_extra_headers(&mut self, headers: HashMap<String, String>) {
+        self.config.extra_headers = headers;
+    }
+
+    /// Attach the configured credentials to a request to the TTS server
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        with_auth(request, self.config.api_key.as_deref(), &self.config.extra_headers)
+    }
+
+    /// Configure output loudness normalization
+    pub fn set_normalization(&mut self, enabled: bool, target_rms: f32) {
+        self.config.normalize_output = enabled;
+        self.config.target_rms = target_rms;
+    }
+
+    /// Set the output channel count (1 = mono, 2 = stereo)
+    pub fn set_channels(&mut self, channels: u16) {
+        self.config.channels = channels;
+    }
+
+    /// Emit response audio as `tts-audio-chunk` events instead of `tts-audio`
+    pub fn set_stream_sentences(&mut self, enabled: bool) {
+        self.config.stream_sentences = enabled;
+    }
+
+    /// Set the longest piece of text sent per synthesis request (0 = no limit)
+    pub fn set_max_chunk_chars(&mut self, max_chars: usize) {
+        self.config.max_chunk_chars = max_chars;
+    }
+
+    /// Enable or disable phoneme/word timing data
+    pub fn set_return_alignment(&mut self, enabled: bool) {
+        self.config.return_alignment = enabled;
+    }
+
     /// Update voice
     pub fn set_voice(&mut self, voice: String) {
         self.config.voice = voice;
@@ -130,3 +477,38 @@ impl VoxCPMTTS {
         self.config.speed = speed;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voice(value: serde_json::Value) -> Option<(String, String, Option<String>, Option<String>)> {
+        VoiceInfo::from_json(&value).map(|v| (v.id, v.name, v.language, v.gender))
+    }
+
+    #[test]
+    fn parses_bare_voice_ids() {
+        assert_eq!(voice(serde_json::json!("alloy")), Some(("alloy".to_string(), "alloy".to_string(), None, None)));
+    }
+
+    #[test]
+    fn parses_voice_objects() {
+        let value = serde_json::json!({"id": "zh_female_1", "name": "Xiaoxiao", "language": "zh", "gender": "female"});
+        assert_eq!(
+            voice(value),
+            Some(("zh_female_1".to_string(), "Xiaoxiao".to_string(), Some("zh".to_string()), Some("female".to_string())))
+        );
+    }
+
+    #[test]
+    fn voice_name_and_id_stand_in_for_each_other() {
+        assert_eq!(voice(serde_json::json!({"name": "narrator"})), Some(("narrator".to_string(), "narrator".to_string(), None, None)));
+        assert_eq!(voice(serde_json::json!({"id": "v2"})), Some(("v2".to_string(), "v2".to_string(), None, None)));
+    }
+
+    #[test]
+    fn rejects_voices_without_id_or_name() {
+        assert_eq!(voice(serde_json::json!({"language": "en"})), None);
+        assert_eq!(voice(serde_json::json!(42)), None);
+    }
+}
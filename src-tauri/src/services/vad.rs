@@ -0,0 +1,103 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio::{frame_levels, vad_frame_len};
+
+/// Speech RMS threshold per aggressiveness level (0 keeps the most audio)
+const THRESHOLDS: [f32; 4] = [0.005, 0.01, 0.02, 0.04];
+
+/// Consecutive voiced frames needed before audio counts as speech, so clicks
+/// and pops do not stop trimming, per aggressiveness level
+const MIN_SPEECH_FRAMES: [usize; 4] = [1, 2, 3, 4];
+
+/// Voice activity detection applied before transcription
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct VadConfig {
+    /// Trim leading and trailing silence before sending audio to the server
+    pub enabled: bool,
+    /// 0 (least aggressive) to 3 (most aggressive), like WebRTC VAD
+    pub aggressiveness: u8,
+    /// Audio kept around the detected speech, in milliseconds
+    pub padding_ms: u32,
+    /// Return an empty transcription without a request when no speech is found
+    pub skip_silent: bool,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            aggressiveness: 1,
+            padding_ms: 200,
+            skip_silent: true,
+        }
+    }
+}
+
+/// Sample range of mono `samples` that contains speech, including padding
+///
+/// Returns None when no frame reaches the speech threshold.
+pub fn speech_range(samples: &[i16], sample_rate: u32, config: &VadConfig) -> Option<Range<usize>> {
+    let level = config.aggressiveness.min(3) as usize;
+    let frame_len = vad_frame_len(sample_rate, 1);
+    let voiced: Vec<bool> = frame_levels(samples, sample_rate, 1)
+        .into_iter()
+        .map(|rms| rms >= THRESHOLDS[level])
+        .collect();
+
+    // Clips shorter than the minimum run only need one voiced frame
+    let run = MIN_SPEECH_FRAMES[level].min(voiced.len()).max(1);
+    let is_speech = |window: &[bool]| window.iter().all(|&v| v);
+    let first = voiced.windows(run).position(is_speech)?;
+    let last = voiced.windows(run).rposition(is_speech)? + run - 1;
+
+    let padding = (sample_rate as u64 * config.padding_ms as u64 / 1000) as usize;
+    let start = (first * frame_len).saturating_sub(padding);
+    let end = ((last + 1) * frame_len + padding).min(samples.len());
+    Some(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16000;
+    /// Samples in 300 ms, ten detector frames
+    const SPAN: usize = 4800;
+
+    fn speech(len: usize) -> Vec<i16> {
+        (0..len).map(|i| if i % 2 == 0 { 8000 } else { -8000 }).collect()
+    }
+
+    fn config(padding_ms: u32) -> VadConfig {
+        VadConfig { enabled: true, padding_ms, ..VadConfig::default() }
+    }
+
+    #[test]
+    fn silence_has_no_speech() {
+        assert_eq!(speech_range(&vec![0; SPAN], RATE, &config(200)), None);
+        assert_eq!(speech_range(&[], RATE, &config(200)), None);
+    }
+
+    #[test]
+    fn speech_keeps_everything() {
+        assert_eq!(speech_range(&speech(SPAN), RATE, &config(200)), Some(0..SPAN));
+    }
+
+    #[test]
+    fn trims_silence_around_speech() {
+        let mut samples = vec![0; SPAN];
+        samples.extend(speech(SPAN));
+        samples.extend(vec![0; SPAN]);
+        assert_eq!(speech_range(&samples, RATE, &config(0)), Some(SPAN..2 * SPAN));
+        assert_eq!(speech_range(&samples, RATE, &config(100)), Some(SPAN - 1600..2 * SPAN + 1600));
+    }
+
+    #[test]
+    fn ignores_clicks_shorter_than_the_minimum_run() {
+        let mut samples = vec![0; SPAN];
+        samples[SPAN / 2..SPAN / 2 + vad_frame_len(RATE, 1)].copy_from_slice(&speech(vad_frame_len(RATE, 1)));
+        assert_eq!(speech_range(&samples, RATE, &config(0)), None);
+    }
+}
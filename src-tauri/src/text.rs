@@ -0,0 +1,487 @@
+//! Text processing helpers for speech synthesis and transcripts
+//!
+//! Splits LLM output into sentence-sized pieces so long responses can be
+//! synthesized incrementally. Handles CJK full-width punctuation (which is not
+//! followed by whitespace) and common English abbreviations. Also restores
+//! punctuation to raw ASR output and estimates token counts.
+
+/// Default maximum sentence length (in characters) before clause splitting
+pub const DEFAULT_MAX_SENTENCE_CHARS: usize = 200;
+
+/// Abbreviations whose trailing period does not end a sentence
+const ABBREVIATIONS: &[&str] = &[
+    "mr.", "mrs.", "ms.", "dr.", "prof.", "sr.", "jr.", "st.", "mt.", "vs.",
+    "etc.", "e.g.", "i.e.", "cf.", "approx.", "no.", "inc.", "ltd.", "co.",
+    "corp.", "u.s.", "u.k.", "a.m.", "p.m.", "jan.", "feb.", "mar.", "apr.",
+    "jun.", "jul.", "aug.", "sep.", "sept.", "oct.", "nov.", "dec.",
+];
+
+/// Characters that may trail a terminator and belong to the same sentence
+const CLOSERS: &[char] = &['"', '\'', ')', ']', '}', '”', '’', '」', '』', '）', '】', '》'];
+
+/// Clause boundaries used to break up overly long sentences
+const CLAUSE_BREAKS: &[char] = &[',', ';', ':', '，', '、', '；', '：', '—'];
+
+/// Whether a language code (or the text itself, for "auto") is CJK
+fn is_cjk_language(language: &str, text: &str) -> bool {
+    let lang = language.to_lowercase();
+    if lang.starts_with("zh") || lang.starts_with("ja") || lang.starts_with("ko") {
+        return true;
+    }
+    if lang.is_empty() || lang == "auto" {
+        return text.chars().any(is_cjk_char);
+    }
+    false
+}
+
+/// Whether a character is in a CJK script block
+fn is_cjk_char(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF   // Hiragana, Katakana
+        | 0x3400..=0x4DBF // CJK Extension A
+        | 0x4E00..=0x9FFF // CJK Unified Ideographs
+        | 0xAC00..=0xD7AF // Hangul syllables
+        | 0xF900..=0xFAFF // CJK Compatibility Ideographs
+    )
+}
+
+/// Whether the period at the end of `current` belongs to an abbreviation,
+/// initial or decimal number rather than ending the sentence
+fn is_non_terminal_period(current: &str, next: Option<char>) -> bool {
+    // Decimal numbers and version strings: "3.5", "v1.2"
+    if next.is_some_and(|c| c.is_ascii_digit()) {
+        return true;
+    }
+
+    let word = current
+        .rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or("")
+        .trim_start_matches(|c: char| !c.is_alphanumeric())
+        .to_lowercase();
+
+    if ABBREVIATIONS.contains(&word.as_str()) {
+        return true;
+    }
+
+    // Single-letter initials: "J. R. R. Tolkien"
+    let mut chars = word.chars();
+    matches!((chars.next(), chars.next(), chars.next()), (Some(c), Some('.'), None) if c.is_alphabetic())
+}
+
+/// Split text into sentences using language-aware rules.
+///
+/// `language` is an ISO code such as "en", "zh" or "ja" (or "auto" to detect
+/// from the text). Sentences longer than `max_chars` are further split on
+/// clause boundaries, then on whitespace as a last resort.
+pub fn split_sentences(text: &str, language: &str, max_chars: usize) -> Vec<String> {
+    let cjk = is_cjk_language(language, text);
+    let chars: Vec<char> = text.chars().collect();
+    let mut sentences = Vec::new();
+    let mut current = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        current.push(c);
+        i += 1;
+
+        let boundary = match c {
+            '。' | '！' | '？' | '…' => true,
+            '\n' => true,
+            '!' | '?' => cjk || chars.get(i).map_or(true, |n| n.is_whitespace() || CLOSERS.contains(n)),
+            '.' => {
+                let next = chars.get(i).copied();
+                let followed_by_break = next.map_or(true, |n| n.is_whitespace() || CLOSERS.contains(&n))
+                    || (cjk && next.is_some_and(is_cjk_char));
+                followed_by_break && !is_non_terminal_period(&current, next)
+            }
+            _ => false,
+        };
+
+        if boundary {
+            // Keep trailing quotes/brackets and repeated terminators together
+            while let Some(&n) = chars.get(i) {
+                if CLOSERS.contains(&n) || matches!(n, '。' | '！' | '？' | '!' | '?' | '…') {
+                    current.push(n);
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            push_sentence(&mut sentences, &current, max_chars);
+            current.clear();
+        }
+    }
+
+    push_sentence(&mut sentences, &current, max_chars);
+    sentences
+}
+
+/// Push a trimmed sentence, splitting it further if it exceeds `max_chars`
+fn push_sentence(sentences: &mut Vec<String>, sentence: &str, max_chars: usize) {
+    let sentence = sentence.trim();
+    if sentence.is_empty() {
+        return;
+    }
+    if max_chars == 0 || sentence.chars().count() <= max_chars {
+        sentences.push(sentence.to_string());
+        return;
+    }
+
+    let mut chunk = String::new();
+    for clause in split_inclusive_chars(sentence, CLAUSE_BREAKS) {
+        if !chunk.is_empty() && chunk.chars().count() + clause.chars().count() > max_chars {
+            push_hard_split(sentences, &chunk, max_chars);
+            chunk.clear();
+        }
+        chunk.push_str(clause);
+    }
+    push_hard_split(sentences, &chunk, max_chars);
+}
+
+/// Split on whitespace (or at `max_chars` for unspaced text) as a last resort
+fn push_hard_split(sentences: &mut Vec<String>, text: &str, max_chars: usize) {
+    let text = text.trim();
+    if text.is_empty() {
+        return;
+    }
+    if text.chars().count() <= max_chars {
+        sentences.push(text.to_string());
+        return;
+    }
+
+    let mut chunk = String::new();
+    for word in text.split_inclusive(char::is_whitespace) {
+        if !chunk.is_empty() && chunk.chars().count() + word.chars().count() > max_chars {
+            sentences.push(chunk.trim().to_string());
+            chunk.clear();
+        }
+        if word.chars().count() > max_chars {
+            // No spaces to split on (e.g. CJK): cut at the character limit
+            let word_chars: Vec<char> = word.chars().collect();
+            for piece in word_chars.chunks(max_chars) {
+                let piece: String = piece.iter().collect();
+                if !piece.trim().is_empty() {
+                    sentences.push(piece.trim().to_string());
+                }
+            }
+        } else {
+            chunk.push_str(word);
+        }
+    }
+    if !chunk.trim().is_empty() {
+        sentences.push(chunk.trim().to_string());
+    }
+}
+
+/// Like `str::split_inclusive`, but for any of several delimiter characters
+fn split_inclusive_chars<'a>(text: &'a str, delimiters: &[char]) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for (idx, c) in text.char_indices() {
+        if delimiters.contains(&c) {
+            let end = idx + c.len_utf8();
+            parts.push(&text[start..end]);
+            start = end;
+        }
+    }
+    if start < text.len() {
+        parts.push(&text[start..]);
+    }
+    parts
+}
+
+/// Remove fenced code blocks and inline code markers, which should not be read aloud
+pub fn strip_code_blocks(text: &str) -> String {
+    let mut spoken = String::with_capacity(text.len());
+    let mut fence: Option<&str> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        match fence {
+            Some(marker) => {
+                if trimmed.starts_with(marker) {
+                    fence = None;
+                }
+            }
+            None => {
+                if let Some(marker) = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m)) {
+                    fence = Some(marker);
+                } else {
+                    spoken.push_str(&line.replace('`', ""));
+                    spoken.push('\n');
+                }
+            }
+        }
+    }
+    spoken
+}
+
+/// Split a response into pieces for speech synthesis
+///
+/// Code blocks are dropped. The first piece is a single sentence so audio can
+/// start as soon as possible; the remaining sentences are packed into pieces
+/// of up to `max_chars` (0 = no limit).
+pub fn speech_chunks(text: &str, language: &str, max_chars: usize) -> Vec<String> {
+    let spoken = strip_code_blocks(text);
+    let mut chunks: Vec<String> = Vec::new();
+    for sentence in split_sentences(&spoken, language, max_chars) {
+        let count = chunks.len();
+        match chunks.last_mut() {
+            Some(last) if count > 1
+                && (max_chars == 0 || last.chars().count() + 1 + sentence.chars().count() <= max_chars) =>
+            {
+                last.push(' ');
+                last.push_str(&sentence);
+            }
+            _ => chunks.push(sentence),
+        }
+    }
+    chunks
+}
+
+/// Releases complete sentences of text that arrives in pieces, such as a
+/// streamed LLM reply, so they can be synthesized before the reply is done
+///
+/// Code blocks are dropped like in `speech_chunks`. The last sentence is held
+/// back until more text shows that it has ended.
+#[derive(Debug, Clone)]
+pub struct SentenceStream {
+    text: String,
+    language: String,
+    max_chars: usize,
+    /// Bytes of spoken text already released
+    released: usize,
+}
+
+impl SentenceStream {
+    pub fn new(language: &str, max_chars: usize) -> Self {
+        Self { text: String::new(), language: language.to_string(), max_chars, released: 0 }
+    }
+
+    /// Spoken text not yet released
+    fn pending(&self) -> String {
+        let mut spoken = strip_code_blocks(&self.text);
+        // An unfinished line is not a line break yet
+        if !self.text.ends_with('\n') && spoken.ends_with('\n') {
+            spoken.pop();
+        }
+        spoken.get(self.released..).unwrap_or("").to_string()
+    }
+
+    /// Add a piece of text and return the sentences it completed
+    pub fn push(&mut self, piece: &str) -> Vec<String> {
+        self.text.push_str(piece);
+        let pending = self.pending();
+        let mut sentences = split_sentences(&pending, &self.language, self.max_chars);
+        let Some(last) = sentences.pop() else {
+            return Vec::new();
+        };
+        if !sentences.is_empty() {
+            self.released += pending.rfind(last.as_str()).unwrap_or(0);
+        }
+        sentences
+    }
+
+    /// Sentences left once all text has arrived
+    pub fn finish(self) -> Vec<String> {
+        split_sentences(&self.pending(), &self.language, self.max_chars)
+    }
+}
+
+/// Words that usually open an English question
+const QUESTION_WORDS: &[&str] = &[
+    "what", "why", "how", "when", "where", "who", "whom", "whose", "which",
+    "is", "are", "am", "was", "were", "do", "does", "did", "can", "could",
+    "would", "will", "should", "shall", "may", "might", "have", "has",
+];
+
+/// Whether text already contains sentence-ending punctuation
+pub fn has_sentence_punctuation(text: &str) -> bool {
+    text.chars().any(|c| matches!(c, '.' | '!' | '?' | '。' | '！' | '？'))
+}
+
+/// Heuristically restore capitalization and terminal punctuation to raw ASR output.
+///
+/// Only applied when the text has no sentence punctuation at all, so
+/// well-formed transcripts pass through unchanged.
+pub fn restore_punctuation(text: &str, language: &str) -> String {
+    let trimmed = text.trim();
+    if trimmed.is_empty() || has_sentence_punctuation(trimmed) {
+        return trimmed.to_string();
+    }
+
+    if is_cjk_language(language, trimmed) {
+        let question = trimmed.ends_with('吗') || trimmed.ends_with('呢') || trimmed.ends_with('か');
+        return format!("{}{}", trimmed, if question { '？' } else { '。' });
+    }
+
+    let words: Vec<String> = trimmed
+        .split_whitespace()
+        .enumerate()
+        .map(|(i, word)| {
+            let lower = word.to_lowercase();
+            // Standalone "i" and its contractions
+            if lower == "i" || lower.starts_with("i'") {
+                return format!("I{}", &word[1..]);
+            }
+            if i == 0 {
+                let mut chars = word.chars();
+                return match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect(),
+                    None => String::new(),
+                };
+            }
+            word.to_string()
+        })
+        .collect();
+
+    let first = words.first().map(|w| w.to_lowercase()).unwrap_or_default();
+    let terminator = if QUESTION_WORDS.contains(&first.as_str()) { '?' } else { '.' };
+    let mut result = words.join(" ");
+    // Drop a trailing comma or similar before terminating
+    while result.ends_with([',', ';', ':']) {
+        result.pop();
+    }
+    result.push(terminator);
+    result
+}
+
+/// Rough token count for context accounting
+///
+/// Each CJK character counts as one token; other text counts as about four
+/// characters per token, with every word costing at least one.
+pub fn estimate_tokens(text: &str) -> usize {
+    let mut tokens = 0;
+    for word in text.split_whitespace() {
+        let cjk = word.chars().filter(|&c| is_cjk_char(c)).count();
+        let other = word.chars().count() - cjk;
+        tokens += cjk + other.div_ceil(4);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_english_sentences() {
+        let sentences = split_sentences("Hello there. How are you? I'm fine!", "en", DEFAULT_MAX_SENTENCE_CHARS);
+        assert_eq!(sentences, ["Hello there.", "How are you?", "I'm fine!"]);
+    }
+
+    #[test]
+    fn keeps_abbreviations_and_decimals_together() {
+        let sentences = split_sentences("Dr. Smith paid $3.50 for it. Then he left.", "en", DEFAULT_MAX_SENTENCE_CHARS);
+        assert_eq!(sentences, ["Dr. Smith paid $3.50 for it.", "Then he left."]);
+    }
+
+    #[test]
+    fn splits_chinese_sentences() {
+        let sentences = split_sentences("今天天气很好。我们去公园吧！你觉得呢？", "zh", DEFAULT_MAX_SENTENCE_CHARS);
+        assert_eq!(sentences, ["今天天气很好。", "我们去公园吧！", "你觉得呢？"]);
+    }
+
+    #[test]
+    fn splits_japanese_sentences() {
+        let sentences = split_sentences("こんにちは。「元気ですか？」はい、元気です。", "ja", DEFAULT_MAX_SENTENCE_CHARS);
+        assert_eq!(sentences, ["こんにちは。", "「元気ですか？」", "はい、元気です。"]);
+    }
+
+    #[test]
+    fn detects_cjk_text_in_auto_mode() {
+        let sentences = split_sentences("你好。再见。", "auto", DEFAULT_MAX_SENTENCE_CHARS);
+        assert_eq!(sentences, ["你好。", "再见。"]);
+    }
+
+    #[test]
+    fn splits_long_sentences_on_clauses() {
+        let sentences = split_sentences("one two three, four five six, seven eight nine.", "en", 17);
+        assert_eq!(sentences, ["one two three,", "four five six,", "seven eight nine."]);
+    }
+
+    #[test]
+    fn splits_long_clauses_on_words() {
+        let sentences = split_sentences("alpha beta gamma delta epsilon.", "en", 12);
+        assert_eq!(sentences, ["alpha beta", "gamma delta", "epsilon."]);
+    }
+
+    #[test]
+    fn cuts_unspaced_text_at_the_limit() {
+        let sentences = split_sentences("一二三四五六七八九十。", "zh", 4);
+        assert_eq!(sentences, ["一二三四", "五六七八", "九十。"]);
+    }
+
+    #[test]
+    fn strips_fenced_and_inline_code() {
+        let text = "Run `cargo build` first.\n```sh\ncargo build\n```\nThen:\n~~~\n```\nstill code\n~~~\nDone.";
+        assert_eq!(strip_code_blocks(text), "Run cargo build first.\nThen:\nDone.\n");
+    }
+
+    #[test]
+    fn strips_unclosed_fence_to_the_end() {
+        assert_eq!(strip_code_blocks("Here it is:\n```\nlet x = 1;"), "Here it is:\n");
+    }
+
+    /// Push `pieces` one at a time, returning what each released and what `finish` released
+    fn stream(pieces: &[&str], max_chars: usize) -> (Vec<Vec<String>>, Vec<String>) {
+        let mut stream = SentenceStream::new("en", max_chars);
+        let released = pieces.iter().map(|piece| stream.push(piece)).collect();
+        (released, stream.finish())
+    }
+
+    #[test]
+    fn sentence_stream_releases_sentences_split_across_chunks() {
+        let (released, rest) = stream(&["Hello the", "re. How are", " you? I'm", " fine"], DEFAULT_MAX_SENTENCE_CHARS);
+        assert_eq!(released, [vec![], vec!["Hello there."], vec!["How are you?"], vec![]]);
+        assert_eq!(rest, ["I'm fine"]);
+    }
+
+    #[test]
+    fn sentence_stream_skips_code_fence_opened_mid_stream() {
+        let (released, rest) = stream(
+            &["Try this.\n``", "`rust\nfn main() {}\n", "```\nIt pri", "nts nothing. Done."],
+            DEFAULT_MAX_SENTENCE_CHARS,
+        );
+        let released: Vec<String> = released.into_iter().flatten().collect();
+        assert_eq!(released, ["Try this.", "It prints nothing."]);
+        assert_eq!(rest, ["Done."]);
+    }
+
+    #[test]
+    fn sentence_stream_hard_splits_long_sentences() {
+        let (released, rest) = stream(&["alpha beta gamma ", "delta epsilon zeta."], 12);
+        let all: Vec<String> = released.into_iter().flatten().chain(rest).collect();
+        assert!(all.iter().all(|piece| piece.chars().count() <= 12), "{:?}", all);
+        assert_eq!(all.join(" "), "alpha beta gamma delta epsilon zeta.");
+    }
+
+    #[test]
+    fn sentence_stream_finish_releases_the_rest() {
+        assert_eq!(stream(&["No ending punctuation"], 0).1, ["No ending punctuation"]);
+        assert!(stream(&[], DEFAULT_MAX_SENTENCE_CHARS).1.is_empty());
+        assert_eq!(stream(&["Done. "], DEFAULT_MAX_SENTENCE_CHARS).1, ["Done."]);
+    }
+
+    #[test]
+    fn restores_english_punctuation() {
+        assert_eq!(restore_punctuation("i think it's going to rain", "en"), "I think it's going to rain.");
+        assert_eq!(restore_punctuation("what time is it,", "en"), "What time is it?");
+        assert_eq!(restore_punctuation("where i'm going", "auto"), "Where I'm going?");
+    }
+
+    #[test]
+    fn restores_cjk_punctuation() {
+        assert_eq!(restore_punctuation("今天天气很好", "zh"), "今天天气很好。");
+        assert_eq!(restore_punctuation("你吃饭了吗", "zh"), "你吃饭了吗？");
+        assert_eq!(restore_punctuation("元気です", "auto"), "元気です。");
+        assert_eq!(restore_punctuation("元気ですか", "ja"), "元気ですか？");
+    }
+
+    #[test]
+    fn leaves_punctuated_text_alone() {
+        assert_eq!(restore_punctuation("  already done. ok  ", "en"), "already done. ok");
+        assert_eq!(restore_punctuation("", "en"), "");
+    }
+}